@@ -1,18 +1,116 @@
 use bollard::errors::Error;
 use chrono::prelude::*;
+use clap::{Args, Parser, Subcommand};
+use std::sync::Arc;
+use tokio::sync::RwLock;
 use tokio::time::{sleep, Duration};
 use uuid::Uuid;
 
+#[path = "../events.rs"]
+mod events;
+#[path = "../exec.rs"]
+mod exec;
+#[cfg(target_os = "linux")]
+#[path = "../isolation.rs"]
+mod isolation;
+#[path = "../manager.rs"]
+mod manager;
+#[path = "../manager_api.rs"]
+mod manager_api;
 #[path = "../node.rs"]
 mod node;
+#[path = "../process.rs"]
+mod process;
+#[path = "../quota.rs"]
+mod quota;
+#[path = "../registry.rs"]
+mod registry;
+#[path = "../scheduler.rs"]
+mod scheduler;
+#[path = "../security.rs"]
+mod security;
 #[path = "../task.rs"]
 mod task;
 #[path = "../worker.rs"]
 mod worker;
+#[path = "../worker_api.rs"]
+mod worker_api;
+
+/// Toy container orchestrator
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+/// How the manager places tasks on the workers
+#[derive(Args, Debug)]
+struct SchedulingArgs {
+    /// One of round-robin, first-fit, best-fit or worst-fit
+    #[clap(long, default_value = "round-robin")]
+    scheduler: String,
+    /// Stop lower priority tasks to make room for higher priority ones
+    #[clap(long)]
+    preemption: bool,
+    /// Memory of each worker node, 0 for unknown
+    #[clap(long, default_value = "0")]
+    node_memory_mb: u64,
+    /// Disk of each worker node, 0 for unknown
+    #[clap(long, default_value = "0")]
+    node_disk_gb: u64,
+    /// CPUs of each worker node, 0 for unknown
+    #[clap(long, default_value = "0")]
+    node_cores: u32,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the manager, its API and the service lookup
+    Manager {
+        /// Port of the manager HTTP API
+        #[clap(long, default_value = "5555")]
+        port: u16,
+        /// UDP port of the service lookup
+        #[clap(long, default_value = "5353")]
+        lookup_port: u16,
+        /// Worker API addresses (host:port), comma separated
+        #[clap(long, value_delimiter = ',', default_value = "127.0.0.1:5556")]
+        workers: Vec<String>,
+        #[clap(flatten)]
+        scheduling: SchedulingArgs,
+        #[clap(flatten)]
+        security: security::SecurityConfig,
+    },
+    /// Run a worker and its API
+    Worker {
+        /// Port of the worker HTTP API
+        #[clap(long, default_value = "5556")]
+        port: u16,
+        /// Worker name, random when not given
+        #[clap(long)]
+        name: Option<String>,
+        #[clap(flatten)]
+        security: security::SecurityConfig,
+    },
+    /// Run a command inside a running task
+    Exec {
+        /// Task to run the command in
+        task_id: Uuid,
+        /// Worker API address (host:port) of the worker running the task
+        #[clap(long, default_value = "127.0.0.1:5556")]
+        worker: String,
+        #[clap(flatten)]
+        security: security::SecurityConfig,
+        /// Command to run, after `--`
+        #[clap(last = true, required = true)]
+        cmd: Vec<String>,
+    },
+}
 
 #[allow(dead_code)]
 async fn create_container(
-) -> Result<task::DockerClient<String>, Box<dyn std::error::Error + 'static>> {
+) -> Result<task::DockerClient<String>, Box<dyn std::error::Error + Send + Sync + 'static>> {
     let c = task::Config::new("test-container-1", "alpine:3", None);
     let mut dc = task::DockerClient::new(c)?;
     let dr = dc.run().await?;
@@ -23,7 +121,7 @@ async fn create_container(
 #[allow(dead_code)]
 async fn stop_container(
     dc: &task::DockerClient<String>,
-) -> Result<task::DockerResult<String>, Box<dyn std::error::Error + 'static>> {
+) -> Result<task::DockerResult<String>, Box<dyn std::error::Error + Send + Sync + 'static>> {
     if let Some(container_id) = &dc.container_id {
         let dr = dc.stop(container_id).await?;
         Ok(dr)
@@ -40,9 +138,183 @@ async fn stop_container(
     }
 }
 
+/// Run a two step pipeline: the second batch task only starts once the
+/// first one, running as a host process, has completed
+async fn run_pipeline() {
+    // Everything stays on the loopback interface
+    let security = security::SecurityConfig {
+        insecure: true,
+        ..Default::default()
+    };
+    let w = Arc::new(RwLock::new(worker::Worker::new(Uuid::new_v4().to_string())));
+    tokio::spawn(worker::run_tasks(w.clone()));
+    let api = w.clone();
+    let api_security = security.clone();
+    tokio::spawn(async move {
+        if let Err(error) =
+            worker_api::serve(api, ([127, 0, 0, 1], 5556).into(), api_security).await
+        {
+            log::error!("Worker API failed: {:#?}", error);
+        }
+    });
+
+    let m = match manager::Manager::new(vec!["127.0.0.1:5556".to_string()], &security) {
+        Ok(m) => m,
+        Err(error) => {
+            log::error!("Failed to create the manager: {:#?}\n", error);
+            return;
+        }
+    };
+
+    let step_a = task::Task {
+        id: Uuid::new_v4(),
+        name: "pipeline-step-a".to_string(),
+        kind: task::Kind::Batch,
+        runtime: task::Runtime::Process,
+        cmd: Some(vec!["sleep".to_string(), "3".to_string()]),
+        ..Default::default()
+    };
+    let step_b = task::Task {
+        id: Uuid::new_v4(),
+        name: "pipeline-step-b".to_string(),
+        image: "alpine:3".to_string(),
+        kind: task::Kind::Batch,
+        cmd: Some(vec!["echo".to_string(), "done".to_string()]),
+        depends_on: vec![step_a.id],
+        ..Default::default()
+    };
+    let step_b_id = step_b.id;
+
+    let m = Arc::new(RwLock::new(m));
+    // Dependencies are submitted first, the manager refuses unknown ones
+    for te in [manager::task_event(step_a), manager::task_event(step_b)] {
+        if let Err(error) = m.write().await.add_task(te) {
+            log::error!("Failed to add a pipeline step: {}\n", error);
+            return;
+        }
+    }
+
+    loop {
+        manager::send_work(&m).await;
+        sleep(Duration::from_secs(1)).await;
+        manager::update_tasks(&m).await;
+
+        let state = m.read().await.task_db[&step_b_id].state.clone();
+        if state == task::State::Completed || state == task::State::Failed {
+            log::info!("pipeline finished: {:#?}\n", state);
+            break;
+        }
+    }
+}
+
+async fn run_manager(
+    port: u16,
+    lookup_port: u16,
+    workers: Vec<String>,
+    scheduling: SchedulingArgs,
+    security: security::SecurityConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let mut m = manager::Manager::new(workers.clone(), &security)?;
+    m.scheduler = scheduler::by_name(&scheduling.scheduler)
+        .ok_or_else(|| format!("Unknown scheduler {}", scheduling.scheduler))?;
+    // Preemption makes room on nodes, there is none to make when their
+    // size is not known
+    if scheduling.preemption && scheduling.node_memory_mb == 0 && scheduling.node_cores == 0 {
+        return Err(
+            "Preemption needs the size of the nodes, pass --node-memory-mb or --node-cores".into(),
+        );
+    }
+    m.preemption = scheduling.preemption;
+    log::info!(
+        "Scheduling with {}, preemption {}",
+        m.scheduler.name(),
+        if m.preemption { "on" } else { "off" }
+    );
+    for address in &workers {
+        let ip = address
+            .rsplit_once(':')
+            .map_or(address.as_str(), |(ip, _)| ip);
+        let mut n = node::Node::new(address, ip, "worker");
+        n.memory = scheduling.node_memory_mb * 1024 * 1024;
+        n.disk = scheduling.node_disk_gb;
+        n.cores = scheduling.node_cores;
+        m.register_node(address, n);
+    }
+    let m = Arc::new(RwLock::new(m));
+
+    let lookup = m.clone();
+    tokio::spawn(async move {
+        if let Err(error) =
+            manager_api::serve_lookup(lookup, ([0, 0, 0, 0], lookup_port).into()).await
+        {
+            log::error!("Service lookup failed: {:#?}", error);
+        }
+    });
+
+    // The manager goes down with its API, which refuses to start when it
+    // would not be secured
+    let api = m.clone();
+    tokio::select! {
+        result = manager_api::serve(api, ([0, 0, 0, 0], port).into(), security) => result,
+        _ = manager::run(m) => Ok(()),
+    }
+}
+
+async fn run_worker(
+    port: u16,
+    name: Option<String>,
+    security: security::SecurityConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let name = name.unwrap_or_else(|| Uuid::new_v4().to_string());
+    log::info!("Starting worker {}", name);
+    let w = Arc::new(RwLock::new(worker::Worker::new(name)));
+    tokio::spawn(worker::run_tasks(w.clone()));
+    worker_api::serve(w, ([0, 0, 0, 0], port).into(), security).await
+}
+
 #[tokio::main]
 async fn main() {
     env_logger::init();
+
+    match Cli::parse().command {
+        Some(Command::Manager {
+            port,
+            lookup_port,
+            workers,
+            scheduling,
+            security,
+        }) => {
+            if let Err(error) = run_manager(port, lookup_port, workers, scheduling, security).await
+            {
+                log::error!("Failed to run the manager: {:#?}", error);
+            }
+            return;
+        }
+        Some(Command::Worker {
+            port,
+            name,
+            security,
+        }) => {
+            if let Err(error) = run_worker(port, name, security).await {
+                log::error!("Failed to run the worker: {:#?}", error);
+            }
+            return;
+        }
+        Some(Command::Exec {
+            task_id,
+            worker,
+            security,
+            cmd,
+        }) => match exec::run(&worker, task_id, cmd, &security).await {
+            Ok(code) => std::process::exit(code as i32),
+            Err(error) => {
+                eprintln!("Failed to run the command: {}", error);
+                std::process::exit(1);
+            }
+        },
+        None => {}
+    }
+
     log::info!("Hello orchestrator!");
 
     let t = task::Task {
@@ -71,12 +343,14 @@ async fn main() {
         name: "Node-1".to_string(),
         ip: "192.168.1.1".to_string(),
         cores: 4,
+        cpu_allocated: 0,
         memory: 1024,
         disk: 25,
         task_count: 0,
         disk_allocated: 0,
         memory_allocated: 0,
         role: "worker".to_string(),
+        status: events::NodeStatus::Unknown,
     };
 
     log::info!("node: {:#?}\n", n);
@@ -121,4 +395,6 @@ async fn main() {
             };
         }
     };
+
+    run_pipeline().await;
 }
@@ -0,0 +1,409 @@
+use clap::Parser;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+#[path = "../events.rs"]
+#[allow(dead_code)]
+mod events;
+#[path = "../node.rs"]
+mod node;
+#[path = "../scheduler.rs"]
+mod scheduler;
+#[path = "../task.rs"]
+#[allow(dead_code)]
+mod task;
+
+const MB: u64 = 1024 * 1024;
+
+/// Replay a workload trace against the schedulers on a virtual cluster and
+/// report how well they use it as CSV
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Cli {
+    /// Workload trace, CSV rows of `submit,duration,memory_mb,disk_gb,cpu`
+    /// with times in seconds
+    trace: PathBuf,
+    /// Scheduler to evaluate, every one of them when not given
+    #[clap(long)]
+    scheduler: Option<String>,
+    /// Number of nodes in the cluster
+    #[clap(long, default_value = "4")]
+    nodes: usize,
+    #[clap(long, default_value = "8192")]
+    node_memory_mb: u64,
+    #[clap(long, default_value = "100")]
+    node_disk_gb: u64,
+    #[clap(long, default_value = "4")]
+    node_cores: u32,
+    /// Virtual seconds between two rows of the report
+    #[clap(long, default_value = "10")]
+    interval: u64,
+    /// Write the report to this file instead of stdout
+    #[clap(long)]
+    output: Option<PathBuf>,
+}
+
+/// A task of the trace and when it is submitted
+#[derive(Debug, Clone)]
+struct Submission {
+    submit: u64,
+    duration: u64,
+    task: task::Task<String>,
+}
+
+fn read_trace(
+    path: &PathBuf,
+) -> Result<Vec<Submission>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let mut trace = Vec::new();
+    for (n, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        // Blank lines, comments and the header
+        if line.is_empty() || line.starts_with('#') || line.starts_with("submit") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() != 5 {
+            return Err(format!("Line {}: expected 5 fields, got {}", n + 1, fields.len()).into());
+        }
+        let parse_error = |error: &dyn std::fmt::Display| format!("Line {}: {}", n + 1, error);
+        trace.push(Submission {
+            submit: fields[0].parse().map_err(|e| parse_error(&e))?,
+            duration: fields[1].parse().map_err(|e| parse_error(&e))?,
+            task: task::Task {
+                id: Uuid::new_v4(),
+                name: format!("task-{}", n + 1),
+                memory: Some(fields[2].parse::<u64>().map_err(|e| parse_error(&e))? * MB),
+                disk: Some(fields[3].parse().map_err(|e| parse_error(&e))?),
+                cpu: Some(fields[4].parse().map_err(|e| parse_error(&e))?),
+                ..Default::default()
+            },
+        });
+    }
+    trace.sort_by_key(|s| s.submit);
+    Ok(trace)
+}
+
+/// A task placed on a node, until it finishes
+#[derive(Debug)]
+struct Placement {
+    finish: u64,
+    node: usize,
+    task: task::Task<String>,
+}
+
+/// The virtual cluster a scheduler places the trace on
+struct Simulation {
+    scheduler: Box<dyn scheduler::Scheduler>,
+    nodes: Vec<node::Node>,
+    running: Vec<Placement>,
+    /// Submitted tasks waiting for room on a node
+    pending: VecDeque<Submission>,
+    completed: u64,
+    /// Tasks which could not be placed when they were submitted
+    placement_failures: u64,
+    /// Tasks larger than any node, they never run
+    rejected: u64,
+}
+
+impl Simulation {
+    fn new(scheduler: Box<dyn scheduler::Scheduler>, cli: &Cli) -> Self {
+        let nodes = (0..cli.nodes)
+            .map(|i| {
+                let mut n = node::Node::new(&format!("node-{}", i), "", "worker");
+                n.memory = cli.node_memory_mb * MB;
+                n.disk = cli.node_disk_gb;
+                n.cores = cli.node_cores;
+                n
+            })
+            .collect();
+        Self {
+            scheduler,
+            nodes,
+            running: Vec::new(),
+            pending: VecDeque::new(),
+            completed: 0,
+            placement_failures: 0,
+            rejected: 0,
+        }
+    }
+
+    /// Play the trace, writing a row of metrics every `interval` seconds
+    /// and a last one once every task has run
+    fn run(&mut self, trace: &[Submission], interval: u64, out: &mut dyn Write) -> io::Result<()> {
+        let mut trace = trace.iter().peekable();
+        let mut next_sample = 0;
+        loop {
+            let next_submit = trace.peek().map(|s| s.submit);
+            let next_finish = self.running.iter().map(|p| p.finish).min();
+            let Some(now) = next_submit.into_iter().chain(next_finish).min() else {
+                break;
+            };
+            // Nothing changes between two events
+            while next_sample < now {
+                self.write_row(next_sample, out)?;
+                next_sample += interval;
+            }
+
+            self.finish(now);
+            while let Some(s) = trace.next_if(|s| s.submit <= now) {
+                self.submit(s.clone());
+            }
+            self.place(now);
+        }
+        self.write_row(next_sample, out)
+    }
+
+    fn finish(&mut self, now: u64) {
+        let (finished, running) = self.running.drain(..).partition(|p| p.finish <= now);
+        self.running = running;
+        for p in finished {
+            let n = &mut self.nodes[p.node];
+            n.memory_allocated -= p.task.memory.unwrap_or_default();
+            n.disk_allocated -= p.task.disk.unwrap_or_default();
+            n.cpu_allocated -= node::millicores(p.task.cpu);
+            n.task_count -= 1;
+            self.completed += 1;
+        }
+    }
+
+    fn submit(&mut self, s: Submission) {
+        let fits_empty_node = self.nodes.iter().any(|n| {
+            let mut empty = n.clone();
+            empty.memory_allocated = 0;
+            empty.disk_allocated = 0;
+            empty.cpu_allocated = 0;
+            scheduler::fits(&s.task, &empty)
+        });
+        if fits_empty_node {
+            self.pending.push_back(s);
+        } else {
+            self.rejected += 1;
+        }
+    }
+
+    /// Place the pending tasks in submission order, those which fit nowhere
+    /// stay pending
+    fn place(&mut self, now: u64) {
+        for s in std::mem::take(&mut self.pending) {
+            let Some(idx) = self.scheduler.select(&s.task, &self.nodes) else {
+                if s.submit == now {
+                    self.placement_failures += 1;
+                }
+                self.pending.push_back(s);
+                continue;
+            };
+            let n = &mut self.nodes[idx];
+            n.memory_allocated += s.task.memory.unwrap_or_default();
+            n.disk_allocated += s.task.disk.unwrap_or_default();
+            n.cpu_allocated += node::millicores(s.task.cpu);
+            n.task_count += 1;
+            self.running.push(Placement {
+                finish: now + s.duration,
+                node: idx,
+                task: s.task,
+            });
+        }
+    }
+
+    /// Share of the free memory which is not on the node with the most of
+    /// it: 0 when all of it is on one node, close to 1 when it is scattered
+    fn fragmentation(&self) -> f64 {
+        let free = self.nodes.iter().map(|n| n.memory - n.memory_allocated);
+        let total: u64 = free.clone().sum();
+        if total == 0 {
+            return 0.0;
+        }
+        1.0 - free.max().unwrap_or_default() as f64 / total as f64
+    }
+
+    fn write_row(&self, time: u64, out: &mut dyn Write) -> io::Result<()> {
+        let utilisation = |allocated: f64, capacity: f64| {
+            if capacity == 0.0 {
+                0.0
+            } else {
+                allocated / capacity
+            }
+        };
+        let sum = |f: fn(&node::Node) -> u64| self.nodes.iter().map(f).sum::<u64>() as f64;
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{:.4},{:.4},{:.4},{:.4}",
+            time,
+            self.scheduler.name(),
+            self.running.len(),
+            self.pending.len(),
+            self.completed,
+            self.placement_failures,
+            self.rejected,
+            utilisation(sum(|n| n.memory_allocated), sum(|n| n.memory)),
+            utilisation(sum(|n| n.cpu_allocated), sum(node::Node::cpu)),
+            utilisation(sum(|n| n.disk_allocated), sum(|n| n.disk)),
+            self.fragmentation(),
+        )
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let names = match &cli.scheduler {
+        Some(name) => vec![name.as_str()],
+        None => scheduler::SCHEDULERS.to_vec(),
+    };
+    if let Some(name) = names.iter().find(|n| scheduler::by_name(n).is_none()) {
+        eprintln!(
+            "Unknown scheduler {}, expected one of {}",
+            name,
+            scheduler::SCHEDULERS.join(", ")
+        );
+        std::process::exit(1);
+    }
+    let trace = match read_trace(&cli.trace) {
+        Ok(trace) => trace,
+        Err(error) => {
+            eprintln!("Failed to read the trace: {}", error);
+            std::process::exit(1);
+        }
+    };
+    let mut out: Box<dyn Write> = match &cli.output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(error) => {
+                eprintln!("Failed to create {:?}: {}", path, error);
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+
+    let res = writeln!(
+        out,
+        "time,scheduler,running,pending,completed,placement_failures,rejected,\
+         memory_utilisation,cpu_utilisation,disk_utilisation,fragmentation"
+    )
+    .and_then(|_| {
+        for scheduler in names.into_iter().filter_map(scheduler::by_name) {
+            Simulation::new(scheduler, &cli).run(&trace, cli.interval.max(1), &mut out)?;
+        }
+        out.flush()
+    });
+    if let Err(error) = res {
+        eprintln!("Simulation failed: {}", error);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace_file(contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("trace-{}.csv", Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn simulation(nodes: usize) -> Simulation {
+        let cli = Cli::parse_from([
+            "simulator",
+            "trace.csv",
+            "--nodes",
+            &nodes.to_string(),
+            "--node-memory-mb",
+            "100",
+        ]);
+        Simulation::new(scheduler::by_name("first-fit").unwrap(), &cli)
+    }
+
+    #[test]
+    fn read_trace_sorts_by_submit_time() {
+        let path = trace_file(
+            "# a comment\nsubmit,duration,memory_mb,disk_gb,cpu\n\n5, 60, 1024, 5, 0.5\n0,120,2048,10,1\n",
+        );
+        let trace = read_trace(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(trace.len(), 2);
+        assert_eq!((trace[0].submit, trace[0].duration), (0, 120));
+        assert_eq!(trace[0].task.memory, Some(2048 * MB));
+        assert_eq!(trace[0].task.disk, Some(10));
+        assert_eq!(trace[0].task.cpu, Some(1.0));
+        assert_eq!((trace[1].submit, trace[1].task.cpu), (5, Some(0.5)));
+        assert_eq!(trace[1].task.name, "task-4");
+    }
+
+    #[test]
+    fn read_trace_reports_the_bad_line() {
+        let path = trace_file("0,120,2048,10,1\n0,120,2048,10\n");
+        let error = read_trace(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error, "Line 2: expected 5 fields, got 4");
+
+        let path = trace_file("0,120,lots,10,1\n");
+        let error = read_trace(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.starts_with("Line 1: "), "{}", error);
+    }
+
+    #[test]
+    fn fragmentation_of_free_memory() {
+        let mut s = simulation(2);
+        assert_eq!(s.fragmentation(), 0.5);
+
+        s.nodes[0].memory_allocated = 100 * MB;
+        assert_eq!(s.fragmentation(), 0.0);
+
+        s.nodes[0].memory_allocated = 75 * MB;
+        assert!((s.fragmentation() - 0.2).abs() < 1e-9);
+
+        s.nodes[1].memory_allocated = 100 * MB;
+        s.nodes[0].memory_allocated = 100 * MB;
+        assert_eq!(s.fragmentation(), 0.0);
+    }
+
+    #[test]
+    fn run_places_and_finishes_every_task() {
+        let mut s = simulation(1);
+        let task = |memory_mb| task::Task {
+            memory: Some(memory_mb * MB),
+            cpu: Some(0.1),
+            ..Default::default()
+        };
+        let trace = vec![
+            Submission {
+                submit: 0,
+                duration: 10,
+                task: task(60),
+            },
+            Submission {
+                submit: 0,
+                duration: 10,
+                task: task(60),
+            },
+            Submission {
+                submit: 1,
+                duration: 10,
+                task: task(200),
+            },
+        ];
+        let mut out = Vec::new();
+        s.run(&trace, 5, &mut out).unwrap();
+
+        assert_eq!(s.completed, 2);
+        assert_eq!(s.placement_failures, 1);
+        assert_eq!(s.rejected, 1);
+        assert_eq!(s.nodes[0].memory_allocated, 0);
+        assert_eq!(s.nodes[0].cpu_allocated, 0);
+        assert!(s.pending.is_empty() && s.running.is_empty());
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.starts_with("0,first-fit,1,1,0,1,0,0.6000,0.0250,"),
+            "{}",
+            out
+        );
+    }
+}
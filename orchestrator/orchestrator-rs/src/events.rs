@@ -0,0 +1,130 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::node::Node;
+use crate::task::{State, TaskEvent};
+
+/// Node reachability as seen by the manager
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub enum NodeStatus {
+    #[default]
+    Unknown,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeEvent {
+    pub timestamp: DateTime<Utc>,
+    pub node: Node,
+}
+
+/// Everything the manager publishes to event stream subscribers
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "event", rename_all = "snake_case")]
+pub enum Event {
+    Task(Box<TaskEvent<String>>),
+    Node(NodeEvent),
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Task(_) => "task",
+            Event::Node(_) => "node",
+        }
+    }
+}
+
+/// Query parameters of the event stream. Node events are only sent when
+/// no task filter is given.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EventFilter {
+    pub task_id: Option<Uuid>,
+    pub state: Option<State>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        match event {
+            Event::Task(te) => {
+                (self.task_id.is_none() || self.task_id == Some(te.task.id))
+                    && (self.state.is_none() || self.state.as_ref() == Some(&te.state))
+            }
+            Event::Node(_) => self.task_id.is_none() && self.state.is_none(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::Task;
+
+    fn task_event(id: Uuid, state: State) -> Event {
+        Event::Task(Box::new(TaskEvent {
+            id: Uuid::new_v4(),
+            state,
+            timestamp: Utc::now(),
+            task: Task {
+                id,
+                ..Default::default()
+            },
+        }))
+    }
+
+    fn node_event() -> Event {
+        Event::Node(NodeEvent {
+            timestamp: Utc::now(),
+            node: Node::new("w1", "10.0.0.1", "worker"),
+        })
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let filter = EventFilter::default();
+        assert!(filter.matches(&task_event(Uuid::new_v4(), State::Running)));
+        assert!(filter.matches(&node_event()));
+    }
+
+    #[test]
+    fn filter_by_task_id_and_state() {
+        let id = Uuid::new_v4();
+        let by_id = EventFilter {
+            task_id: Some(id),
+            state: None,
+        };
+        assert!(by_id.matches(&task_event(id, State::Running)));
+        assert!(!by_id.matches(&task_event(Uuid::new_v4(), State::Running)));
+
+        let by_state = EventFilter {
+            task_id: None,
+            state: Some(State::Failed),
+        };
+        assert!(by_state.matches(&task_event(id, State::Failed)));
+        assert!(!by_state.matches(&task_event(id, State::Running)));
+
+        let both = EventFilter {
+            task_id: Some(id),
+            state: Some(State::Failed),
+        };
+        assert!(both.matches(&task_event(id, State::Failed)));
+        assert!(!both.matches(&task_event(id, State::Running)));
+        assert!(!both.matches(&task_event(Uuid::new_v4(), State::Failed)));
+    }
+
+    #[test]
+    fn filters_suppress_node_events() {
+        let by_id = EventFilter {
+            task_id: Some(Uuid::new_v4()),
+            state: None,
+        };
+        let by_state = EventFilter {
+            task_id: None,
+            state: Some(State::Running),
+        };
+        assert!(!by_id.matches(&node_event()));
+        assert!(!by_state.matches(&node_event()));
+    }
+}
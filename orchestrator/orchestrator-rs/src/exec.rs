@@ -0,0 +1,211 @@
+//! Commands run inside running tasks, streamed over a WebSocket of the
+//! worker API.
+//!
+//! The client opens `/tasks/{id}/exec` and sends the command as a text
+//! message holding an `ExecRequest`. Then binary messages carry stdin from
+//! the client, an empty one closing it, and the command's output to the
+//! client, prefixed with the `STDOUT` or `STDERR` channel byte. The last
+//! message is an `ExecResult`, as text.
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message as ClientMessage;
+use tokio_tungstenite::WebSocketStream;
+use uuid::Uuid;
+use warp::ws::{Message, WebSocket};
+
+use crate::security::SecurityConfig;
+use crate::task::{ExecOutput, ExecSession};
+use crate::worker::SharedWorker;
+
+pub const STDOUT: u8 = 1;
+pub const STDERR: u8 = 2;
+
+/// First message of an exec WebSocket
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecRequest {
+    pub cmd: Vec<String>,
+}
+
+/// Last message of an exec WebSocket
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExecResult {
+    pub exit_code: Option<i64>,
+    pub error: Option<String>,
+}
+
+/// Worker side of an exec WebSocket
+pub async fn serve_session(ws: WebSocket, worker: SharedWorker, id: Uuid) {
+    let (mut tx, mut rx) = ws.split();
+
+    let request = match rx.next().await {
+        Some(Ok(msg)) if msg.is_text() => {
+            serde_json::from_str::<ExecRequest>(msg.to_str().unwrap_or_default())
+                .map_err(|e| e.to_string())
+        }
+        _ => Err("Expected the command as first message".to_string()),
+    };
+    let session = match request {
+        Ok(request) => worker
+            .read()
+            .await
+            .exec(&id, &request.cmd)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(error) => Err(error),
+    };
+    let ExecSession { stdin, mut output } = match session {
+        Ok(session) => session,
+        Err(error) => {
+            log::error!("Error running command in task {:#?}: {}", &id, error);
+            let result = ExecResult {
+                exit_code: None,
+                error: Some(error),
+            };
+            let _ = tx.send(result_message(&result)).await;
+            let _ = tx.close().await;
+            return;
+        }
+    };
+
+    let stdin = tokio::spawn(async move {
+        let mut stdin = Some(stdin);
+        while let Some(Ok(msg)) = rx.next().await {
+            if msg.is_close() {
+                break;
+            }
+            if !msg.is_binary() {
+                continue;
+            }
+            if msg.as_bytes().is_empty() {
+                stdin = None;
+            } else if let Some(tx) = &stdin {
+                if tx.send(msg.as_bytes().to_vec()).await.is_err() {
+                    stdin = None;
+                }
+            }
+        }
+    });
+
+    let mut result = ExecResult::default();
+    while let Some(out) = output.recv().await {
+        let frame = match out {
+            ExecOutput::Stdout(data) => channel_frame(STDOUT, data),
+            ExecOutput::Stderr(data) => channel_frame(STDERR, data),
+            ExecOutput::Exit(code) => {
+                result.exit_code = Some(code);
+                continue;
+            }
+        };
+        if tx.send(Message::binary(frame)).await.is_err() {
+            log::info!("Exec client of task {:#?} went away", &id);
+            break;
+        }
+    }
+    let _ = tx.send(result_message(&result)).await;
+    let _ = tx.close().await;
+    stdin.abort();
+}
+
+fn channel_frame(channel: u8, mut data: Vec<u8>) -> Vec<u8> {
+    data.insert(0, channel);
+    data
+}
+
+fn result_message(result: &ExecResult) -> Message {
+    Message::text(serde_json::to_string(result).unwrap_or_default())
+}
+
+/// Run the command inside the task through the worker API at `worker`,
+/// wiring it to our stdin, stdout and stderr. Returns its exit code.
+pub async fn run(
+    worker: &str,
+    id: Uuid,
+    cmd: Vec<String>,
+    security: &SecurityConfig,
+) -> Result<i64, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let stream = TcpStream::connect(worker).await?;
+    if security.tls_enabled() {
+        let host = worker.rsplit_once(':').map_or(worker, |(host, _)| host);
+        let domain = tokio_rustls::rustls::pki_types::ServerName::try_from(host.to_string())?;
+        let stream = security.tls_connector()?.connect(domain, stream).await?;
+        let url = format!("wss://{}/tasks/{}/exec", worker, id);
+        let (ws, _) = tokio_tungstenite::client_async(url, stream).await?;
+        client_session(ws, cmd).await
+    } else {
+        let url = format!("ws://{}/tasks/{}/exec", worker, id);
+        let (ws, _) = tokio_tungstenite::client_async(url, stream).await?;
+        client_session(ws, cmd).await
+    }
+}
+
+async fn client_session<S>(
+    ws: WebSocketStream<S>,
+    cmd: Vec<String>,
+) -> Result<i64, Box<dyn std::error::Error + Send + Sync + 'static>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut tx, mut rx) = ws.split();
+    let request = serde_json::to_string(&ExecRequest { cmd })?;
+    tx.send(ClientMessage::Text(request)).await?;
+
+    let stdin = tokio::spawn(async move {
+        let mut stdin = tokio::io::stdin();
+        let mut buf = vec![0u8; 8192];
+        loop {
+            match stdin.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(len) => {
+                    if tx
+                        .send(ClientMessage::Binary(buf[..len].to_vec()))
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+        }
+        // Close the command's stdin
+        let _ = tx.send(ClientMessage::Binary(Vec::new())).await;
+    });
+
+    let mut stdout = tokio::io::stdout();
+    let mut stderr = tokio::io::stderr();
+    let mut result = None;
+    while let Some(msg) = rx.next().await {
+        match msg? {
+            ClientMessage::Binary(data) => match data.split_first() {
+                Some((&STDOUT, data)) => {
+                    stdout.write_all(data).await?;
+                    stdout.flush().await?;
+                }
+                Some((&STDERR, data)) => {
+                    stderr.write_all(data).await?;
+                    stderr.flush().await?;
+                }
+                _ => log::warn!("Ignoring output on an unknown channel"),
+            },
+            ClientMessage::Text(text) => {
+                result = Some(serde_json::from_str::<ExecResult>(&text)?);
+                break;
+            }
+            ClientMessage::Close(_) => break,
+            _ => {}
+        }
+    }
+    stdin.abort();
+
+    match result {
+        Some(ExecResult {
+            error: Some(error), ..
+        }) => Err(error.into()),
+        Some(ExecResult {
+            exit_code: Some(code),
+            ..
+        }) => Ok(code),
+        _ => Err("Connection closed before the command exited".into()),
+    }
+}
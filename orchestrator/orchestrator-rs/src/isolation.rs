@@ -0,0 +1,288 @@
+//! cgroup v2 limits and namespaces for the process runtime (Linux only)
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use nix::mount::{mount, MsFlags};
+use nix::sched::{setns, unshare, CloneFlags};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{chdir, chroot, fchdir, fork, ForkResult, Pid};
+
+use crate::task::Isolation;
+
+/// Parent of the cgroups created for the tasks
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup/orchestrator";
+/// cpu.max period, in microseconds
+const CPU_PERIOD: u64 = 100_000;
+/// Smallest cpu.max quota the kernel accepts, in microseconds
+const MIN_CPU_QUOTA: u64 = 1_000;
+
+/// The cgroup v2 a single task runs in
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Create `<root>/<name>` enforcing the memory limit (bytes) and the
+    /// number of CPUs
+    pub fn create(
+        root: &Path,
+        name: &str,
+        memory: Option<u64>,
+        cpu: Option<f64>,
+    ) -> io::Result<Self> {
+        let hierarchy = root.parent().unwrap_or(root);
+        if !hierarchy.join("cgroup.controllers").exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No cgroup v2 hierarchy mounted at {:?}", hierarchy),
+            ));
+        }
+        fs::create_dir_all(root)?;
+        // Delegate the controllers to the task cgroups
+        fs::write(root.join("cgroup.subtree_control"), "+memory +cpu")?;
+
+        let cgroup = Self {
+            path: root.join(name),
+        };
+        fs::create_dir(&cgroup.path)?;
+        if let Some(memory) = memory {
+            fs::write(cgroup.path.join("memory.max"), memory.to_string())?;
+            // Hitting the limit must end in an OOM kill, not in swapping
+            if let Err(error) = fs::write(cgroup.path.join("memory.swap.max"), "0") {
+                log::info!("Cannot disable swap for {:?}: {}", cgroup.path, error);
+            }
+        }
+        if let Some(cpu) = cpu {
+            let quota = ((cpu * CPU_PERIOD as f64) as u64).max(MIN_CPU_QUOTA);
+            fs::write(
+                cgroup.path.join("cpu.max"),
+                format!("{} {}", quota, CPU_PERIOD),
+            )?;
+        }
+        Ok(cgroup)
+    }
+
+    /// `cgroup.procs` opened for writing, handed to the child so it can
+    /// move itself into the cgroup before running the task
+    pub fn procs_file(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .open(self.path.join("cgroup.procs"))
+    }
+
+    pub fn pids(&self) -> Vec<Pid> {
+        fs::read_to_string(self.path.join("cgroup.procs"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|pid| pid.trim().parse().ok())
+            .map(Pid::from_raw)
+            .collect()
+    }
+
+    /// Send the signal to every process of the cgroup but `except`
+    pub fn kill(&self, signal: Signal, except: Option<Pid>) {
+        for pid in self.pids() {
+            if Some(pid) != except {
+                let _ = kill(pid, signal);
+            }
+        }
+    }
+
+    /// Whether the kernel OOM killed any process of the cgroup
+    pub fn oom_killed(&self) -> bool {
+        fs::read_to_string(self.path.join("memory.events"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .any(|(key, count)| key == "oom_kill" && count.trim() != "0")
+    }
+
+    pub fn remove(&self) -> io::Result<()> {
+        fs::remove_dir(&self.path)
+    }
+}
+
+/// Everything the child does between fork and exec. It is prepared in the
+/// parent because only async-signal-safe calls are allowed in the child:
+/// nothing in `run` allocates.
+#[derive(Debug)]
+pub struct ChildSetup {
+    cgroup_procs: Option<File>,
+    pid_namespace: bool,
+    mount_namespace: bool,
+    rootfs: Option<CString>,
+}
+
+impl ChildSetup {
+    pub fn new(cgroup_procs: Option<File>, isolation: &Isolation) -> io::Result<Self> {
+        if isolation.rootfs.is_some() && !isolation.mount_namespace {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A rootfs needs a mount namespace",
+            ));
+        }
+        let rootfs = match &isolation.rootfs {
+            Some(rootfs) => Some(CString::new(rootfs.as_str())?),
+            None => None,
+        };
+        Ok(Self {
+            cgroup_procs,
+            pid_namespace: isolation.pid_namespace,
+            mount_namespace: isolation.mount_namespace,
+            rootfs,
+        })
+    }
+
+    pub fn run(&self) -> io::Result<()> {
+        if let Some(procs) = &self.cgroup_procs {
+            // "0" stands for the writing process
+            nix::unistd::write(procs.as_raw_fd(), b"0")?;
+        }
+
+        let mut flags = CloneFlags::empty();
+        if self.mount_namespace {
+            flags |= CloneFlags::CLONE_NEWNS;
+        }
+        if self.pid_namespace {
+            flags |= CloneFlags::CLONE_NEWPID;
+        }
+        if !flags.is_empty() {
+            unshare(flags)?;
+        }
+        if self.mount_namespace {
+            // Keep our mounts from propagating back to the host
+            mount(
+                None::<&str>,
+                "/",
+                None::<&str>,
+                MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+                None::<&str>,
+            )?;
+        }
+        if self.pid_namespace {
+            // The task becomes init of the new namespace
+            fork_and_wait()?;
+        }
+        if let Some(rootfs) = &self.rootfs {
+            chroot(rootfs.as_c_str())?;
+            chdir("/")?;
+        }
+        if self.pid_namespace && self.mount_namespace {
+            // Show the processes of the new namespace only. Best effort, a
+            // rootfs may not have a /proc
+            let _ = mount(
+                Some("proc"),
+                "/proc",
+                Some("proc"),
+                MsFlags::empty(),
+                None::<&str>,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Joins the cgroup, namespaces and root directory of a running task
+/// between fork and exec, for commands run inside it. Like `ChildSetup`,
+/// everything is opened in the parent.
+#[derive(Debug)]
+pub struct EnterSetup {
+    cgroup_procs: Option<File>,
+    mount_namespace: Option<File>,
+    pid_namespace: Option<File>,
+    root: Option<File>,
+}
+
+impl EnterSetup {
+    /// Enter what process `target` runs in, leaving out the namespaces it
+    /// shares with us
+    pub fn new(target: u32, cgroup_procs: Option<File>) -> io::Result<Self> {
+        let proc_dir = PathBuf::from(format!("/proc/{}", target));
+        let namespace = |name: &str| -> io::Result<Option<File>> {
+            let theirs = proc_dir.join("ns").join(name);
+            let ours = Path::new("/proc/self/ns").join(name);
+            if fs::metadata(&theirs)?.ino() == fs::metadata(ours)?.ino() {
+                Ok(None)
+            } else {
+                File::open(theirs).map(Some)
+            }
+        };
+        let root = if fs::read_link(proc_dir.join("root"))? == Path::new("/") {
+            None
+        } else {
+            Some(File::open(proc_dir.join("root"))?)
+        };
+        Ok(Self {
+            cgroup_procs,
+            mount_namespace: namespace("mnt")?,
+            pid_namespace: namespace("pid")?,
+            root,
+        })
+    }
+
+    pub fn run(&self) -> io::Result<()> {
+        if let Some(procs) = &self.cgroup_procs {
+            nix::unistd::write(procs.as_raw_fd(), b"0")?;
+        }
+        if let Some(ns) = &self.mount_namespace {
+            setns(ns.as_raw_fd(), CloneFlags::CLONE_NEWNS)?;
+        }
+        if let Some(ns) = &self.pid_namespace {
+            setns(ns.as_raw_fd(), CloneFlags::CLONE_NEWPID)?;
+        }
+        if let Some(root) = &self.root {
+            fchdir(root.as_raw_fd())?;
+            chroot(".")?;
+            chdir("/")?;
+        }
+        if self.pid_namespace.is_some() {
+            fork_and_wait()?;
+        }
+        Ok(())
+    }
+}
+
+/// First child of the process, the task itself when the process waits on a
+/// PID namespace
+pub fn first_child(pid: u32) -> io::Result<u32> {
+    fs::read_to_string(format!("/proc/{}/task/{}/children", pid, pid))?
+        .split_whitespace()
+        .next()
+        .and_then(|child| child.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Process has no child"))
+}
+
+/// Only the children of a process entering a PID namespace are in it: fork,
+/// carry on in the child and have the parent exit the way the child does
+fn fork_and_wait() -> io::Result<()> {
+    if let ForkResult::Parent { child } = unsafe { fork() }? {
+        // Let go of the descriptors the parent passed down, among them the
+        // pipe it waits on until exec
+        close_descriptors();
+        let code = match waitpid(child, None) {
+            Ok(WaitStatus::Exited(_, code)) => code,
+            Ok(WaitStatus::Signaled(_, signal, _)) => 128 + signal as i32,
+            _ => 1,
+        };
+        unsafe { nix::libc::_exit(code) };
+    }
+    Ok(())
+}
+
+/// Close every descriptor but stdin, stdout and stderr
+fn close_descriptors() {
+    unsafe {
+        if nix::libc::syscall(nix::libc::SYS_close_range, 3, u32::MAX, 0) != 0 {
+            let max = nix::libc::sysconf(nix::libc::_SC_OPEN_MAX).max(1024) as i32;
+            for fd in 3..max {
+                nix::libc::close(fd);
+            }
+        }
+    }
+}
@@ -0,0 +1,997 @@
+use crate::events::{Event, NodeEvent, NodeStatus};
+use crate::node::{millicores, Node};
+use crate::quota::{NamespaceUsage, Quota, QuotaExceeded, Usage};
+use crate::registry::Registry;
+use crate::scheduler::{self, RoundRobin, Scheduler};
+use crate::security::SecurityConfig;
+use crate::task::{State, Task, TaskEvent, DEFAULT_NAMESPACE};
+use chrono::prelude::*;
+use futures_util::future::join_all;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::{broadcast, RwLock};
+use tokio::time::{sleep, Duration};
+use uuid::Uuid;
+
+pub type SharedManager = Arc<RwLock<Manager>>;
+
+/// Events a slow subscriber can fall behind before it starts missing some
+const EVENTS_CAPACITY: usize = 1024;
+
+/// Why a submitted task was refused
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddTaskError {
+    Quota(QuotaExceeded),
+    /// The task depends on a task the manager does not know
+    UnknownDependency(Uuid),
+    /// The task ends up depending on itself
+    DependencyCycle(Uuid),
+    /// The task asks for a negative or non-finite number of CPUs
+    InvalidCpu(f64),
+}
+
+impl fmt::Display for AddTaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddTaskError::Quota(error) => error.fmt(f),
+            AddTaskError::UnknownDependency(id) => write!(f, "Unknown dependency {}", id),
+            AddTaskError::DependencyCycle(id) => {
+                write!(f, "Task {} depends on itself through its dependencies", id)
+            }
+            AddTaskError::InvalidCpu(cpu) => {
+                write!(f, "Invalid cpu {}, expected a number of CPUs", cpu)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AddTaskError {}
+
+impl From<QuotaExceeded> for AddTaskError {
+    fn from(error: QuotaExceeded) -> Self {
+        AddTaskError::Quota(error)
+    }
+}
+
+/// Talks to the worker APIs. Cloned out of the manager so requests are made
+/// without holding its lock.
+#[derive(Debug, Clone)]
+struct WorkerClient {
+    client: reqwest::Client,
+    scheme: &'static str,
+}
+
+impl WorkerClient {
+    async fn send_task(&self, worker: &str, te: &TaskEvent<String>) -> bool {
+        log::info!("Sending task {:#?} to worker {}", &te.task.id, worker);
+        let res = self
+            .client
+            .post(format!("{}://{}/tasks", self.scheme, worker))
+            .json(te)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        if let Err(error) = res {
+            log::error!("Error connecting to {}: {:#?}", worker, error);
+            return false;
+        }
+        true
+    }
+
+    async fn send_stop(&self, worker: &str, id: &Uuid) -> bool {
+        log::info!("Sending stop for task {:#?} to worker {}", id, worker);
+        let res = self
+            .client
+            .delete(format!("{}://{}/tasks/{}", self.scheme, worker, id))
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        if let Err(error) = res {
+            log::error!("Error stopping task {:#?} on {}: {:#?}", id, worker, error);
+            return false;
+        }
+        true
+    }
+
+    async fn get_tasks(&self, worker: &str) -> Result<Vec<Task<String>>, reqwest::Error> {
+        self.client
+            .get(format!("{}://{}/tasks", self.scheme, worker))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+}
+
+/// What to do next with the pending task of highest priority
+enum Work {
+    /// Send the task to the worker
+    Send(String, Box<TaskEvent<String>>),
+    /// Stop these tasks of the worker to make room for the task
+    Preempt {
+        worker: String,
+        victims: Vec<Uuid>,
+        task: Uuid,
+    },
+}
+
+#[derive(Debug)]
+pub struct Manager {
+    /// Tasks ready to be sent to a worker
+    pub pending: VecDeque<TaskEvent<String>>,
+    /// Tasks held back until all of their dependencies are `Completed`
+    pub blocked: HashMap<Uuid, TaskEvent<String>>,
+    pub task_db: HashMap<Uuid, Task<String>>,
+    pub event_db: HashMap<Uuid, Vec<TaskEvent<String>>>,
+    /// Address (`host:port`) of every worker API
+    pub workers: Vec<String>,
+    pub worker_task_map: HashMap<String, Vec<Uuid>>,
+    pub task_worker_map: HashMap<Uuid, String>,
+    /// Node each worker runs on, keyed by worker address
+    pub worker_nodes: HashMap<String, Node>,
+    /// Endpoints of the running tasks, keyed by service name
+    pub registry: Registry,
+    /// Quotas of the namespaces having one, keyed by namespace
+    pub quotas: HashMap<String, Quota>,
+    /// Picks the worker each task is sent to
+    pub scheduler: Box<dyn Scheduler>,
+    /// Stop lower priority tasks to make room for a task which fits on no
+    /// worker
+    pub preemption: bool,
+    /// Tasks asked to stop to make room, until their worker reports them
+    /// stopped, and the task each one makes room for
+    preempting: HashMap<Uuid, Uuid>,
+    workers_api: WorkerClient,
+    events: broadcast::Sender<Event>,
+}
+
+impl Manager {
+    /// Manager sending work to the worker APIs at `workers`, over mutual
+    /// TLS when the security config has certificates
+    pub fn new(
+        workers: Vec<String>,
+        security: &SecurityConfig,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let worker_task_map = workers.iter().map(|w| (w.clone(), Vec::new())).collect();
+        Ok(Self {
+            pending: VecDeque::new(),
+            blocked: HashMap::new(),
+            task_db: HashMap::new(),
+            event_db: HashMap::new(),
+            workers,
+            worker_task_map,
+            task_worker_map: HashMap::new(),
+            worker_nodes: HashMap::new(),
+            registry: Registry::default(),
+            quotas: HashMap::new(),
+            scheduler: Box::<RoundRobin>::default(),
+            preemption: false,
+            preempting: HashMap::new(),
+            workers_api: WorkerClient {
+                client: security.worker_client()?,
+                scheme: security.scheme(),
+            },
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn register_node(&mut self, worker: &str, node: Node) {
+        self.worker_nodes.insert(worker.to_string(), node);
+    }
+
+    /// Receive every task event and node status change from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_tasks(&self) -> Vec<Task<String>> {
+        self.task_db.values().cloned().collect()
+    }
+
+    /// Accept a new task and return its id. Tasks whose dependencies are
+    /// not `Completed` yet stay `Pending` until `update_tasks` releases them.
+    /// Tasks which do not fit in their namespace's quota, or whose
+    /// dependencies could never complete, are refused.
+    #[allow(dead_code)]
+    pub fn add_task(&mut self, mut te: TaskEvent<String>) -> Result<Uuid, AddTaskError> {
+        // Fill in what API clients are allowed to leave out
+        if te.task.id.is_nil() {
+            te.task.id = Uuid::new_v4();
+        }
+        if te.id.is_nil() {
+            te.id = Uuid::new_v4();
+        }
+        if te.task.namespace.is_empty() {
+            te.task.namespace = DEFAULT_NAMESPACE.to_string();
+        }
+        // Negative CPUs would lower the namespace's usage past its quota
+        if let Some(cpu) = te.task.cpu {
+            if !cpu.is_finite() || cpu < 0.0 {
+                return Err(AddTaskError::InvalidCpu(cpu));
+            }
+        }
+        self.check_dependencies(&te.task)?;
+        if let Some(quota) = self.quotas.get(&te.task.namespace) {
+            let usage = self.usage(&te.task.namespace);
+            quota.check(&te.task.namespace, &usage, &te.task)?;
+        }
+        te.timestamp = Utc::now();
+        self.task_db.insert(te.task.id, te.task.clone());
+        self.record_event(te.clone());
+        let id = te.task.id;
+        if self.dependencies_completed(&te.task) {
+            self.pending.push_back(te);
+        } else {
+            log::info!(
+                "Task {:#?} waiting for {:#?}",
+                &te.task.id,
+                &te.task.depends_on
+            );
+            self.blocked.insert(te.task.id, te);
+        }
+        Ok(id)
+    }
+
+    /// Set the quota of a namespace. It only applies to the tasks submitted
+    /// from now on.
+    #[allow(dead_code)]
+    pub fn set_quota(&mut self, namespace: &str, quota: Quota) {
+        self.quotas.insert(namespace.to_string(), quota);
+    }
+
+    /// What the unfinished tasks of the namespace ask for
+    pub fn usage(&self, namespace: &str) -> Usage {
+        let mut usage = Usage::default();
+        for t in self.task_db.values().filter(|t| t.namespace == namespace) {
+            usage.add(t);
+        }
+        usage
+    }
+
+    /// Quota and usage of every namespace having a quota or tasks
+    #[allow(dead_code)]
+    pub fn namespaces(&self) -> Vec<NamespaceUsage> {
+        let mut names: Vec<&String> = self
+            .quotas
+            .keys()
+            .chain(self.task_db.values().map(|t| &t.namespace))
+            .collect();
+        names.sort();
+        names.dedup();
+        names.into_iter().map(|name| self.namespace(name)).collect()
+    }
+
+    pub fn namespace(&self, namespace: &str) -> NamespaceUsage {
+        NamespaceUsage {
+            namespace: namespace.to_string(),
+            quota: self.quotas.get(namespace).cloned().unwrap_or_default(),
+            usage: self.usage(namespace),
+        }
+    }
+
+    /// Node of every worker, in the order of `workers`
+    fn nodes(&self) -> Vec<Node> {
+        self.workers
+            .iter()
+            .map(|name| {
+                self.worker_nodes
+                    .get(name)
+                    .cloned()
+                    .unwrap_or_else(|| Node::new(name, "", "worker"))
+            })
+            .collect()
+    }
+
+    /// Worker the scheduler places the task on, if it fits anywhere
+    #[allow(dead_code)]
+    pub fn select_worker(&mut self, t: &Task<String>) -> Option<usize> {
+        let nodes = self.nodes();
+        self.scheduler.select(t, &nodes)
+    }
+
+    /// Take the pending task with the highest priority and pick what to do
+    /// with it. The task is accounted on its worker right away so the next
+    /// pick does not count on the same room; `unsent` gives it back.
+    fn next_work(&mut self) -> Option<Work> {
+        // The first one submitted among the highest priority ones
+        let next = (0..self.pending.len())
+            .rev()
+            .max_by_key(|&idx| self.pending[idx].task.priority);
+        let Some(mut te) = next.and_then(|idx| self.pending.remove(idx)) else {
+            log::info!("No work in the queue");
+            return None;
+        };
+        let Some(idx) = self.select_worker(&te.task) else {
+            log::error!("No workers available for task {:#?}", &te.task.id);
+            // Room being made for it already, wait for the victims to stop
+            let waiting = self.preempting.values().any(|id| *id == te.task.id);
+            let preempt = if self.preemption && !waiting {
+                self.victims_for(&te.task)
+            } else {
+                None
+            };
+            let task = te.task.id;
+            self.pending.push_front(te);
+            return preempt.map(|(worker, victims)| Work::Preempt {
+                worker,
+                victims,
+                task,
+            });
+        };
+        let name = self.workers[idx].clone();
+
+        te.state = State::Scheduled;
+        te.timestamp = Utc::now();
+        te.task.state = State::Scheduled;
+        // Left over from a preemption, the events keep track of it
+        te.task.reason = None;
+        self.allocate(&name, &te.task);
+        Some(Work::Send(name, Box::new(te)))
+    }
+
+    /// The worker accepted the task
+    fn sent(&mut self, te: TaskEvent<String>) {
+        self.task_db.insert(te.task.id, te.task.clone());
+        self.record_event(te);
+    }
+
+    /// The worker could not be reached, queue the task again
+    fn unsent(&mut self, worker: &str, mut te: TaskEvent<String>) {
+        self.release(worker, &te.task);
+        te.state = State::Pending;
+        te.task.state = State::Pending;
+        self.pending.push_back(te);
+    }
+
+    /// The fewest, lowest priority tasks of a single worker which leave room
+    /// for the task once gone. Tasks already stopping count as gone.
+    fn victims_for(&self, t: &Task<String>) -> Option<(String, Vec<Uuid>)> {
+        let mut best: Option<(i32, usize, String, Vec<Uuid>)> = None;
+        for (name, mut node) in self.workers.iter().zip(self.nodes()) {
+            let tasks = self
+                .worker_task_map
+                .get(name)
+                .into_iter()
+                .flatten()
+                .filter_map(|id| self.task_db.get(id));
+            let mut candidates = Vec::new();
+            for running in tasks {
+                if self.preempting.contains_key(&running.id) {
+                    release_on(&mut node, running);
+                } else if running.priority < t.priority {
+                    candidates.push(running);
+                }
+            }
+            candidates.sort_by_key(|running| running.priority);
+
+            let mut victims = Vec::new();
+            for running in candidates {
+                if scheduler::fits(t, &node) {
+                    break;
+                }
+                release_on(&mut node, running);
+                victims.push(running);
+            }
+            if victims.is_empty() || !scheduler::fits(t, &node) {
+                continue;
+            }
+            let highest = victims.iter().map(|v| v.priority).max().unwrap_or_default();
+            let better = match &best {
+                Some((best_highest, count, ..)) => {
+                    (highest, victims.len()) < (*best_highest, *count)
+                }
+                None => true,
+            };
+            if better {
+                let ids = victims.iter().map(|v| v.id).collect();
+                best = Some((highest, victims.len(), name.clone(), ids));
+            }
+        }
+
+        match best {
+            Some((_, _, name, victims)) => Some((name, victims)),
+            None => {
+                log::info!("Nothing to preempt for task {:#?}", &t.id);
+                None
+            }
+        }
+    }
+
+    /// The worker accepted to stop the victims. They keep their resources
+    /// until it reports them stopped, see `requeue`.
+    fn preempted(&mut self, worker: &str, victims: &[Uuid], task: &Uuid) {
+        for id in victims {
+            // Finished or stopped while the worker was asked
+            if self.task_worker_map.get(id).map(String::as_str) != Some(worker) {
+                continue;
+            }
+            self.preempting.insert(*id, *task);
+        }
+    }
+
+    /// The worker stopped a victim of preemption, queue it again
+    fn requeue(&mut self, worker: &str, mut victim: Task<String>, task: &Uuid) {
+        self.release(worker, &victim);
+        victim.state = State::Pending;
+        victim.reason = Some(format!("Preempted by task {}", task));
+        victim.start_time = None;
+        victim.finish_time = None;
+        self.task_db.insert(victim.id, victim.clone());
+        self.registry.deregister(&victim.id);
+        let te = task_event(victim);
+        self.pending.push_back(te.clone());
+        self.record_event(te);
+    }
+
+    /// Account for the task sent to the worker
+    fn allocate(&mut self, worker: &str, t: &Task<String>) {
+        self.worker_task_map
+            .entry(worker.to_string())
+            .or_default()
+            .push(t.id);
+        self.task_worker_map.insert(t.id, worker.to_string());
+        if let Some(node) = self.worker_nodes.get_mut(worker) {
+            node.memory_allocated += t.memory.unwrap_or_default();
+            node.disk_allocated += t.disk.unwrap_or_default();
+            node.cpu_allocated += millicores(t.cpu);
+            node.task_count += 1;
+        }
+    }
+
+    /// The task stopped using the worker's resources
+    fn release(&mut self, worker: &str, t: &Task<String>) {
+        if let Some(ids) = self.worker_task_map.get_mut(worker) {
+            ids.retain(|id| *id != t.id);
+        }
+        self.task_worker_map.remove(&t.id);
+        if let Some(node) = self.worker_nodes.get_mut(worker) {
+            release_on(node, t);
+        }
+    }
+
+    /// The worker stopped the task on request
+    fn stopped(&mut self, worker: &str, id: &Uuid) {
+        // Finished or preempted while the worker was asked
+        if self.task_worker_map.get(id).map(String::as_str) != Some(worker) {
+            return;
+        }
+        self.preempting.remove(id);
+        let mut t = self.task_db[id].clone();
+        self.release(worker, &t);
+        t.state = State::Completed;
+        t.finish_time = Some(Utc::now());
+        self.task_db.insert(*id, t.clone());
+        self.registry.deregister(id);
+        self.record_event(task_event(t));
+    }
+
+    /// Take a pending or blocked task off the queue. It ends up `Failed`,
+    /// failing the tasks depending on it in turn.
+    fn cancel(&mut self, id: &Uuid) -> bool {
+        let te = match self.pending.iter().position(|te| te.task.id == *id) {
+            Some(idx) => self.pending.remove(idx),
+            None => self.blocked.remove(id),
+        };
+        let Some(mut te) = te else {
+            return false;
+        };
+        log::info!("Cancelled task {:#?}", id);
+        te.id = Uuid::new_v4();
+        te.state = State::Failed;
+        te.timestamp = Utc::now();
+        te.task.state = State::Failed;
+        te.task.reason = Some("Cancelled".to_string());
+        te.task.finish_time = Some(te.timestamp);
+        self.task_db.insert(*id, te.task.clone());
+        self.record_event(te);
+        true
+    }
+
+    /// Apply the tasks a worker listed, or mark its node down when it could
+    /// not be reached
+    fn worker_tasks(&mut self, name: &str, tasks: Result<Vec<Task<String>>, reqwest::Error>) {
+        let tasks = match tasks {
+            Ok(tasks) => tasks,
+            Err(error) => {
+                log::error!("Error getting tasks from {}: {:#?}", name, error);
+                self.set_node_status(name, NodeStatus::Down);
+                return;
+            }
+        };
+        self.set_node_status(name, NodeStatus::Up);
+
+        for t in tasks {
+            // Tasks stopped or preempted since, the worker still lists them
+            if self.task_worker_map.get(&t.id).map(String::as_str) != Some(name) {
+                continue;
+            }
+            let Some(persisted) = self.task_db.get_mut(&t.id) else {
+                log::error!("Task with id {:#?} not found", &t.id);
+                continue;
+            };
+            let changed = persisted.state != t.state;
+            persisted.state = t.state;
+            persisted.container_id = t.container_id;
+            persisted.start_time = t.start_time;
+            persisted.finish_time = t.finish_time;
+            persisted.reason = t.reason;
+            if changed {
+                let t = persisted.clone();
+                let finished = t.state == State::Completed || t.state == State::Failed;
+                if finished {
+                    if let Some(task) = self.preempting.remove(&t.id) {
+                        self.requeue(name, t, &task);
+                        continue;
+                    }
+                    self.release(name, &t);
+                }
+                self.record_event(task_event(t));
+            }
+        }
+    }
+
+    fn update_registry(&mut self) {
+        for t in self.task_db.values() {
+            if t.state != State::Running {
+                self.registry.deregister(&t.id);
+                continue;
+            }
+            let node = self
+                .task_worker_map
+                .get(&t.id)
+                .and_then(|name| self.worker_nodes.get(name));
+            match node {
+                Some(node) => self.registry.register(t, node),
+                None => log::error!("No node known for task {:#?}", &t.id),
+            }
+        }
+    }
+
+    fn release_blocked(&mut self) {
+        let ids: Vec<Uuid> = self.blocked.keys().cloned().collect();
+        for id in ids {
+            let t = self.blocked[&id].task.clone();
+            if self.dependency_failed(&t) {
+                let mut te = self.blocked.remove(&id).unwrap();
+                log::error!("Task {:#?} failed: a dependency failed", &id);
+                te.state = State::Failed;
+                te.timestamp = Utc::now();
+                te.task.state = State::Failed;
+                te.task.finish_time = Some(te.timestamp);
+                self.task_db.insert(id, te.task.clone());
+                self.record_event(te);
+            } else if self.dependencies_completed(&t) {
+                let te = self.blocked.remove(&id).unwrap();
+                log::info!("Dependencies of task {:#?} completed", &id);
+                self.pending.push_back(te);
+            }
+        }
+    }
+
+    /// Dependencies must be known tasks, and must not lead back to the task:
+    /// either way it would stay blocked forever
+    fn check_dependencies(&self, t: &Task<String>) -> Result<(), AddTaskError> {
+        if let Some(id) = t
+            .depends_on
+            .iter()
+            .find(|id| !self.task_db.contains_key(id))
+        {
+            return Err(AddTaskError::UnknownDependency(*id));
+        }
+        let mut seen = HashSet::new();
+        let mut next: Vec<Uuid> = t.depends_on.clone();
+        while let Some(id) = next.pop() {
+            if id == t.id {
+                return Err(AddTaskError::DependencyCycle(t.id));
+            }
+            if seen.insert(id) {
+                if let Some(dep) = self.task_db.get(&id) {
+                    next.extend(dep.depends_on.iter().cloned());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn dependencies_completed(&self, t: &Task<String>) -> bool {
+        t.depends_on.iter().all(|id| {
+            self.task_db
+                .get(id)
+                .is_some_and(|dep| dep.state == State::Completed)
+        })
+    }
+
+    fn dependency_failed(&self, t: &Task<String>) -> bool {
+        t.depends_on.iter().any(|id| {
+            self.task_db
+                .get(id)
+                .is_some_and(|dep| dep.state == State::Failed)
+        })
+    }
+
+    fn set_node_status(&mut self, worker: &str, status: NodeStatus) {
+        let Some(node) = self.worker_nodes.get_mut(worker) else {
+            return;
+        };
+        if node.status != status {
+            log::info!("Node {} is {:#?}", worker, status);
+            node.status = status;
+            let _ = self.events.send(Event::Node(NodeEvent {
+                timestamp: Utc::now(),
+                node: node.clone(),
+            }));
+        }
+    }
+
+    fn record_event(&mut self, te: TaskEvent<String>) {
+        // Sending only fails when nobody is subscribed
+        let _ = self.events.send(Event::Task(Box::new(te.clone())));
+        self.event_db.entry(te.task.id).or_default().push(te);
+    }
+}
+
+fn release_on(node: &mut Node, t: &Task<String>) {
+    node.memory_allocated = node
+        .memory_allocated
+        .saturating_sub(t.memory.unwrap_or_default());
+    node.disk_allocated = node
+        .disk_allocated
+        .saturating_sub(t.disk.unwrap_or_default());
+    node.cpu_allocated = node.cpu_allocated.saturating_sub(millicores(t.cpu));
+    node.task_count = node.task_count.saturating_sub(1);
+}
+
+/// Wrap a task into a new event carrying its current state
+#[allow(dead_code)]
+pub fn task_event(t: Task<String>) -> TaskEvent<String> {
+    TaskEvent {
+        id: Uuid::new_v4(),
+        state: t.state.clone(),
+        timestamp: Utc::now(),
+        task: t,
+    }
+}
+
+/// Send the pending task with the highest priority to a worker,
+/// preempting lower priority tasks when it fits nowhere and preemption is
+/// enabled. The manager is only locked around the bookkeeping, not while
+/// workers are asked.
+pub async fn send_work(manager: &SharedManager) {
+    let (work, workers_api) = {
+        let mut m = manager.write().await;
+        (m.next_work(), m.workers_api.clone())
+    };
+    match work {
+        None => {}
+        Some(Work::Send(name, te)) => {
+            let sent = workers_api.send_task(&name, &te).await;
+            let mut m = manager.write().await;
+            if sent {
+                m.sent(*te);
+            } else {
+                m.unsent(&name, *te);
+            }
+        }
+        Some(Work::Preempt {
+            worker,
+            victims,
+            task,
+        }) => {
+            let mut stopped = Vec::new();
+            for id in victims {
+                log::info!(
+                    "Preempting task {:#?} on {} for task {:#?}",
+                    &id,
+                    worker,
+                    &task
+                );
+                if workers_api.send_stop(&worker, &id).await {
+                    stopped.push(id);
+                }
+            }
+            manager.write().await.preempted(&worker, &stopped, &task);
+        }
+    }
+}
+
+/// Ask the worker running the task to stop it, or cancel it when it has not
+/// been sent to a worker yet. Returns `false` when the task is unknown, has
+/// finished or the worker could not be reached.
+pub async fn stop_task(manager: &SharedManager, id: &Uuid) -> bool {
+    let (name, workers_api) = {
+        let mut m = manager.write().await;
+        if m.cancel(id) {
+            return true;
+        }
+        let Some(name) = m.task_worker_map.get(id).cloned() else {
+            return false;
+        };
+        (name, m.workers_api.clone())
+    };
+    if !workers_api.send_stop(&name, id).await {
+        return false;
+    }
+    manager.write().await.stopped(&name, id);
+    true
+}
+
+/// Refresh the state of every task from its worker, keep the service
+/// registry in sync and release the blocked tasks whose dependencies have
+/// finished. Workers are asked concurrently, without holding the lock.
+pub async fn update_tasks(manager: &SharedManager) {
+    let (workers, workers_api) = {
+        let m = manager.read().await;
+        (m.workers.clone(), m.workers_api.clone())
+    };
+    let tasks = join_all(workers.iter().map(|name| workers_api.get_tasks(name))).await;
+
+    let mut m = manager.write().await;
+    for (name, tasks) in workers.iter().zip(tasks) {
+        m.worker_tasks(name, tasks);
+    }
+    m.update_registry();
+    m.release_blocked();
+}
+
+/// Send pending work and refresh task states forever
+pub async fn run(manager: SharedManager) {
+    loop {
+        send_work(&manager).await;
+        update_tasks(&manager).await;
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORKERS: [&str; 2] = ["w1:5556", "w2:5556"];
+
+    fn manager() -> Manager {
+        let workers = WORKERS.iter().map(|w| w.to_string()).collect();
+        let mut m = Manager::new(workers, &SecurityConfig::default()).unwrap();
+        m.preemption = true;
+        for name in WORKERS {
+            let mut node = Node::new(name, "", "worker");
+            node.memory = 100;
+            m.register_node(name, node);
+        }
+        m
+    }
+
+    /// Task running on the worker
+    fn run_on(m: &mut Manager, worker: &str, memory: u64, priority: i32) -> Uuid {
+        let t = Task {
+            id: Uuid::new_v4(),
+            state: State::Running,
+            memory: Some(memory),
+            priority,
+            ..Default::default()
+        };
+        m.allocate(worker, &t);
+        m.task_db.insert(t.id, t.clone());
+        t.id
+    }
+
+    fn task(memory: u64, priority: i32) -> Task<String> {
+        Task {
+            id: Uuid::new_v4(),
+            memory: Some(memory),
+            priority,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn victims_are_the_fewest_lowest_priority_tasks() {
+        let mut m = manager();
+        let low = run_on(&mut m, WORKERS[0], 30, 0);
+        let _mid = run_on(&mut m, WORKERS[0], 30, 1);
+        let _high = run_on(&mut m, WORKERS[0], 40, 5);
+        m.worker_nodes.get_mut(WORKERS[1]).unwrap().memory_allocated = 100;
+
+        assert_eq!(
+            m.victims_for(&task(30, 2)),
+            Some((WORKERS[0].to_string(), vec![low]))
+        );
+        let victims = m.victims_for(&task(60, 2)).unwrap().1;
+        assert_eq!(victims.len(), 2);
+        assert!(victims.contains(&low));
+    }
+
+    #[test]
+    fn victims_come_from_the_worker_with_the_lowest_priorities() {
+        let mut m = manager();
+        run_on(&mut m, WORKERS[0], 100, 3);
+        let low = run_on(&mut m, WORKERS[1], 100, 1);
+
+        assert_eq!(
+            m.victims_for(&task(50, 4)),
+            Some((WORKERS[1].to_string(), vec![low]))
+        );
+    }
+
+    #[test]
+    fn no_victims_without_room_from_lower_priorities() {
+        let mut m = manager();
+        run_on(&mut m, WORKERS[0], 50, 0);
+        run_on(&mut m, WORKERS[0], 50, 2);
+        run_on(&mut m, WORKERS[1], 100, 2);
+
+        assert_eq!(m.victims_for(&task(10, 0)), None);
+        assert_eq!(m.victims_for(&task(60, 1)), None);
+        assert_eq!(m.victims_for(&task(101, 9)), None);
+    }
+
+    #[test]
+    fn preemption_triggers_on_nodes_of_known_size() {
+        let mut m = manager();
+        run_on(&mut m, WORKERS[0], 100, 0);
+        run_on(&mut m, WORKERS[1], 100, 0);
+        let urgent = task(50, 1);
+        m.pending.push_back(task_event(urgent.clone()));
+
+        match m.next_work() {
+            Some(Work::Preempt { victims, task, .. }) => {
+                assert_eq!(victims.len(), 1);
+                assert_eq!(task, urgent.id);
+            }
+            _ => panic!("expected a preemption"),
+        }
+        assert_eq!(m.pending.len(), 1);
+    }
+
+    #[test]
+    fn victims_keep_their_resources_until_stopped() {
+        let mut m = manager();
+        let victim = run_on(&mut m, WORKERS[0], 100, 0);
+        m.worker_nodes.get_mut(WORKERS[1]).unwrap().memory_allocated = 100;
+        let urgent = task(50, 1);
+        m.pending.push_back(task_event(urgent.clone()));
+        m.preempted(WORKERS[0], &[victim], &urgent.id);
+
+        // Still running: no room yet, and no more victims looked for
+        assert!(m.next_work().is_none());
+        assert_eq!(m.worker_nodes[WORKERS[0]].memory_allocated, 100);
+        assert_eq!(m.task_worker_map[&victim], WORKERS[0]);
+
+        let mut stopped = m.task_db[&victim].clone();
+        stopped.state = State::Completed;
+        m.worker_tasks(WORKERS[0], Ok(vec![stopped]));
+
+        let requeued = &m.task_db[&victim];
+        assert_eq!(requeued.state, State::Pending);
+        assert_eq!(
+            requeued.reason,
+            Some(format!("Preempted by task {}", urgent.id))
+        );
+        assert!(!m.task_worker_map.contains_key(&victim));
+        assert!(m.preempting.is_empty());
+
+        // The urgent task goes first, the victim after it
+        match m.next_work() {
+            Some(Work::Send(name, te)) => {
+                assert_eq!(name, WORKERS[0]);
+                assert_eq!(te.task.id, urgent.id);
+            }
+            _ => panic!("expected the urgent task to be sent"),
+        }
+        assert_eq!(m.pending.len(), 1);
+        assert_eq!(m.pending[0].task.id, victim);
+    }
+
+    #[test]
+    fn tasks_asking_for_invalid_cpu_are_refused() {
+        let mut m = manager();
+        m.set_quota(
+            DEFAULT_NAMESPACE,
+            Quota {
+                cpu: Some(1.0),
+                ..Default::default()
+            },
+        );
+        for cpu in [-4.0, f64::NAN, f64::INFINITY] {
+            let t = Task {
+                cpu: Some(cpu),
+                ..Default::default()
+            };
+            assert!(matches!(
+                m.add_task(task_event(t)),
+                Err(AddTaskError::InvalidCpu(_))
+            ));
+        }
+        assert!(m.task_db.is_empty());
+        assert_eq!(m.usage(DEFAULT_NAMESPACE).cpu, 0);
+    }
+
+    fn depending_on(depends_on: &[Uuid]) -> Task<String> {
+        Task {
+            id: Uuid::new_v4(),
+            depends_on: depends_on.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unknown_dependencies_are_refused() {
+        let mut m = manager();
+        let missing = Uuid::new_v4();
+        assert!(matches!(
+            m.add_task(task_event(depending_on(&[missing]))),
+            Err(AddTaskError::UnknownDependency(id)) if id == missing
+        ));
+        assert!(m.task_db.is_empty());
+    }
+
+    #[test]
+    fn dependency_cycles_are_refused() {
+        let mut m = manager();
+
+        // Sent again depending on itself
+        let t = depending_on(&[]);
+        m.task_db.insert(t.id, t.clone());
+        let itself = Task {
+            depends_on: vec![t.id],
+            ..t.clone()
+        };
+        assert!(matches!(
+            m.add_task(task_event(itself)),
+            Err(AddTaskError::DependencyCycle(id)) if id == t.id
+        ));
+
+        // Through another task depending on it
+        let new_id = Uuid::new_v4();
+        let middle = depending_on(&[new_id]);
+        m.task_db.insert(middle.id, middle.clone());
+        let new = Task {
+            id: new_id,
+            ..depending_on(&[middle.id])
+        };
+        assert!(matches!(
+            m.add_task(task_event(new)),
+            Err(AddTaskError::DependencyCycle(id)) if id == new_id
+        ));
+        assert!(!m.task_db.contains_key(&new_id));
+    }
+
+    #[test]
+    fn blocked_tasks_are_released_once_their_dependencies_complete() {
+        let mut m = manager();
+        let dependency = m.add_task(task_event(depending_on(&[]))).unwrap();
+        let dependant = m.add_task(task_event(depending_on(&[dependency]))).unwrap();
+        assert!(m.blocked.contains_key(&dependant));
+        assert_eq!(m.pending.len(), 1);
+
+        m.release_blocked();
+        assert!(m.blocked.contains_key(&dependant));
+
+        m.task_db.get_mut(&dependency).unwrap().state = State::Completed;
+        m.release_blocked();
+        assert!(m.blocked.is_empty());
+        assert_eq!(m.pending.back().unwrap().task.id, dependant);
+    }
+
+    #[test]
+    fn dependants_of_a_failed_task_fail() {
+        let mut m = manager();
+        let dependency = m.add_task(task_event(depending_on(&[]))).unwrap();
+        let other = m.add_task(task_event(depending_on(&[]))).unwrap();
+        let dependant = m
+            .add_task(task_event(depending_on(&[dependency, other])))
+            .unwrap();
+
+        m.task_db.get_mut(&dependency).unwrap().state = State::Failed;
+        m.release_blocked();
+
+        assert!(m.blocked.is_empty());
+        assert_eq!(m.pending.len(), 2);
+        let failed = &m.task_db[&dependant];
+        assert_eq!(failed.state, State::Failed);
+        assert!(failed.finish_time.is_some());
+    }
+}
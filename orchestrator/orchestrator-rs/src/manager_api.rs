@@ -0,0 +1,270 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+use warp::{http::StatusCode, sse, Filter, Rejection, Reply};
+
+use crate::events::EventFilter;
+use crate::manager::{self, AddTaskError, SharedManager};
+use crate::quota::Quota;
+use crate::security::{self, SecurityConfig};
+use crate::task::TaskEvent;
+
+#[derive(Debug, Serialize)]
+pub struct ErrResponse {
+    pub http_status_code: u16,
+    pub message: String,
+}
+
+pub fn error_reply(
+    status: StatusCode,
+    message: String,
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(
+        warp::reply::json(&ErrResponse {
+            http_status_code: status.as_u16(),
+            message,
+        }),
+        status,
+    )
+}
+
+async fn start_task(
+    manager: SharedManager,
+    te: TaskEvent<String>,
+) -> Result<impl Reply, Rejection> {
+    let mut m = manager.write().await;
+    let id = match m.add_task(te) {
+        Ok(id) => id,
+        Err(error) => {
+            log::info!("Refused task: {}", error);
+            let status = match error {
+                AddTaskError::Quota(_) => StatusCode::FORBIDDEN,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            return Ok(error_reply(status, error.to_string()));
+        }
+    };
+    let t = m.task_db[&id].clone();
+    log::info!("Added task {:#?}", &t.id);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&t),
+        StatusCode::CREATED,
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TasksQuery {
+    pub namespace: Option<String>,
+}
+
+async fn get_tasks(query: TasksQuery, manager: SharedManager) -> Result<impl Reply, Rejection> {
+    let mut tasks = manager.read().await.get_tasks();
+    if let Some(namespace) = query.namespace {
+        tasks.retain(|t| t.namespace == namespace);
+    }
+    Ok(warp::reply::json(&tasks))
+}
+
+async fn get_namespaces(manager: SharedManager) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&manager.read().await.namespaces()))
+}
+
+async fn get_namespace(name: String, manager: SharedManager) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&manager.read().await.namespace(&name)))
+}
+
+async fn set_quota(
+    name: String,
+    manager: SharedManager,
+    quota: Quota,
+) -> Result<impl Reply, Rejection> {
+    let mut m = manager.write().await;
+    log::info!("Quota of namespace {}: {:#?}", name, &quota);
+    m.set_quota(&name, quota);
+    Ok(warp::reply::json(&m.namespace(&name)))
+}
+
+async fn stop_task(id: Uuid, manager: SharedManager) -> Result<impl Reply, Rejection> {
+    if manager::stop_task(&manager, &id).await {
+        Ok(StatusCode::NO_CONTENT.into_response())
+    } else {
+        log::info!("No task with id {:#?} found", &id);
+        Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("No task with id {} found", id),
+        )
+        .into_response())
+    }
+}
+
+async fn get_services(manager: SharedManager) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&manager.read().await.registry.services()))
+}
+
+/// Endpoints of the service addressed as `name.namespace`, or `name` in the
+/// default namespace
+async fn get_service(name: String, manager: SharedManager) -> Result<impl Reply, Rejection> {
+    let endpoints = manager.read().await.registry.lookup(&name);
+    if endpoints.is_empty() {
+        Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("No endpoints for service {}", name),
+        ))
+    } else {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&endpoints),
+            StatusCode::OK,
+        ))
+    }
+}
+
+/// Server-sent events stream of task events and node status changes
+async fn get_events(filter: EventFilter, manager: SharedManager) -> Result<impl Reply, Rejection> {
+    let rx = manager.read().await.subscribe();
+    let events = stream::unfold((rx, filter), |(mut rx, filter)| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) if filter.matches(&event) => {
+                    let sse_event = sse::Event::default().event(event.name()).json_data(&event);
+                    return Some((sse_event, (rx, filter)));
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    log::warn!("Event stream subscriber missed {} events", skipped);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    Ok(sse::reply(sse::keep_alive().stream(events)))
+}
+
+pub fn routes(
+    manager: SharedManager,
+    tokens: Arc<Vec<String>>,
+    insecure: bool,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let manager_filter = warp::any().map(move || manager.clone());
+
+    let start_task = warp::post()
+        .and(warp::path("tasks"))
+        .and(warp::path::end())
+        .and(manager_filter.clone())
+        .and(warp::body::json())
+        .and_then(start_task);
+
+    let get_tasks = warp::get()
+        .and(warp::path("tasks"))
+        .and(warp::path::end())
+        .and(warp::query::<TasksQuery>())
+        .and(manager_filter.clone())
+        .and_then(get_tasks);
+
+    let stop_task = warp::delete()
+        .and(warp::path("tasks"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path::end())
+        .and(manager_filter.clone())
+        .and_then(stop_task);
+
+    let get_services = warp::get()
+        .and(warp::path("services"))
+        .and(warp::path::end())
+        .and(manager_filter.clone())
+        .and_then(get_services);
+
+    let get_namespaces = warp::get()
+        .and(warp::path("namespaces"))
+        .and(warp::path::end())
+        .and(manager_filter.clone())
+        .and_then(get_namespaces);
+
+    let get_namespace = warp::get()
+        .and(warp::path("namespaces"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(manager_filter.clone())
+        .and_then(get_namespace);
+
+    let set_quota = warp::put()
+        .and(warp::path("namespaces"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("quota"))
+        .and(warp::path::end())
+        .and(manager_filter.clone())
+        .and(warp::body::json())
+        .and_then(set_quota);
+
+    let get_events = warp::get()
+        .and(warp::path("events"))
+        .and(warp::path::end())
+        .and(warp::query::<EventFilter>())
+        .and(manager_filter.clone())
+        .and_then(get_events);
+
+    let get_service = warp::get()
+        .and(warp::path("services"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(manager_filter)
+        .and_then(get_service);
+
+    security::bearer_auth(tokens, insecure)
+        .and(
+            start_task
+                .or(get_tasks)
+                .or(stop_task)
+                .or(get_services)
+                .or(get_service)
+                .or(get_namespaces)
+                .or(get_namespace)
+                .or(set_quota)
+                .or(get_events),
+        )
+        .recover(security::handle_rejection)
+}
+
+/// Serve the manager API, over HTTPS when TLS is configured. Clients
+/// authenticate with a bearer token rather than a certificate.
+pub async fn serve(
+    manager: SharedManager,
+    addr: SocketAddr,
+    config: SecurityConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let tokens = config.load_tokens()?;
+    if tokens.is_empty() {
+        if !config.insecure {
+            return Err("No API tokens configured, pass --tokens-file or --insecure".into());
+        }
+        log::warn!("No API tokens configured, the manager API is open to anyone");
+    }
+    log::info!("Manager API listening on {}", addr);
+    let routes = routes(manager, Arc::new(tokens), config.insecure);
+    security::serve(routes, addr, &config, false).await
+}
+
+/// DNS-like service lookup: every UDP datagram carrying a service name,
+/// `name.namespace` or `name` in the default namespace, is answered with
+/// that service's endpoints, one `ip:port` per line. Unknown services get
+/// an empty answer.
+pub async fn serve_lookup(
+    manager: SharedManager,
+    addr: SocketAddr,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let socket = UdpSocket::bind(addr).await?;
+    log::info!("Service lookup listening on {}", addr);
+    let mut buf = [0u8; 512];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        let name = String::from_utf8_lossy(&buf[..len]).trim().to_string();
+        let endpoints = manager.read().await.registry.lookup(&name);
+        socket
+            .send_to(endpoints.join("\n").as_bytes(), peer)
+            .await?;
+    }
+}
@@ -1,4 +1,17 @@
-#[derive(Debug, Clone)]
+use serde::Serialize;
+
+use crate::events::NodeStatus;
+
+/// Millicores in a core. CPU is allocated in millicores so that placing and
+/// removing tasks always adds back up to the same number.
+pub const MILLICORES: u64 = 1000;
+
+/// Millicores used by a task asking for `cpu` cores
+pub fn millicores(cpu: Option<f64>) -> u64 {
+    (cpu.unwrap_or_default().max(0.0) * MILLICORES as f64).round() as u64
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Node {
     pub name: String,
     pub ip: String,
@@ -6,7 +19,34 @@ pub struct Node {
     pub memory_allocated: u64,
     pub disk: u64,
     pub cores: u32,
+    /// Millicores allocated to the tasks placed on the node
+    pub cpu_allocated: u64,
     pub disk_allocated: u64,
     pub task_count: u32,
     pub role: String,
+    pub status: NodeStatus,
+}
+
+impl Node {
+    #[allow(dead_code)]
+    pub fn new(name: &str, ip: &str, role: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ip: ip.to_string(),
+            memory: 0,
+            memory_allocated: 0,
+            disk: 0,
+            cores: 0,
+            cpu_allocated: 0,
+            disk_allocated: 0,
+            task_count: 0,
+            role: role.to_string(),
+            status: NodeStatus::default(),
+        }
+    }
+
+    /// Millicores of all of the node's cores
+    pub fn cpu(&self) -> u64 {
+        u64::from(self.cores) * MILLICORES
+    }
 }
@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bollard::errors::Error;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::sync::{mpsc, RwLock};
+use tokio::time::{sleep, Duration, Instant};
+
+#[cfg(target_os = "linux")]
+use crate::isolation::{self, Cgroup, ChildSetup, EnterSetup, CGROUP_ROOT};
+use crate::task::{
+    Config, DockerResult, ExecOutput, ExecSession, ExitStatus, DEFAULT_STOP_SIGNAL,
+    DEFAULT_STOP_TIMEOUT,
+};
+
+/// A process started by the client
+#[derive(Debug, Default)]
+struct Process {
+    pid: u32,
+    /// cgroup holding the process and everything it forks
+    #[cfg(target_os = "linux")]
+    cgroup: Option<Arc<Cgroup>>,
+    /// The pid is the one of the process waiting for the task to exit,
+    /// the task itself runs in a new PID namespace
+    pid_namespace: bool,
+    /// Set once the process has been reaped
+    exit: Option<ExitStatus>,
+}
+
+/// Runs a task's command as a plain host process instead of a container.
+/// A random id takes the place of the container id: pids get reused, the
+/// exit status of a process must not pass on to the next one holding its
+/// pid. On Linux the process runs
+/// in its own cgroup enforcing the memory and CPU limits, and in new
+/// namespaces when the task asks for them.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessClient {
+    processes: Arc<RwLock<HashMap<String, Process>>>,
+}
+
+impl ProcessClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn run(
+        &self,
+        config: &Config<String>,
+    ) -> Result<DockerResult<String>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let cmd = match &config.cmd {
+            Some(cmd) if !cmd.is_empty() => cmd,
+            _ => {
+                return Ok(DockerResult::new(
+                    Some(Error::DockerResponseServerError {
+                        status_code: 422,
+                        message: "Missing command".to_string(),
+                    }),
+                    "start".to_string(),
+                    None,
+                    Some("failed".to_string()),
+                ))
+            }
+        };
+
+        let mut command = Command::new(&cmd[0]);
+        command.args(&cmd[1..]).stdin(Stdio::null());
+        for (key, value) in config
+            .env
+            .iter()
+            .flatten()
+            .filter_map(|e| e.split_once('='))
+        {
+            command.env(key, value);
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        #[cfg(target_os = "linux")]
+        let cgroup = isolate(&mut command, config, &id)?.map(Arc::new);
+
+        // Hold the lock until the process is tracked so the reaper cannot
+        // miss it
+        let mut processes = self.processes.write().await;
+        let mut child = command.spawn()?;
+        let pid = child.id().ok_or("Process exited before it started")?;
+        processes.insert(
+            id.clone(),
+            Process {
+                pid,
+                #[cfg(target_os = "linux")]
+                cgroup: cgroup.clone(),
+                pid_namespace: config.isolation.pid_namespace,
+                exit: None,
+            },
+        );
+        drop(processes);
+
+        // Reap the child in the background so it never lingers as a zombie
+        let processes = self.processes.clone();
+        let process_id = id.clone();
+        tokio::spawn(async move {
+            let code = match child.wait().await {
+                // Same convention as the shells for processes killed by a signal
+                Ok(status) => status
+                    .code()
+                    .or_else(|| status.signal().map(|s| 128 + s))
+                    .map(i64::from)
+                    .unwrap_or(-1),
+                Err(error) => {
+                    log::error!("Error waiting for process {}: {:#?}", pid, error);
+                    -1
+                }
+            };
+            #[cfg(target_os = "linux")]
+            let exit = match &cgroup {
+                Some(cgroup) => {
+                    let exit = ExitStatus::new(code, cgroup.oom_killed());
+                    cleanup(cgroup).await;
+                    exit
+                }
+                None => ExitStatus::new(code, false),
+            };
+            #[cfg(not(target_os = "linux"))]
+            let exit = ExitStatus::new(code, false);
+            if let Some(p) = processes.write().await.get_mut(&process_id) {
+                p.exit = Some(exit);
+            }
+        });
+
+        Ok(DockerResult::new(
+            None,
+            "start".to_string(),
+            Some(id),
+            Some("success".to_string()),
+        ))
+    }
+
+    /// Block until the process exits and return how it exited
+    pub async fn wait(
+        &self,
+        id: &str,
+    ) -> Result<ExitStatus, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        loop {
+            match self.processes.read().await.get(id) {
+                Some(Process {
+                    exit: Some(exit), ..
+                }) => return Ok(exit.clone()),
+                Some(_) => {}
+                None => return Err(format!("Unknown process {}", id).into()),
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    async fn exited(&self, id: &str) -> bool {
+        !matches!(
+            self.processes.read().await.get(id),
+            Some(Process { exit: None, .. })
+        )
+    }
+
+    /// Send the signal to the task, that is to every process of its cgroup
+    /// when it has one. A process gone in the meantime counts as signalled.
+    async fn kill(&self, id: &str, signal: Signal) -> Result<(), nix::Error> {
+        let processes = self.processes.read().await;
+        let Some(p) = processes.get(id) else {
+            return Ok(());
+        };
+        // Reaped already, the pid may belong to another process by now
+        if p.exit.is_some() {
+            return Ok(());
+        }
+        let pid = p.pid;
+        #[cfg(target_os = "linux")]
+        if let Some(cgroup) = &p.cgroup {
+            // The process waiting on a PID namespace does not forward
+            // signals, it exits along with the task
+            let except =
+                (p.pid_namespace && signal != Signal::SIGKILL).then(|| Pid::from_raw(pid as i32));
+            cgroup.kill(signal, except);
+            return Ok(());
+        }
+        match signal::kill(Pid::from_raw(pid as i32), signal) {
+            // Exited between the check and the signal, not reaped yet
+            Err(nix::Error::ESRCH) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Send the configured stop signal and escalate to `SIGKILL` when the
+    /// process is still around once the grace period is over
+    pub async fn stop(
+        &self,
+        id: &str,
+        config: &Config<String>,
+    ) -> Result<DockerResult<String>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let signal = parse_signal(config.stop_signal.as_deref().unwrap_or(DEFAULT_STOP_SIGNAL))?;
+        let timeout = Duration::from_secs(config.stop_timeout.unwrap_or(DEFAULT_STOP_TIMEOUT));
+
+        if !self.exited(id).await {
+            self.kill(id, signal).await?;
+            let deadline = Instant::now() + timeout;
+            while !self.exited(id).await {
+                if Instant::now() >= deadline {
+                    log::info!("Process {} ignored {}, sending SIGKILL", id, signal);
+                    self.kill(id, Signal::SIGKILL).await?;
+                    self.wait(id).await?;
+                    break;
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        }
+
+        Ok(DockerResult::new(
+            None,
+            "stop".to_string(),
+            Some(id.to_string()),
+            Some("success".to_string()),
+        ))
+    }
+
+    /// Run a command in the same cgroup, namespaces and root directory as
+    /// the process
+    pub async fn exec(
+        &self,
+        id: &str,
+        cmd: &[String],
+    ) -> Result<ExecSession, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let mut command = Command::new(&cmd[0]);
+        command
+            .args(&cmd[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        #[cfg(target_os = "linux")]
+        {
+            let processes = self.processes.read().await;
+            let p = processes
+                .get(id)
+                .filter(|p| p.exit.is_none())
+                .ok_or_else(|| format!("Process {} is not running", id))?;
+            let target = if p.pid_namespace {
+                isolation::first_child(p.pid)?
+            } else {
+                p.pid
+            };
+            let procs = match &p.cgroup {
+                Some(cgroup) => Some(cgroup.procs_file()?),
+                None => None,
+            };
+            let setup = EnterSetup::new(target, procs)?;
+            // Safety: `EnterSetup::run` only makes async-signal-safe calls
+            unsafe {
+                command.pre_exec(move || setup.run());
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        if !matches!(
+            self.processes.read().await.get(id),
+            Some(Process { exit: None, .. })
+        ) {
+            return Err(format!("Process {} is not running", id).into());
+        }
+
+        spawn_session(command)
+    }
+}
+
+/// Start the command, streaming its stdin and output through the session
+fn spawn_session(
+    mut command: Command,
+) -> Result<ExecSession, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let mut child = command.spawn()?;
+    let mut stdin = child.stdin.take().ok_or("Missing stdin")?;
+    let stdout = child.stdout.take().ok_or("Missing stdout")?;
+    let stderr = child.stderr.take().ok_or("Missing stderr")?;
+
+    let (stdin_tx, mut stdin_rx) = mpsc::channel::<Vec<u8>>(16);
+    tokio::spawn(async move {
+        while let Some(data) = stdin_rx.recv().await {
+            if stdin.write_all(&data).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let (output_tx, output_rx) = mpsc::channel(16);
+    let stdout = tokio::spawn(forward(stdout, output_tx.clone(), ExecOutput::Stdout));
+    let stderr = tokio::spawn(forward(stderr, output_tx.clone(), ExecOutput::Stderr));
+    tokio::spawn(async move {
+        let code = match child.wait().await {
+            Ok(status) => status
+                .code()
+                .or_else(|| status.signal().map(|s| 128 + s))
+                .map(i64::from)
+                .unwrap_or(-1),
+            Err(error) => {
+                log::error!("Error waiting for exec: {:#?}", error);
+                -1
+            }
+        };
+        // Everything the command wrote goes out before its exit code
+        let _ = tokio::join!(stdout, stderr);
+        let _ = output_tx.send(ExecOutput::Exit(code)).await;
+    });
+
+    Ok(ExecSession {
+        stdin: stdin_tx,
+        output: output_rx,
+    })
+}
+
+async fn forward<R: AsyncRead + Unpin>(
+    mut reader: R,
+    tx: mpsc::Sender<ExecOutput>,
+    wrap: fn(Vec<u8>) -> ExecOutput,
+) {
+    let mut buf = vec![0u8; 8192];
+    loop {
+        match reader.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(len) => {
+                if tx.send(wrap(buf[..len].to_vec())).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Accept both `SIGTERM` and `TERM` spellings
+fn parse_signal(name: &str) -> Result<Signal, nix::Error> {
+    let name = name.to_uppercase();
+    if name.starts_with("SIG") {
+        Signal::from_str(&name)
+    } else {
+        Signal::from_str(&format!("SIG{}", name))
+    }
+}
+
+/// Put the command in a new cgroup and set up the namespaces the task asks
+/// for. Failing to create the cgroup is only an error when there are
+/// limits to enforce.
+#[cfg(target_os = "linux")]
+fn isolate(
+    command: &mut Command,
+    config: &Config<String>,
+    name: &str,
+) -> Result<Option<Cgroup>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let root = std::path::Path::new(CGROUP_ROOT);
+    let cgroup = match Cgroup::create(root, name, config.memory, config.cpu) {
+        Ok(cgroup) => Some(cgroup),
+        Err(error) if config.memory.is_some() || config.cpu.is_some() => {
+            return Err(format!("Cannot enforce the resource limits: {}", error).into())
+        }
+        Err(error) => {
+            log::info!("Running {} without a cgroup: {}", config.name, error);
+            None
+        }
+    };
+    let procs = match &cgroup {
+        Some(cgroup) => Some(cgroup.procs_file()?),
+        None => None,
+    };
+    let setup = ChildSetup::new(procs, &config.isolation)?;
+    // Safety: `ChildSetup::run` only makes async-signal-safe calls
+    unsafe {
+        command.pre_exec(move || setup.run());
+    }
+    Ok(cgroup)
+}
+
+/// Kill whatever the task left behind and remove its cgroup
+#[cfg(target_os = "linux")]
+async fn cleanup(cgroup: &Cgroup) {
+    for _ in 0..50 {
+        if cgroup.pids().is_empty() {
+            break;
+        }
+        cgroup.kill(Signal::SIGKILL, None);
+        sleep(Duration::from_millis(100)).await;
+    }
+    if let Err(error) = cgroup.remove() {
+        log::error!("Error removing cgroup {:#?}: {}", cgroup, error);
+    }
+}
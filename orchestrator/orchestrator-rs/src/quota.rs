@@ -0,0 +1,255 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::node::{millicores, MILLICORES};
+use crate::task::{State, Task};
+
+/// Limits on what the tasks of a namespace may ask for altogether. Missing
+/// limits are unbounded.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Quota {
+    /// Memory in bytes
+    pub memory: Option<u64>,
+    pub disk: Option<u64>,
+    /// Number of CPUs
+    pub cpu: Option<f64>,
+    pub tasks: Option<usize>,
+}
+
+/// What the tasks of a namespace which have not finished ask for
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub memory: u64,
+    pub disk: u64,
+    /// Millicores, counted like the allocations of the nodes
+    pub cpu: u64,
+    pub tasks: usize,
+}
+
+impl Usage {
+    /// Account for the task, unless it has already finished. Sums too large
+    /// to count stay at the maximum, over any quota.
+    pub fn add(&mut self, t: &Task<String>) {
+        if t.state == State::Completed || t.state == State::Failed {
+            return;
+        }
+        self.memory = self.memory.saturating_add(t.memory.unwrap_or_default());
+        self.disk = self.disk.saturating_add(t.disk.unwrap_or_default());
+        self.cpu = self.cpu.saturating_add(millicores(t.cpu));
+        self.tasks = self.tasks.saturating_add(1);
+    }
+}
+
+/// Whether asking for `requested` on top of `used` goes over the limit. A
+/// total too large to count is over any limit.
+fn over(used: u64, requested: u64, limit: u64) -> bool {
+    match used.checked_add(requested) {
+        Some(total) => total > limit,
+        None => true,
+    }
+}
+
+/// Quota and usage of a namespace, as reported by the API
+#[derive(Debug, Clone, Serialize)]
+pub struct NamespaceUsage {
+    pub namespace: String,
+    pub quota: Quota,
+    pub usage: Usage,
+}
+
+/// A task asked for more than what is left of its namespace's quota
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaExceeded {
+    pub namespace: String,
+    pub resource: &'static str,
+    pub requested: String,
+    pub available: String,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Quota of namespace {} exceeded: {} requested, {} {} available",
+            self.namespace, self.requested, self.available, self.resource
+        )
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Cores in `millicores`, for messages
+fn cores(millicores: u64) -> f64 {
+    millicores as f64 / MILLICORES as f64
+}
+
+impl Quota {
+    /// Check the task fits in what the namespace has left given its
+    /// current usage
+    pub fn check(
+        &self,
+        namespace: &str,
+        usage: &Usage,
+        t: &Task<String>,
+    ) -> Result<(), QuotaExceeded> {
+        let exceeded = |resource, requested: String, available: String| QuotaExceeded {
+            namespace: namespace.to_string(),
+            resource,
+            requested,
+            available,
+        };
+        if let Some(limit) = self.tasks {
+            if over(usage.tasks as u64, 1, limit as u64) {
+                return Err(exceeded(
+                    "tasks",
+                    "1 task".to_string(),
+                    limit.saturating_sub(usage.tasks).to_string(),
+                ));
+            }
+        }
+        let memory = t.memory.unwrap_or_default();
+        if let Some(limit) = self.memory {
+            if over(usage.memory, memory, limit) {
+                return Err(exceeded(
+                    "memory bytes",
+                    format!("{} memory bytes", memory),
+                    limit.saturating_sub(usage.memory).to_string(),
+                ));
+            }
+        }
+        let disk = t.disk.unwrap_or_default();
+        if let Some(limit) = self.disk {
+            if over(usage.disk, disk, limit) {
+                return Err(exceeded(
+                    "disk",
+                    format!("{} disk", disk),
+                    limit.saturating_sub(usage.disk).to_string(),
+                ));
+            }
+        }
+        let cpu = millicores(t.cpu);
+        if let Some(limit) = self.cpu {
+            let limit = millicores(Some(limit));
+            if over(usage.cpu, cpu, limit) {
+                return Err(exceeded(
+                    "CPUs",
+                    format!("{} CPUs", cores(cpu)),
+                    cores(limit.saturating_sub(usage.cpu)).to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(memory: u64, disk: u64, cpu: f64) -> Task<String> {
+        Task {
+            memory: Some(memory),
+            disk: Some(disk),
+            cpu: Some(cpu),
+            ..Default::default()
+        }
+    }
+
+    fn quota() -> Quota {
+        Quota {
+            memory: Some(100),
+            disk: Some(10),
+            cpu: Some(2.0),
+            tasks: Some(3),
+        }
+    }
+
+    #[test]
+    fn check_accepts_what_is_left() {
+        let usage = Usage {
+            memory: 60,
+            disk: 5,
+            cpu: 1500,
+            tasks: 2,
+        };
+        assert_eq!(quota().check("ns", &usage, &task(40, 5, 0.5)), Ok(()));
+        assert_eq!(
+            Quota::default().check("ns", &usage, &task(u64::MAX, u64::MAX, 64.0)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn check_reports_the_exceeded_resource() {
+        let usage = Usage {
+            memory: 60,
+            disk: 5,
+            cpu: 1500,
+            tasks: 2,
+        };
+        let resource = |t| quota().check("ns", &usage, &t).unwrap_err().resource;
+        assert_eq!(resource(task(41, 0, 0.0)), "memory bytes");
+        assert_eq!(resource(task(0, 6, 0.0)), "disk");
+        assert_eq!(resource(task(0, 0, 0.6)), "CPUs");
+
+        let full = Usage { tasks: 3, ..usage };
+        let error = quota().check("ns", &full, &task(0, 0, 0.0)).unwrap_err();
+        assert_eq!(
+            error,
+            QuotaExceeded {
+                namespace: "ns".to_string(),
+                resource: "tasks",
+                requested: "1 task".to_string(),
+                available: "0".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn check_treats_overflow_as_exceeded() {
+        let quota = Quota {
+            memory: Some(u64::MAX),
+            disk: Some(u64::MAX),
+            ..Default::default()
+        };
+        let usage = Usage {
+            memory: u64::MAX - 1,
+            disk: 1,
+            ..Default::default()
+        };
+        let error = quota.check("ns", &usage, &task(2, 0, 0.0)).unwrap_err();
+        assert_eq!(error.resource, "memory bytes");
+        let error = quota
+            .check("ns", &usage, &task(0, u64::MAX, 0.0))
+            .unwrap_err();
+        assert_eq!(error.resource, "disk");
+    }
+
+    #[test]
+    fn usage_saturates_and_skips_finished_tasks() {
+        let mut usage = Usage::default();
+        usage.add(&task(u64::MAX, 1, 1.0));
+        usage.add(&task(1, 1, 1.0));
+        let mut done = task(1, 1, 1.0);
+        done.state = State::Completed;
+        usage.add(&done);
+        assert_eq!(usage.memory, u64::MAX);
+        assert_eq!((usage.disk, usage.cpu, usage.tasks), (2, 2000, 2));
+    }
+
+    #[test]
+    fn cpu_filling_the_quota_exactly_fits() {
+        let quota = Quota {
+            cpu: Some(0.3),
+            ..Default::default()
+        };
+        let mut usage = Usage::default();
+        usage.add(&task(0, 0, 0.1));
+        assert_eq!(quota.check("ns", &usage, &task(0, 0, 0.2)), Ok(()));
+        usage.add(&task(0, 0, 0.2));
+        let error = quota.check("ns", &usage, &task(0, 0, 0.001)).unwrap_err();
+        assert_eq!((error.resource, error.available.as_str()), ("CPUs", "0"));
+    }
+}
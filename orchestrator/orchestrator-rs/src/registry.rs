@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::node::Node;
+use crate::task::{Task, DEFAULT_NAMESPACE};
+
+/// Where one of the tasks backing a service can be reached
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Endpoint {
+    pub task_id: Uuid,
+    /// `node.ip:host_port`
+    pub address: String,
+}
+
+/// Service to endpoints map kept up to date by the manager as tasks start
+/// and stop. A service is a task name within a namespace, so every replica
+/// of a task adds its endpoints under the same service, and namespaces
+/// using the same task name do not share it. Services are addressed as
+/// `name.namespace`, or `name` alone in the default namespace.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    services: HashMap<(String, String), Vec<Endpoint>>,
+}
+
+/// Namespace and name of the service addressed as `service`
+fn key(service: &str) -> (String, String) {
+    match service.rsplit_once('.') {
+        Some((name, namespace)) => (namespace.to_string(), name.to_string()),
+        None => (DEFAULT_NAMESPACE.to_string(), service.to_string()),
+    }
+}
+
+impl Registry {
+    /// Add one endpoint per host port bound by the task running on `node`
+    pub fn register(&mut self, t: &Task<String>, node: &Node) {
+        self.deregister(&t.id);
+        let endpoints: Vec<Endpoint> = t
+            .port_bindings
+            .iter()
+            .flatten()
+            .map(|(_, host_port)| Endpoint {
+                task_id: t.id,
+                address: format!("{}:{}", node.ip, host_port),
+            })
+            .collect();
+        if !endpoints.is_empty() {
+            self.services
+                .entry((t.namespace.clone(), t.name.clone()))
+                .or_default()
+                .extend(endpoints);
+        }
+    }
+
+    pub fn deregister(&mut self, task_id: &Uuid) {
+        for endpoints in self.services.values_mut() {
+            endpoints.retain(|e| e.task_id != *task_id);
+        }
+        self.services.retain(|_, endpoints| !endpoints.is_empty());
+    }
+
+    pub fn lookup(&self, service: &str) -> Vec<String> {
+        self.services
+            .get(&key(service))
+            .map(|endpoints| endpoints.iter().map(|e| e.address.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Endpoints of every service, by `name.namespace`
+    pub fn services(&self) -> HashMap<String, Vec<String>> {
+        self.services
+            .iter()
+            .map(|((namespace, name), endpoints)| {
+                (
+                    format!("{}.{}", name, namespace),
+                    endpoints.iter().map(|e| e.address.clone()).collect(),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(namespace: &str, name: &str, host_port: &str) -> Task<String> {
+        Task {
+            id: Uuid::new_v4(),
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            port_bindings: Some(HashMap::from([(
+                "80/tcp".to_string(),
+                host_port.to_string(),
+            )])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn namespaces_do_not_share_services() {
+        let node = Node::new("w1", "10.0.0.1", "worker");
+        let mut registry = Registry::default();
+        registry.register(&task(DEFAULT_NAMESPACE, "web", "8001"), &node);
+        registry.register(&task("team-a", "web", "8002"), &node);
+
+        assert_eq!(registry.lookup("web"), vec!["10.0.0.1:8001"]);
+        assert_eq!(registry.lookup("web.default"), vec!["10.0.0.1:8001"]);
+        assert_eq!(registry.lookup("web.team-a"), vec!["10.0.0.1:8002"]);
+        assert!(registry.lookup("web.team-b").is_empty());
+        assert_eq!(
+            registry.services(),
+            HashMap::from([
+                ("web.default".to_string(), vec!["10.0.0.1:8001".to_string()]),
+                ("web.team-a".to_string(), vec!["10.0.0.1:8002".to_string()]),
+            ])
+        );
+    }
+
+    #[test]
+    fn register_replaces_the_old_endpoints_of_the_task() {
+        let mut registry = Registry::default();
+        let mut t = task(DEFAULT_NAMESPACE, "web", "8001");
+        registry.register(&t, &Node::new("w1", "10.0.0.1", "worker"));
+        t.port_bindings = Some(HashMap::from([("80/tcp".to_string(), "9001".to_string())]));
+        registry.register(&t, &Node::new("w2", "10.0.0.2", "worker"));
+
+        assert_eq!(registry.lookup("web"), vec!["10.0.0.2:9001"]);
+    }
+
+    #[test]
+    fn deregister_drops_services_left_without_endpoints() {
+        let node = Node::new("w1", "10.0.0.1", "worker");
+        let mut registry = Registry::default();
+        let first = task(DEFAULT_NAMESPACE, "web", "8001");
+        let second = task(DEFAULT_NAMESPACE, "web", "8002");
+        registry.register(&first, &node);
+        registry.register(&second, &node);
+
+        registry.deregister(&first.id);
+        assert_eq!(registry.lookup("web"), vec!["10.0.0.1:8002"]);
+        registry.deregister(&second.id);
+        assert!(registry.services().is_empty());
+    }
+
+    #[test]
+    fn lookup_of_an_unknown_service_is_empty() {
+        let registry = Registry::default();
+        assert!(registry.lookup("web").is_empty());
+        assert!(registry.lookup("web.team-a").is_empty());
+    }
+}
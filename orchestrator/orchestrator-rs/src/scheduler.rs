@@ -0,0 +1,201 @@
+use crate::node::{millicores, Node};
+use crate::task::Task;
+
+/// Picks the node a task is placed on
+pub trait Scheduler: std::fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Index of the node the task goes to, `None` when it fits nowhere
+    fn select(&mut self, t: &Task<String>, nodes: &[Node]) -> Option<usize>;
+}
+
+/// Whether the node has enough unallocated memory, disk and CPU for the
+/// task. Resources the node does not report, left at 0, are not checked.
+pub fn fits(t: &Task<String>, node: &Node) -> bool {
+    (node.memory == 0
+        || node.memory.saturating_sub(node.memory_allocated) >= t.memory.unwrap_or_default())
+        && (node.disk == 0
+            || node.disk.saturating_sub(node.disk_allocated) >= t.disk.unwrap_or_default())
+        && (node.cores == 0 || node.cpu().saturating_sub(node.cpu_allocated) >= millicores(t.cpu))
+}
+
+/// Memory left on the node once the task is placed on it
+fn memory_left(t: &Task<String>, node: &Node) -> u64 {
+    node.memory
+        .saturating_sub(node.memory_allocated)
+        .saturating_sub(t.memory.unwrap_or_default())
+}
+
+/// Next node the task fits on, going round the nodes
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    last: usize,
+}
+
+impl Scheduler for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round-robin"
+    }
+
+    fn select(&mut self, t: &Task<String>, nodes: &[Node]) -> Option<usize> {
+        let idx = (1..=nodes.len())
+            .map(|i| (self.last + i) % nodes.len())
+            .find(|&idx| fits(t, &nodes[idx]))?;
+        self.last = idx;
+        Some(idx)
+    }
+}
+
+/// First node the task fits on
+#[derive(Debug, Default)]
+pub struct FirstFit;
+
+impl Scheduler for FirstFit {
+    fn name(&self) -> &'static str {
+        "first-fit"
+    }
+
+    fn select(&mut self, t: &Task<String>, nodes: &[Node]) -> Option<usize> {
+        nodes.iter().position(|node| fits(t, node))
+    }
+}
+
+/// Node left with the least free memory, packing tasks together
+#[derive(Debug, Default)]
+pub struct BestFit;
+
+impl Scheduler for BestFit {
+    fn name(&self) -> &'static str {
+        "best-fit"
+    }
+
+    fn select(&mut self, t: &Task<String>, nodes: &[Node]) -> Option<usize> {
+        (0..nodes.len())
+            .filter(|&idx| fits(t, &nodes[idx]))
+            .min_by_key(|&idx| memory_left(t, &nodes[idx]))
+    }
+}
+
+/// Node left with the most free memory, spreading tasks out
+#[derive(Debug, Default)]
+pub struct WorstFit;
+
+impl Scheduler for WorstFit {
+    fn name(&self) -> &'static str {
+        "worst-fit"
+    }
+
+    fn select(&mut self, t: &Task<String>, nodes: &[Node]) -> Option<usize> {
+        (0..nodes.len())
+            .filter(|&idx| fits(t, &nodes[idx]))
+            .max_by_key(|&idx| memory_left(t, &nodes[idx]))
+    }
+}
+
+/// Scheduler by name, as given on the command line
+#[allow(dead_code)]
+pub fn by_name(name: &str) -> Option<Box<dyn Scheduler>> {
+    match name {
+        "round-robin" => Some(Box::<RoundRobin>::default()),
+        "first-fit" => Some(Box::new(FirstFit)),
+        "best-fit" => Some(Box::new(BestFit)),
+        "worst-fit" => Some(Box::new(WorstFit)),
+        _ => None,
+    }
+}
+
+/// Names of every scheduler `by_name` knows
+#[allow(dead_code)]
+pub const SCHEDULERS: [&str; 4] = ["round-robin", "first-fit", "best-fit", "worst-fit"];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(memory: u64, memory_allocated: u64) -> Node {
+        let mut node = Node::new("node", "", "worker");
+        node.memory = memory;
+        node.memory_allocated = memory_allocated;
+        node.disk = 100;
+        node.cores = 2;
+        node
+    }
+
+    fn task(memory: u64, cpu: f64) -> Task<String> {
+        Task {
+            memory: Some(memory),
+            disk: Some(1),
+            cpu: Some(cpu),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fits_checks_every_resource() {
+        let n = node(100, 60);
+        assert!(fits(&task(40, 2.0), &n));
+        assert!(!fits(&task(41, 1.0), &n));
+        assert!(!fits(&task(10, 2.1), &n));
+
+        let mut full = node(100, 0);
+        full.disk_allocated = 100;
+        assert!(!fits(&task(10, 1.0), &full));
+    }
+
+    #[test]
+    fn fits_skips_unreported_resources() {
+        let n = Node::new("node", "", "worker");
+        assert!(fits(&task(1 << 40, 64.0), &n));
+    }
+
+    #[test]
+    fn cpu_adds_up_exactly() {
+        let mut n = node(100, 0);
+        for _ in 0..10 {
+            n.cpu_allocated += millicores(Some(0.1));
+        }
+        for _ in 0..10 {
+            n.cpu_allocated -= millicores(Some(0.1));
+        }
+        assert_eq!(n.cpu_allocated, 0);
+        n.cpu_allocated = millicores(Some(1.9));
+        assert!(fits(&task(10, 0.1), &n));
+        assert!(!fits(&task(10, 0.2), &n));
+    }
+
+    #[test]
+    fn round_robin_goes_round_the_nodes() {
+        let nodes = vec![node(100, 0), node(100, 0), node(100, 0)];
+        let mut s = RoundRobin::default();
+        let picks: Vec<_> = (0..4).map(|_| s.select(&task(10, 1.0), &nodes)).collect();
+        assert_eq!(picks, vec![Some(1), Some(2), Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn round_robin_skips_full_nodes() {
+        let nodes = vec![node(100, 0), node(100, 100), node(100, 0)];
+        let mut s = RoundRobin::default();
+        assert_eq!(s.select(&task(10, 1.0), &nodes), Some(2));
+        assert_eq!(s.select(&task(10, 1.0), &nodes), Some(0));
+    }
+
+    #[test]
+    fn fit_schedulers_pick_by_free_memory() {
+        let nodes = vec![node(100, 50), node(100, 80), node(100, 0), node(100, 95)];
+        let t = task(10, 1.0);
+        assert_eq!(FirstFit.select(&t, &nodes), Some(0));
+        assert_eq!(BestFit.select(&t, &nodes), Some(1));
+        assert_eq!(WorstFit.select(&t, &nodes), Some(2));
+    }
+
+    #[test]
+    fn schedulers_return_none_when_nothing_fits() {
+        let nodes = vec![node(100, 95), node(100, 99)];
+        let t = task(10, 1.0);
+        for name in SCHEDULERS {
+            let mut s = by_name(name).unwrap();
+            assert_eq!(s.select(&t, &nodes), None, "{}", name);
+            assert_eq!(s.select(&t, &[]), None, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Args;
+use warp::{http::StatusCode, reject::Reject, Filter, Rejection, Reply};
+
+/// TLS material and client tokens of the manager and worker APIs.
+///
+/// With a certificate and key the API is served over HTTPS. The CA is used
+/// for mutual TLS between manager and workers: workers only accept clients
+/// presenting a certificate signed by it and the manager presents its own
+/// certificate to them. Tokens, one per line in the tokens file, are the
+/// bearer tokens clients of the manager API must send.
+///
+/// The APIs refuse to start without TLS, and the manager API without
+/// tokens, unless `insecure` says otherwise.
+#[derive(Args, Debug, Clone, Default)]
+pub struct SecurityConfig {
+    /// PEM certificate of this API
+    #[clap(long, env = "ORCHESTRATOR_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of this API
+    #[clap(long, env = "ORCHESTRATOR_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+    /// PEM CA certificate used to verify manager and worker certificates
+    #[clap(long, env = "ORCHESTRATOR_TLS_CA")]
+    pub tls_ca: Option<PathBuf>,
+    /// File with the accepted bearer tokens, one per line
+    #[clap(long, env = "ORCHESTRATOR_TOKENS_FILE")]
+    pub tokens_file: Option<PathBuf>,
+    /// Serve plain HTTP and let in clients without a token or certificate
+    /// when they are not configured. For local development only.
+    #[clap(long, env = "ORCHESTRATOR_INSECURE")]
+    pub insecure: bool,
+}
+
+/// How long the manager waits for a worker to accept a connection
+const WORKER_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long the manager waits for a worker to answer a request
+const WORKER_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl SecurityConfig {
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_cert.is_some() && self.tls_key.is_some()
+    }
+
+    /// Whether clients of the worker API must present a certificate signed
+    /// by the CA
+    pub fn client_auth_enabled(&self) -> bool {
+        self.tls_enabled() && self.tls_ca.is_some()
+    }
+
+    pub fn load_tokens(&self) -> std::io::Result<Vec<String>> {
+        let Some(path) = &self.tokens_file else {
+            return Ok(Vec::new());
+        };
+        Ok(std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|t| !t.is_empty() && !t.starts_with('#'))
+            .map(String::from)
+            .collect())
+    }
+
+    /// HTTP client the manager uses to talk to workers: trusts only the
+    /// configured CA and authenticates with the manager's own certificate
+    pub fn worker_client(
+        &self,
+    ) -> Result<reqwest::Client, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        // A worker which stopped answering must not hold up the manager
+        let mut builder = reqwest::Client::builder()
+            .use_rustls_tls()
+            .connect_timeout(WORKER_CONNECT_TIMEOUT)
+            .timeout(WORKER_TIMEOUT);
+        if let Some(ca) = &self.tls_ca {
+            let ca = reqwest::Certificate::from_pem(&std::fs::read(ca)?)?;
+            builder = builder
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(ca);
+        }
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            let mut pem = std::fs::read(cert)?;
+            pem.extend(std::fs::read(key)?);
+            builder = builder.identity(reqwest::Identity::from_pem(&pem)?);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// TLS connector for clients of the worker API which are not HTTP
+    /// clients, set up like `worker_client`
+    pub fn tls_connector(
+        &self,
+    ) -> Result<tokio_rustls::TlsConnector, Box<dyn std::error::Error + Send + Sync + 'static>>
+    {
+        use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+        let mut roots = RootCertStore::empty();
+        if let Some(ca) = &self.tls_ca {
+            let mut reader = std::io::BufReader::new(std::fs::File::open(ca)?);
+            for cert in rustls_pemfile::certs(&mut reader) {
+                roots.add(cert?)?;
+            }
+        }
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let config = match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => {
+                let mut reader = std::io::BufReader::new(std::fs::File::open(cert)?);
+                let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+                let mut reader = std::io::BufReader::new(std::fs::File::open(key)?);
+                let key =
+                    rustls_pemfile::private_key(&mut reader)?.ok_or("No private key found")?;
+                builder.with_client_auth_cert(certs, key)?
+            }
+            _ => builder.with_no_client_auth(),
+        };
+        Ok(tokio_rustls::TlsConnector::from(Arc::new(config)))
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        if self.tls_enabled() {
+            "https"
+        } else {
+            "http"
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+/// Require `Authorization: Bearer <token>` with one of the configured
+/// tokens. Without tokens configured requests are only let through when
+/// `insecure` is set.
+pub fn bearer_auth(
+    tokens: Arc<Vec<String>>,
+    insecure: bool,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let tokens = tokens.clone();
+            async move {
+                if tokens.is_empty() && insecure {
+                    return Ok(());
+                }
+                match header.as_deref().and_then(|h| h.strip_prefix("Bearer ")) {
+                    Some(token) if known_token(&tokens, token.trim()) => Ok(()),
+                    _ => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Compare the token with every configured one, in a time which does not
+/// depend on how much of it matches
+fn known_token(tokens: &[String], token: &str) -> bool {
+    tokens.iter().fold(false, |found, t| {
+        found | constant_time_eq(t.as_bytes(), token.as_bytes())
+    })
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+pub async fn handle_rejection(r: Rejection) -> Result<impl Reply, Rejection> {
+    if r.find::<Unauthorized>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&crate::manager_api::ErrResponse {
+                http_status_code: StatusCode::UNAUTHORIZED.as_u16(),
+                message: "Missing or invalid bearer token".to_string(),
+            }),
+            StatusCode::UNAUTHORIZED,
+        ))
+    } else {
+        Err(r)
+    }
+}
+
+/// Serve the routes over HTTPS, requiring a client certificate signed by
+/// the CA when `client_auth` is set. Without TLS configured, plain HTTP is
+/// only served when the config is `insecure`.
+pub async fn serve<F>(
+    routes: F,
+    addr: SocketAddr,
+    config: &SecurityConfig,
+    client_auth: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>>
+where
+    F: Filter<Error = Rejection> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let server = warp::serve(routes).tls().cert_path(cert).key_path(key);
+            match (&config.tls_ca, client_auth) {
+                (Some(ca), true) => server.client_auth_required_path(ca).run(addr).await,
+                (None, true) if !config.insecure => {
+                    return Err(
+                        "Client certificates required but no CA configured, pass --tls-ca".into(),
+                    )
+                }
+                _ => server.run(addr).await,
+            }
+        }
+        _ if config.insecure => {
+            log::warn!(
+                "No TLS certificate configured, serving plain HTTP on {}",
+                addr
+            );
+            warp::serve(routes).run(addr).await
+        }
+        _ => {
+            return Err(
+                "No TLS certificate configured, pass --tls-cert and --tls-key or --insecure".into(),
+            )
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(tokens: &[&str]) -> Arc<Vec<String>> {
+        Arc::new(tokens.iter().map(|t| t.to_string()).collect())
+    }
+
+    /// Whether the filter lets a request with the header through
+    async fn allowed(tokens: Arc<Vec<String>>, insecure: bool, header: Option<&str>) -> bool {
+        let mut request = warp::test::request();
+        if let Some(header) = header {
+            request = request.header("authorization", header);
+        }
+        request.filter(&bearer_auth(tokens, insecure)).await.is_ok()
+    }
+
+    #[tokio::test]
+    async fn bearer_auth_requires_a_known_token() {
+        let known = tokens(&["secret", "other"]);
+        assert!(allowed(known.clone(), false, Some("Bearer secret")).await);
+        assert!(allowed(known.clone(), false, Some("Bearer other")).await);
+        assert!(!allowed(known.clone(), false, Some("Bearer wrong")).await);
+        assert!(!allowed(known.clone(), false, Some("secret")).await);
+        assert!(!allowed(known.clone(), false, Some("Basic secret")).await);
+        assert!(!allowed(known.clone(), false, None).await);
+        // Tokens are checked as soon as there are some, insecure or not
+        assert!(!allowed(known, true, None).await);
+    }
+
+    #[tokio::test]
+    async fn bearer_auth_without_tokens_needs_insecure() {
+        assert!(allowed(tokens(&[]), true, None).await);
+        assert!(!allowed(tokens(&[]), false, None).await);
+        assert!(!allowed(tokens(&[]), false, Some("Bearer ")).await);
+    }
+
+    #[test]
+    fn load_tokens_skips_comments_and_blank_lines() {
+        let path = std::env::temp_dir().join(format!("tokens-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "# ops team\nfirst\n\n   \n  second  \n#old\n").unwrap();
+        let config = SecurityConfig {
+            tokens_file: Some(path.clone()),
+            ..Default::default()
+        };
+        let loaded = config.load_tokens();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), vec!["first", "second"]);
+        assert!(SecurityConfig::default().load_tokens().unwrap().is_empty());
+    }
+
+    #[test]
+    fn constant_time_eq_compares_lengths_and_bytes() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokex"));
+        assert!(!constant_time_eq(b"token", b"token2"));
+        assert!(!constant_time_eq(b"", b"t"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
@@ -2,16 +2,25 @@ use std::collections::HashMap;
 use std::hash::Hash;
 
 use bollard::{
-    container::Config as ContainerConfig, container::RemoveContainerOptions as RemoveOptions,
-    container::StopContainerOptions as StopOptions, errors::Error, image::CreateImageOptions,
+    container::Config as ContainerConfig,
+    container::LogOutput,
+    container::RemoveContainerOptions as RemoveOptions,
+    container::StopContainerOptions as StopOptions,
+    errors::Error,
+    exec::{CreateExecOptions, StartExecResults},
+    image::CreateImageOptions,
+    models::{HostConfig, PortBinding},
     Docker,
 };
-use futures_util::TryStreamExt;
+use futures_util::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
 
 use chrono::prelude::*;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Eq, PartialEq, Default, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Default, Hash, Serialize, Deserialize)]
 pub enum State {
     #[default]
     Pending,
@@ -21,6 +30,32 @@ pub enum State {
     Failed,
 }
 
+/// Whether a task runs until stopped or until its command exits
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Hash, Serialize, Deserialize)]
+pub enum Kind {
+    /// Long-running service, stopped on request
+    #[default]
+    Service,
+    /// Runs to completion: `Completed` on exit code 0, `Failed` otherwise
+    Batch,
+}
+
+/// Where a task runs
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Hash, Serialize, Deserialize)]
+pub enum Runtime {
+    #[default]
+    Docker,
+    /// Plain host process running the task's `cmd`
+    Process,
+}
+
+/// Signal sent to a task when it has to stop
+pub const DEFAULT_STOP_SIGNAL: &str = "SIGTERM";
+/// Seconds a task has to exit after the stop signal before being killed
+pub const DEFAULT_STOP_TIMEOUT: u64 = 15;
+/// Namespace of the tasks submitted without one
+pub const DEFAULT_NAMESPACE: &str = "default";
+
 #[allow(dead_code)]
 pub fn contains(src: &State, dst: &State) -> bool {
     let state_transition_map: HashMap<State, Vec<State>> = HashMap::from([
@@ -46,7 +81,41 @@ pub fn contains(src: &State, dst: &State) -> bool {
     }
 }
 
+/// Linux isolation of the tasks run by the process runtime
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Isolation {
+    /// Run the task as init of its own PID namespace
+    pub pid_namespace: bool,
+    /// Run the task in its own mount namespace
+    pub mount_namespace: bool,
+    /// Directory the task is chrooted into, needs `mount_namespace`
+    pub rootfs: Option<String>,
+}
+
+/// How a task's container or process exited
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExitStatus {
+    pub code: i64,
+    /// Why it did not exit cleanly, if known
+    pub reason: Option<String>,
+}
+
+impl ExitStatus {
+    pub fn new(code: i64, oom_killed: bool) -> Self {
+        let reason = if oom_killed {
+            Some("OOM killed".to_string())
+        } else if code != 0 {
+            Some(format!("Exited with code {}", code))
+        } else {
+            None
+        };
+        Self { code, reason }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, bound(deserialize = "T: Deserialize<'de> + Default"))]
 pub struct Task<T>
 where
     T: Into<String> + Eq + Hash,
@@ -54,19 +123,40 @@ where
     pub id: Uuid,
     pub container_id: Option<T>,
     pub name: T,
+    // Namespace quotas are accounted in, `DEFAULT_NAMESPACE` when empty
+    pub namespace: T,
     pub state: State,
     pub image: T,
+    pub kind: Kind,
+    pub runtime: Runtime,
+    pub cmd: Option<Vec<T>>,
+    // Ids of the tasks which must be `Completed` before this one is scheduled
+    pub depends_on: Vec<Uuid>,
+    // Higher priorities are scheduled first and may preempt lower ones
+    pub priority: i32,
+    // Number of CPUs, fractions allowed
+    pub cpu: Option<f64>,
+    // Memory limit in bytes
     pub memory: Option<u64>,
     pub disk: Option<u64>,
+    pub isolation: Isolation,
     // Not absolutely sure of the format of these, we'll see
     pub exposed_ports: Option<HashMap<T, HashMap<(), ()>>>,
     pub port_bindings: Option<HashMap<T, T>>,
     pub restart_policy: Option<T>,
+    pub stop_signal: Option<T>,
+    // Grace period in seconds between the stop signal and SIGKILL
+    pub stop_timeout: Option<u64>,
+    // Keep the stopped container around for inspection instead of removing it
+    pub keep_stopped: bool,
     pub start_time: Option<DateTime<Utc>>,
     pub finish_time: Option<DateTime<Utc>>,
+    // Why the task failed
+    pub reason: Option<T>,
 }
 
-#[derive(Debug, Clone, Default, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, bound(deserialize = "T: Deserialize<'de> + Default"))]
 pub struct TaskEvent<T>
 where
     T: Into<String> + Eq + Hash,
@@ -87,6 +177,8 @@ where
     pub attach_stdout: Option<bool>,
     pub attach_stderr: Option<bool>,
     pub exposed_ports: Option<HashMap<T, HashMap<(), ()>>>,
+    // Container port (`80/tcp`) to host port
+    pub port_bindings: Option<HashMap<T, T>>,
     pub cmd: Option<Vec<T>>,
     pub image: T,
     pub cpu: Option<f64>,
@@ -94,6 +186,10 @@ where
     pub disk: Option<u64>,
     pub env: Option<Vec<T>>,
     pub restart_policy: Option<T>,
+    pub stop_signal: Option<T>,
+    pub stop_timeout: Option<u64>,
+    pub keep_stopped: bool,
+    pub isolation: Isolation,
 }
 
 #[derive(Debug, Clone)]
@@ -129,6 +225,23 @@ impl DockerResult<String> {
     }
 }
 
+/// Output of a command run inside a task
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecOutput {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    /// Always the last output
+    Exit(i64),
+}
+
+/// A command running inside a task. Dropping `stdin` closes the command's
+/// stdin.
+#[derive(Debug)]
+pub struct ExecSession {
+    pub stdin: mpsc::Sender<Vec<u8>>,
+    pub output: mpsc::Receiver<ExecOutput>,
+}
+
 impl Config<String> {
     pub fn new(name: &str, image: &str, env: Option<Vec<String>>) -> Self {
         Self {
@@ -140,8 +253,31 @@ impl Config<String> {
     }
 }
 
+impl From<&Task<String>> for Config<String> {
+    fn from(t: &Task<String>) -> Self {
+        Self {
+            name: t.name.clone(),
+            image: t.image.clone(),
+            cmd: t.cmd.clone(),
+            exposed_ports: t.exposed_ports.clone(),
+            port_bindings: t.port_bindings.clone(),
+            cpu: t.cpu,
+            memory: t.memory,
+            disk: t.disk,
+            isolation: t.isolation.clone(),
+            restart_policy: t.restart_policy.clone(),
+            stop_signal: t.stop_signal.clone(),
+            stop_timeout: t.stop_timeout,
+            keep_stopped: t.keep_stopped,
+            ..Default::default()
+        }
+    }
+}
+
 impl DockerClient<String> {
-    pub fn new(config: Config<String>) -> Result<Self, Box<dyn std::error::Error + 'static>> {
+    pub fn new(
+        config: Config<String>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync + 'static>> {
         let docker = Docker::connect_with_socket_defaults()?;
         Ok(Self {
             client: docker,
@@ -150,7 +286,9 @@ impl DockerClient<String> {
         })
     }
 
-    pub async fn run(&self) -> Result<DockerResult<String>, Box<dyn std::error::Error + 'static>> {
+    pub async fn run(
+        &self,
+    ) -> Result<DockerResult<String>, Box<dyn std::error::Error + Send + Sync + 'static>> {
         let mut image = self.config.image.as_str();
         if image.is_empty() {
             image = "alpine:3"
@@ -168,13 +306,64 @@ impl DockerClient<String> {
             .try_collect::<Vec<_>>()
             .await?;
 
+        let cmd = self
+            .config
+            .cmd
+            .as_ref()
+            .map(|c| c.iter().map(String::as_str).collect::<Vec<_>>());
+        let env = self
+            .config
+            .env
+            .as_ref()
+            .map(|e| e.iter().map(String::as_str).collect::<Vec<_>>());
+
+        // Every bound port must be exposed as well
+        let mut exposed_ports: HashMap<&str, HashMap<(), ()>> = self
+            .config
+            .exposed_ports
+            .iter()
+            .flatten()
+            .map(|(port, _)| (port.as_str(), HashMap::new()))
+            .collect();
+        let port_bindings = self.config.port_bindings.as_ref().map(|bindings| {
+            bindings
+                .iter()
+                .map(|(container_port, host_port)| {
+                    exposed_ports.insert(container_port.as_str(), HashMap::new());
+                    (
+                        container_port.clone(),
+                        Some(vec![PortBinding {
+                            host_ip: None,
+                            host_port: Some(host_port.clone()),
+                        }]),
+                    )
+                })
+                .collect()
+        });
+
         let container_id = self
             .client
             .create_container::<&str, &str>(
                 None,
                 ContainerConfig {
-                    image: Some(&image),
+                    image: Some(image),
+                    cmd,
+                    env,
                     tty: Some(true),
+                    stop_signal: Some(
+                        self.config
+                            .stop_signal
+                            .as_deref()
+                            .unwrap_or(DEFAULT_STOP_SIGNAL),
+                    ),
+                    stop_timeout: Some(self.stop_timeout()),
+                    exposed_ports: Some(exposed_ports),
+                    host_config: Some(HostConfig {
+                        port_bindings,
+                        memory: self.config.memory.map(|m| m as i64),
+                        nano_cpus: self.config.cpu.map(|c| (c * 1e9) as i64),
+                        ..Default::default()
+                    }),
                     ..Default::default()
                 },
             )
@@ -193,22 +382,61 @@ impl DockerClient<String> {
         ))
     }
 
+    /// Block until the container exits and return how it exited
+    pub async fn wait(
+        &self,
+        container_id: &str,
+    ) -> Result<ExitStatus, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        // Non-zero exit codes may come back as an error, the exit code is
+        // read from the container state below anyway
+        if let Err(error) = self
+            .client
+            .wait_container::<String>(container_id, None)
+            .try_collect::<Vec<_>>()
+            .await
+        {
+            log::info!("Container {} exited: {}", container_id, error);
+        }
+        let state = self
+            .client
+            .inspect_container(container_id, None)
+            .await?
+            .state
+            .unwrap_or_default();
+        Ok(ExitStatus::new(
+            state.exit_code.unwrap_or_default(),
+            state.oom_killed.unwrap_or_default(),
+        ))
+    }
+
+    /// Stop the container with its configured signal, letting Docker send
+    /// SIGKILL once the grace period expires, and remove it unless the task
+    /// asked to keep it for inspection
     pub async fn stop(
         &self,
         container_id: &str,
-    ) -> Result<DockerResult<String>, Box<dyn std::error::Error + 'static>> {
-        self.client
-            .stop_container(container_id, Some(StopOptions { t: 15 }))
-            .await?;
+    ) -> Result<DockerResult<String>, Box<dyn std::error::Error + Send + Sync + 'static>> {
         self.client
-            .remove_container(
+            .stop_container(
                 container_id,
-                Some(RemoveOptions {
-                    force: true,
-                    ..Default::default()
+                Some(StopOptions {
+                    t: self.stop_timeout(),
                 }),
             )
             .await?;
+        if self.config.keep_stopped {
+            log::info!("Keeping stopped container {}", container_id);
+        } else {
+            self.client
+                .remove_container(
+                    container_id,
+                    Some(RemoveOptions {
+                        force: true,
+                        ..Default::default()
+                    }),
+                )
+                .await?;
+        }
         Ok(DockerResult::new(
             None,
             "stop".to_string(),
@@ -216,4 +444,74 @@ impl DockerClient<String> {
             Some("success".to_string()),
         ))
     }
+
+    /// Run a command inside the container, attached to its stdin, stdout
+    /// and stderr
+    pub async fn exec(
+        &self,
+        container_id: &str,
+        cmd: &[String],
+    ) -> Result<ExecSession, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let exec = self
+            .client
+            .create_exec(
+                container_id,
+                CreateExecOptions {
+                    cmd: Some(cmd.to_vec()),
+                    attach_stdin: Some(true),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let StartExecResults::Attached {
+            mut output,
+            mut input,
+        } = self.client.start_exec(&exec.id, None).await?
+        else {
+            return Err("Exec started detached".into());
+        };
+
+        let (stdin_tx, mut stdin_rx) = mpsc::channel::<Vec<u8>>(16);
+        tokio::spawn(async move {
+            while let Some(data) = stdin_rx.recv().await {
+                if input.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+            let _ = input.shutdown().await;
+        });
+
+        let (output_tx, output_rx) = mpsc::channel(16);
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            while let Some(Ok(log)) = output.next().await {
+                let out = match log {
+                    LogOutput::StdErr { message } => ExecOutput::Stderr(message.to_vec()),
+                    other => ExecOutput::Stdout(other.into_bytes().to_vec()),
+                };
+                if output_tx.send(out).await.is_err() {
+                    break;
+                }
+            }
+            let code = match client.inspect_exec(&exec.id).await {
+                Ok(inspect) => inspect.exit_code.unwrap_or(-1),
+                Err(error) => {
+                    log::error!("Error inspecting exec {}: {:#?}", &exec.id, error);
+                    -1
+                }
+            };
+            let _ = output_tx.send(ExecOutput::Exit(code)).await;
+        });
+
+        Ok(ExecSession {
+            stdin: stdin_tx,
+            output: output_rx,
+        })
+    }
+
+    fn stop_timeout(&self) -> i64 {
+        self.config.stop_timeout.unwrap_or(DEFAULT_STOP_TIMEOUT) as i64
+    }
 }
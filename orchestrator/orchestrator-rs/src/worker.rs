@@ -1,8 +1,13 @@
+use crate::process::ProcessClient;
 use crate::task::{self, Task};
 use bollard::errors::Error;
+use chrono::prelude::*;
 use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use tokio::time::{sleep, Duration};
+
+pub type SharedWorker = Arc<RwLock<Worker>>;
 
 #[derive(Debug, Clone)]
 pub struct Worker {
@@ -10,6 +15,7 @@ pub struct Worker {
     pub queue: VecDeque<Task<String>>,
     pub db: Arc<RwLock<HashMap<uuid::Uuid, Task<String>>>>,
     pub task_count: usize,
+    pub processes: ProcessClient,
 }
 
 impl Worker {
@@ -20,6 +26,69 @@ impl Worker {
             queue: VecDeque::new(),
             db: Arc::new(RwLock::new(HashMap::new())),
             task_count: 0,
+            processes: ProcessClient::new(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn get_tasks(&self) -> Vec<Task<String>> {
+        self.db.read().await.values().cloned().collect()
+    }
+
+    /// Stop a task known to this worker: one still waiting in the queue to
+    /// start is dropped from it, one which started gets a stop queued.
+    /// Returns `false` when the task is unknown.
+    pub async fn stop(&mut self, id: &uuid::Uuid) -> bool {
+        let queued = self.queue.len();
+        self.queue
+            .retain(|t| t.id != *id || t.state != task::State::Scheduled);
+        let dropped = self.queue.len() < queued;
+        if dropped {
+            log::info!("Dropped task {:#?} before it started", id);
+        }
+
+        let Some(mut t) = self.db.read().await.get(id).cloned() else {
+            return dropped;
+        };
+        // Scheduled again after finishing here, and dropped before starting
+        // over: nothing left to stop
+        if dropped && (t.state == task::State::Completed || t.state == task::State::Failed) {
+            return true;
+        }
+        t.state = task::State::Completed;
+        log::info!("Added task {:#?} to stop {:#?}", id, &t.container_id);
+        self.add_task(t);
+        true
+    }
+
+    /// Run a command inside a running task
+    pub async fn exec(
+        &self,
+        id: &uuid::Uuid,
+        cmd: &[String],
+    ) -> Result<task::ExecSession, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let t = self
+            .db
+            .read()
+            .await
+            .get(id)
+            .cloned()
+            .ok_or_else(|| format!("No task with id {} found", id))?;
+        if t.state != task::State::Running {
+            return Err(format!("Task {} is not running", id).into());
+        }
+        if cmd.is_empty() {
+            return Err("Missing command".into());
+        }
+        let container_id = t.container_id.clone().ok_or("Missing container id")?;
+        log::info!("Running {:?} in task {:#?}", cmd, id);
+        match t.runtime {
+            task::Runtime::Docker => {
+                task::DockerClient::new(task::Config::from(&t))?
+                    .exec(&container_id, cmd)
+                    .await
+            }
+            task::Runtime::Process => self.processes.exec(&container_id, cmd).await,
         }
     }
 
@@ -31,11 +100,34 @@ impl Worker {
         self.queue.push_back(t)
     }
 
+    /// Worker sharing the task db and processes, without the queue, to run
+    /// a task once the queue is let go of
+    fn runner(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            queue: VecDeque::new(),
+            db: self.db.clone(),
+            task_count: self.task_count,
+            processes: self.processes.clone(),
+        }
+    }
+
     #[allow(dead_code)]
     pub async fn run_task(
         &mut self,
-    ) -> Result<task::DockerResult<String>, Box<dyn std::error::Error + 'static>> {
-        match self.queue.pop_front() {
+    ) -> Result<task::DockerResult<String>, Box<dyn std::error::Error + Send + Sync + 'static>>
+    {
+        let next = self.queue.pop_front();
+        self.run(next).await
+    }
+
+    /// Start or stop the task, depending on the state it is sent in
+    async fn run(
+        &self,
+        next: Option<Task<String>>,
+    ) -> Result<task::DockerResult<String>, Box<dyn std::error::Error + Send + Sync + 'static>>
+    {
+        match next {
             None => Ok(task::DockerResult {
                 action: "run".to_string(),
                 container_id: None,
@@ -43,10 +135,22 @@ impl Worker {
                 result: None,
             }),
             Some(t) => {
-                let reader = self.db.read().await;
-                let mut persisted = reader.get(&t.id);
-                let t_persisted = persisted.get_or_insert(&t);
-                if task::contains(&t_persisted.state, &t.state) {
+                // Release the read lock before starting or stopping the task,
+                // both of them need to write to the db
+                let persisted_state = match self.db.read().await.get(&t.id) {
+                    // A finished task scheduled again, e.g. after being
+                    // preempted, starts over
+                    Some(persisted)
+                        if t.state == task::State::Scheduled
+                            && (persisted.state == task::State::Completed
+                                || persisted.state == task::State::Failed) =>
+                    {
+                        task::State::Scheduled
+                    }
+                    Some(persisted) => persisted.state.clone(),
+                    None => t.state.clone(),
+                };
+                if task::contains(&persisted_state, &t.state) {
                     match t.state {
                         task::State::Scheduled => return self.start_task(t).await,
                         task::State::Completed => return self.stop_task(t).await,
@@ -63,6 +167,12 @@ impl Worker {
                         }
                     }
                 }
+                log::error!(
+                    "Task {:#?} can not go from {:#?} to {:#?}",
+                    &t.id,
+                    persisted_state,
+                    &t.state
+                );
                 Ok(task::DockerResult {
                     action: "run".to_string(),
                     container_id: None,
@@ -77,32 +187,115 @@ impl Worker {
     pub async fn start_task(
         &self,
         mut t: Task<String>,
-    ) -> Result<task::DockerResult<String>, Box<dyn std::error::Error + 'static>> {
-        let config = task::Config::new(&t.name, &t.image, None);
-        let dc = task::DockerClient::new(config)?;
-        let dr = dc.run().await?;
+    ) -> Result<task::DockerResult<String>, Box<dyn std::error::Error + Send + Sync + 'static>>
+    {
+        let config = task::Config::from(&t);
+        let result = match t.runtime {
+            task::Runtime::Docker => match task::DockerClient::new(config) {
+                Ok(dc) => dc.run().await,
+                Err(error) => Err(error),
+            },
+            task::Runtime::Process => self.processes.run(&config).await,
+        };
+        let dr = match result {
+            Ok(dr) => dr,
+            Err(error) => {
+                log::error!("Error running task {:#?}: {:#?}", &t.id, error);
+                t.state = task::State::Failed;
+                t.reason = Some(error.to_string());
+                self.db.write().await.insert(t.id, t);
+                return Err(error);
+            }
+        };
         if dr.error.is_some() {
             log::info!("Error running task: {:#?}: {:#?}", &t.id, &dr.container_id);
             t.state = task::State::Failed;
-            self.db.write().await.insert(t.id.clone(), t);
+            t.reason = dr.error.as_ref().map(|e| e.to_string());
+            self.db.write().await.insert(t.id, t);
         } else {
             t.state = task::State::Running;
             t.container_id = dr.container_id.clone();
-            self.db.write().await.insert(t.id.clone(), t);
+            t.start_time = Some(Utc::now());
+            // Stored before the watcher starts, or a task exiting right away
+            // would have its exit overwritten with Running
+            self.db.write().await.insert(t.id, t.clone());
+            // Process tasks are watched whatever their kind, nothing else
+            // would notice them being OOM killed
+            if t.kind == task::Kind::Batch || t.runtime == task::Runtime::Process {
+                self.watch_task(t);
+            }
         }
         Ok(dr)
     }
 
+    /// Wait in the background for a task to exit. A task exiting with code
+    /// 0 is `Completed`, any other exit not asked for by a stop is `Failed`
+    /// with its reason.
+    fn watch_task(&self, t: Task<String>) {
+        let db = self.db.clone();
+        let processes = self.processes.clone();
+        tokio::spawn(async move {
+            let container_id = t.container_id.clone().unwrap_or_default();
+            let exit = match t.runtime {
+                task::Runtime::Docker => match task::DockerClient::new(task::Config::from(&t)) {
+                    Ok(dc) => dc.wait(&container_id).await,
+                    Err(error) => Err(error),
+                },
+                task::Runtime::Process => processes.wait(&container_id).await,
+            };
+            let exit = match exit {
+                Ok(exit) => exit,
+                Err(error) => {
+                    log::error!("Error waiting for task {:#?}: {:#?}", &t.id, error);
+                    task::ExitStatus::new(-1, false)
+                }
+            };
+
+            let mut db = db.write().await;
+            let Some(t) = db.get_mut(&t.id) else {
+                return;
+            };
+            // Stopped, or stopped and started again, in the meantime
+            if t.state != task::State::Running
+                || t.container_id.as_deref() != Some(container_id.as_str())
+            {
+                return;
+            }
+            t.state = if exit.code == 0 && exit.reason.is_none() {
+                task::State::Completed
+            } else {
+                task::State::Failed
+            };
+            t.reason = exit.reason;
+            t.finish_time = Some(Utc::now());
+            log::info!(
+                "Task {:#?} exited with code {}: {:#?}",
+                &t.id,
+                exit.code,
+                &t.state
+            );
+        });
+    }
+
     #[allow(dead_code)]
     pub async fn stop_task(
         &self,
-        t: Task<String>,
-    ) -> Result<task::DockerResult<String>, Box<dyn std::error::Error + 'static>> {
-        let config = task::Config::new(&t.name, &t.image, None);
-        let dc = task::DockerClient::new(config)?;
+        mut t: Task<String>,
+    ) -> Result<task::DockerResult<String>, Box<dyn std::error::Error + Send + Sync + 'static>>
+    {
+        let config = task::Config::from(&t);
 
-        if let Some(container_id) = &dc.container_id {
-            let dr = dc.stop(container_id).await?;
+        if let Some(container_id) = &t.container_id {
+            let dr = match t.runtime {
+                task::Runtime::Docker => {
+                    task::DockerClient::new(config)?.stop(container_id).await?
+                }
+                task::Runtime::Process => self.processes.stop(container_id, &config).await?,
+            };
+            t.state = task::State::Completed;
+            t.finish_time = Some(Utc::now());
+            log::info!("Stopped {} for task {:#?}", container_id, &t.id);
+            self.db.write().await.insert(t.id, t);
             Ok(dr)
         } else {
             Ok(task::DockerResult {
@@ -117,3 +310,60 @@ impl Worker {
         }
     }
 }
+
+/// Process the worker queue forever. Starting a task can take as long as
+/// pulling its image: the worker is only locked to take the task off the
+/// queue, not while running it.
+pub async fn run_tasks(worker: SharedWorker) {
+    loop {
+        let (next, runner) = {
+            let mut w = worker.write().await;
+            (w.queue.pop_front(), w.runner())
+        };
+        if next.is_some() {
+            if let Err(error) = runner.run(next).await {
+                log::error!("Failed to run task: {:#?}", error);
+            }
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(state: task::State) -> Task<String> {
+        Task {
+            id: uuid::Uuid::new_v4(),
+            state,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn stop_drops_tasks_not_started_yet() {
+        let mut worker = Worker::new("w1".to_string());
+        let queued = task(task::State::Scheduled);
+        let other = task(task::State::Scheduled);
+        worker.add_task(queued.clone());
+        worker.add_task(other.clone());
+
+        assert!(worker.stop(&queued.id).await);
+        assert_eq!(worker.queue.len(), 1);
+        assert_eq!(worker.queue[0].id, other.id);
+        assert!(!worker.stop(&queued.id).await);
+        assert!(!worker.stop(&uuid::Uuid::new_v4()).await);
+    }
+
+    #[tokio::test]
+    async fn stop_queues_a_stop_for_started_tasks() {
+        let mut worker = Worker::new("w1".to_string());
+        let running = task(task::State::Running);
+        worker.db.write().await.insert(running.id, running.clone());
+
+        assert!(worker.stop(&running.id).await);
+        assert_eq!(worker.queue.len(), 1);
+        assert_eq!(worker.queue[0].state, task::State::Completed);
+    }
+}
@@ -0,0 +1,143 @@
+use std::net::SocketAddr;
+
+use uuid::Uuid;
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::exec;
+use crate::manager_api::error_reply;
+use crate::security::{self, SecurityConfig};
+use crate::task::{State, TaskEvent};
+use crate::worker::SharedWorker;
+
+async fn start_task(worker: SharedWorker, te: TaskEvent<String>) -> Result<impl Reply, Rejection> {
+    let t = te.task;
+    log::info!("Added task {:#?}", &t.id);
+    let mut w = worker.write().await;
+    if t.state == State::Scheduled {
+        // Sent again after finishing here, e.g. once preempted: stop
+        // reporting the previous run
+        let mut db = w.db.write().await;
+        if db
+            .get(&t.id)
+            .is_some_and(|p| p.state == State::Completed || p.state == State::Failed)
+        {
+            db.remove(&t.id);
+        }
+    }
+    w.add_task(t.clone());
+    Ok(warp::reply::with_status(
+        warp::reply::json(&t),
+        StatusCode::CREATED,
+    ))
+}
+
+async fn get_tasks(worker: SharedWorker) -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&worker.read().await.get_tasks().await))
+}
+
+async fn stop_task(id: Uuid, worker: SharedWorker) -> Result<impl Reply, Rejection> {
+    if worker.write().await.stop(&id).await {
+        Ok(StatusCode::NO_CONTENT.into_response())
+    } else {
+        log::info!("No task with id {:#?} found", &id);
+        Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("No task with id {} found", id),
+        )
+        .into_response())
+    }
+}
+
+async fn exec_task(
+    id: Uuid,
+    exec_enabled: bool,
+    worker: SharedWorker,
+    ws: warp::ws::Ws,
+) -> Result<impl Reply, Rejection> {
+    if !exec_enabled {
+        return Ok(error_reply(
+            StatusCode::FORBIDDEN,
+            "Exec requires client certificates, configure --tls-ca".to_string(),
+        )
+        .into_response());
+    }
+    let state = worker
+        .read()
+        .await
+        .db
+        .read()
+        .await
+        .get(&id)
+        .map(|t| t.state.clone());
+    match state {
+        Some(State::Running) => Ok(ws
+            .on_upgrade(move |socket| exec::serve_session(socket, worker, id))
+            .into_response()),
+        Some(_) => Ok(
+            error_reply(StatusCode::CONFLICT, format!("Task {} is not running", id))
+                .into_response(),
+        ),
+        None => Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("No task with id {} found", id),
+        )
+        .into_response()),
+    }
+}
+
+/// Routes of the worker API. Running commands in tasks is only allowed
+/// when `exec_enabled`, i.e. clients are authenticated.
+pub fn routes(
+    worker: SharedWorker,
+    exec_enabled: bool,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let worker_filter = warp::any().map(move || worker.clone());
+
+    let start_task = warp::post()
+        .and(warp::path("tasks"))
+        .and(warp::path::end())
+        .and(worker_filter.clone())
+        .and(warp::body::json())
+        .and_then(start_task);
+
+    let get_tasks = warp::get()
+        .and(warp::path("tasks"))
+        .and(warp::path::end())
+        .and(worker_filter.clone())
+        .and_then(get_tasks);
+
+    let stop_task = warp::delete()
+        .and(warp::path("tasks"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path::end())
+        .and(worker_filter.clone())
+        .and_then(stop_task);
+
+    let exec_task = warp::get()
+        .and(warp::path("tasks"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("exec"))
+        .and(warp::path::end())
+        .and(warp::any().map(move || exec_enabled))
+        .and(worker_filter)
+        .and(warp::ws())
+        .and_then(exec_task);
+
+    start_task.or(get_tasks).or(stop_task).or(exec_task)
+}
+
+/// Serve the worker API. Only clients presenting a certificate signed by
+/// the configured CA, i.e. the manager, get through unless the config is
+/// `insecure`. Exec is only served when client certificates are required.
+pub async fn serve(
+    worker: SharedWorker,
+    addr: SocketAddr,
+    config: SecurityConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    log::info!("Worker API listening on {}", addr);
+    let exec_enabled = config.client_auth_enabled();
+    if !exec_enabled {
+        log::warn!("No client certificates required, exec is disabled");
+    }
+    security::serve(routes(worker, exec_enabled), addr, &config, true).await
+}
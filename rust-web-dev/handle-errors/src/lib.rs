@@ -1,11 +1,19 @@
 use argon2::Error as ArgonError;
 use reqwest::Error as ReqwestError;
 use reqwest_middleware::Error as MiddlewareReqwestError;
+use serde::Serialize;
+use serde_json::{json, Value};
 use tracing::{event, instrument, Level};
+use utoipa::ToSchema;
 use warp::{
     filters::{body::BodyDeserializeError, cors::CorsForbidden},
+    http::header::RETRY_AFTER,
     http::StatusCode,
-    reject::Reject,
+    reject::{
+        InvalidQuery, MethodNotAllowed, MissingHeader, PayloadTooLarge, Reject,
+        UnsupportedMediaType,
+    },
+    reply::Response,
     Rejection, Reply,
 };
 
@@ -13,12 +21,24 @@ use warp::{
 pub enum Error {
     ParseError(std::num::ParseIntError),
     MissingParameters,
+    InvalidParameter(String),
+    InvalidEmail(String),
     WrongPassword,
+    EmailNotVerified,
+    /// Seconds until the client can try again
+    TooManyRequests(u64),
+    InvalidAccountToken,
     CannotDecryptToken,
+    TokenRevoked,
+    InvalidRefreshToken,
     Unauthorized,
+    Forbidden,
+    NotFound,
     ArgonLibraryError(ArgonError),
     DatabaseQueryError(sqlx::Error),
     MigrationError(sqlx::migrate::MigrateError),
+    ConfigurationError(String),
+    MailerError(String),
     ReqwestAPIError(ReqwestError),
     MiddlewareReqwestAPIError(MiddlewareReqwestError),
     ClientError(APILayerError),
@@ -44,9 +64,20 @@ impl std::fmt::Display for Error {
                 write!(f, "Cannot parse parameter: {}", err)
             }
             Error::MissingParameters => write!(f, "Missing parameter"),
+            Error::InvalidParameter(param) => write!(f, "Invalid parameter: {}", param),
+            Error::InvalidEmail(email) => write!(f, "Invalid email address: {}", email),
             Error::WrongPassword => write!(f, "Wrong password"),
+            Error::EmailNotVerified => write!(f, "Email address not verified"),
+            Error::TooManyRequests(retry_after) => {
+                write!(f, "Too many requests, retry in {} seconds", retry_after)
+            }
+            Error::InvalidAccountToken => write!(f, "Invalid verification or reset token"),
             Error::CannotDecryptToken => write!(f, "Cannot decrypt error"),
-            Error::Unauthorized => write!(f, "No permission to change the underlying resource"),
+            Error::TokenRevoked => write!(f, "Token has been revoked"),
+            Error::InvalidRefreshToken => write!(f, "Invalid refresh token"),
+            Error::Unauthorized => write!(f, "Missing or invalid access token"),
+            Error::Forbidden => write!(f, "No permission to change the underlying resource"),
+            Error::NotFound => write!(f, "Resource not found"),
             Error::ArgonLibraryError(_) => {
                 write!(f, "Cannot verifiy password")
             }
@@ -54,6 +85,8 @@ impl std::fmt::Display for Error {
                 write!(f, "Cannot update, invalid data")
             }
             Error::MigrationError(_) => write!(f, "Cannot migrate data"),
+            Error::ConfigurationError(err) => write!(f, "Invalid configuration: {}", err),
+            Error::MailerError(err) => write!(f, "Cannot send email: {}", err),
             Error::ReqwestAPIError(err) => {
                 write!(f, "External API error: {}", err)
             }
@@ -70,94 +103,248 @@ impl std::fmt::Display for Error {
     }
 }
 
-const DUPLICATE_KEY: u32 = 23505;
+// Postgres error codes of the constraint violations
+const UNIQUE_VIOLATION: &str = "23505";
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+const NOT_NULL_VIOLATION: &str = "23502";
+const CHECK_VIOLATION: &str = "23514";
 
 impl Reject for Error {}
 impl Reject for APILayerError {}
 
-#[instrument]
-pub async fn return_error(r: Rejection) -> Result<impl Reply, Rejection> {
-    if let Some(crate::Error::DatabaseQueryError(e)) = r.find() {
-        event!(Level::ERROR, "Database query error");
-
-        match e {
-            sqlx::Error::Database(err) => {
-                if err.code().unwrap().parse::<u32>().unwrap() == DUPLICATE_KEY {
-                    Ok(warp::reply::with_status(
-                        "Account already exsists".to_string(),
-                        StatusCode::UNPROCESSABLE_ENTITY,
-                    ))
-                } else {
-                    Ok(warp::reply::with_status(
-                        "Cannot update data".to_string(),
-                        StatusCode::UNPROCESSABLE_ENTITY,
-                    ))
-                }
-            }
-            _ => Ok(warp::reply::with_status(
-                "Cannot update data".to_string(),
-                StatusCode::UNPROCESSABLE_ENTITY,
-            )),
+/// Body of every error response
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ErrorBody {
+    /// Stable, machine readable code of the error
+    pub code: &'static str,
+    /// Human readable description of the error
+    pub message: String,
+    /// More about the error, depending on the code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<Value>,
+    /// Id of the request, to find it in the logs
+    pub request_id: String,
+}
+
+impl Error {
+    /// Code identifying the error in responses. Clients match on it, so
+    /// codes never change once released.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::ParseError(_) => "parse_error",
+            Error::MissingParameters => "missing_parameters",
+            Error::InvalidParameter(_) => "invalid_parameter",
+            Error::InvalidEmail(_) => "invalid_email",
+            Error::WrongPassword => "wrong_credentials",
+            Error::EmailNotVerified => "email_not_verified",
+            Error::TooManyRequests(_) => "too_many_requests",
+            Error::InvalidAccountToken => "invalid_account_token",
+            Error::CannotDecryptToken => "invalid_token",
+            Error::TokenRevoked => "token_revoked",
+            Error::InvalidRefreshToken => "invalid_refresh_token",
+            Error::Unauthorized => "unauthorized",
+            Error::Forbidden => "forbidden",
+            Error::NotFound => "not_found",
+            Error::ArgonLibraryError(_) => "password_hash_error",
+            Error::DatabaseQueryError(e) => database_error(e).1,
+            Error::MigrationError(_) => "migration_error",
+            Error::ConfigurationError(_) => "configuration_error",
+            Error::MailerError(_) => "mailer_error",
+            Error::ReqwestAPIError(_)
+            | Error::MiddlewareReqwestAPIError(_)
+            | Error::ClientError(_)
+            | Error::ServerError(_) => "external_api_error",
         }
-    } else if let Some(crate::Error::ReqwestAPIError(e)) = r.find() {
-        event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
-            "Internal Server Error".to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    } else if let Some(crate::Error::Unauthorized) = r.find() {
-        event!(Level::ERROR, "Not matching account id");
-        Ok(warp::reply::with_status(
-            "No permission to change underlying resource".to_string(),
-            StatusCode::UNAUTHORIZED,
-        ))
-    } else if let Some(crate::Error::WrongPassword) = r.find() {
-        event!(Level::ERROR, "Entered wrong password");
-        Ok(warp::reply::with_status(
-            "Wrong E-Mail/Password combination".to_string(),
-            StatusCode::UNAUTHORIZED,
-        ))
-    } else if let Some(crate::Error::MiddlewareReqwestAPIError(e)) = r.find() {
-        event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
-            "Internal Server Error".to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    } else if let Some(crate::Error::ClientError(e)) = r.find() {
-        event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
-            "Internal Server Error".to_string(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    } else if let Some(crate::Error::ServerError(e)) = r.find() {
-        event!(Level::ERROR, "{}", e);
-        Ok(warp::reply::with_status(
-            "Internal Server Error".to_string(),
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::ParseError(_)
+            | Error::MissingParameters
+            | Error::InvalidParameter(_)
+            | Error::InvalidAccountToken => StatusCode::BAD_REQUEST,
+            Error::InvalidEmail(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::WrongPassword
+            | Error::CannotDecryptToken
+            | Error::TokenRevoked
+            | Error::InvalidRefreshToken
+            | Error::Unauthorized => StatusCode::UNAUTHORIZED,
+            Error::EmailNotVerified | Error::Forbidden => StatusCode::FORBIDDEN,
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Error::DatabaseQueryError(e) => database_error(e).0,
+            Error::ArgonLibraryError(_)
+            | Error::MigrationError(_)
+            | Error::ConfigurationError(_)
+            | Error::MailerError(_)
+            | Error::ReqwestAPIError(_)
+            | Error::MiddlewareReqwestAPIError(_)
+            | Error::ClientError(_)
+            | Error::ServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Message for the client. Server errors keep their cause to the logs.
+    fn message(&self) -> String {
+        match self {
+            Error::WrongPassword => "Wrong E-Mail/Password combination".to_string(),
+            Error::TokenRevoked => "Session has been logged out".to_string(),
+            Error::DatabaseQueryError(e) => database_error(e).2.to_string(),
+            _ if self.status().is_server_error() => "Internal Server Error".to_string(),
+            _ => self.to_string(),
+        }
+    }
+
+    fn details(&self) -> Option<Value> {
+        match self {
+            Error::ParseError(e) => Some(json!({ "reason": e.to_string() })),
+            Error::InvalidParameter(parameter) => Some(json!({ "parameter": parameter })),
+            Error::InvalidEmail(email) => Some(json!({ "email": email })),
+            Error::TooManyRequests(retry_after) => Some(json!({ "retry_after": retry_after })),
+            Error::DatabaseQueryError(sqlx::Error::Database(e)) => e
+                .constraint()
+                .map(|constraint| json!({ "constraint": constraint })),
+            _ => None,
+        }
+    }
+}
+
+/// Status, code and message of a database error. Constraint violations are
+/// the client's doing, anything else is the server's.
+fn database_error(e: &sqlx::Error) -> (StatusCode, &'static str, &'static str) {
+    match e {
+        sqlx::Error::RowNotFound => (StatusCode::NOT_FOUND, "not_found", "Resource not found"),
+        sqlx::Error::Database(err) => match err.code().as_deref() {
+            Some(UNIQUE_VIOLATION) => (
+                StatusCode::CONFLICT,
+                "duplicate_entry",
+                "Resource already exists",
+            ),
+            Some(FOREIGN_KEY_VIOLATION) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_reference",
+                "Referenced resource does not exist",
+            ),
+            Some(NOT_NULL_VIOLATION) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "missing_value",
+                "Missing a required value",
+            ),
+            Some(CHECK_VIOLATION) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_value",
+                "Value not allowed",
+            ),
+            _ => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_data",
+                "Cannot update, invalid data",
+            ),
+        },
+        _ => (
             StatusCode::INTERNAL_SERVER_ERROR,
-        ))
-    } else if let Some(error) = r.find::<CorsForbidden>() {
-        event!(Level::ERROR, "CORS forbidden error: {}", error);
-        Ok(warp::reply::with_status(
-            error.to_string(),
-            StatusCode::FORBIDDEN,
-        ))
-    } else if let Some(error) = r.find::<BodyDeserializeError>() {
-        event!(Level::ERROR, "Cannot deserizalize request body: {}", error);
-        Ok(warp::reply::with_status(
-            error.to_string(),
-            StatusCode::UNPROCESSABLE_ENTITY,
-        ))
-    } else if let Some(error) = r.find::<Error>() {
-        event!(Level::ERROR, "{}", error);
-        Ok(warp::reply::with_status(
-            error.to_string(),
+            "database_error",
+            "Internal Server Error",
+        ),
+    }
+}
+
+/// Turn the rejection into a JSON error response
+#[instrument]
+pub fn return_error(r: Rejection, request_id: &str) -> Response {
+    let (status, code, message, details) = if let Some(e) = r.find::<Error>() {
+        if e.status().is_server_error() {
+            event!(Level::ERROR, request_id, "{:?}", e);
+        } else {
+            event!(Level::WARN, request_id, "{}", e);
+        }
+        (e.status(), e.code(), e.message(), e.details())
+    } else if let Some(e) = r.find::<CorsForbidden>() {
+        event!(Level::WARN, request_id, "CORS forbidden error: {}", e);
+        (StatusCode::FORBIDDEN, "cors_forbidden", e.to_string(), None)
+    } else if let Some(e) = r.find::<BodyDeserializeError>() {
+        event!(
+            Level::WARN,
+            request_id,
+            "Cannot deserialize request body: {}",
+            e
+        );
+        (
             StatusCode::UNPROCESSABLE_ENTITY,
-        ))
-    } else {
-        event!(Level::WARN, "Requested route was not found");
-        Ok(warp::reply::with_status(
-            "Route not found".to_string(),
+            "invalid_body",
+            "Cannot deserialize request body".to_string(),
+            Some(json!({ "reason": e.to_string() })),
+        )
+    } else if let Some(e) = r.find::<MethodNotAllowed>() {
+        event!(Level::WARN, request_id, "{}", e);
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method_not_allowed",
+            e.to_string(),
+            None,
+        )
+    } else if let Some(e) = r.find::<UnsupportedMediaType>() {
+        event!(Level::WARN, request_id, "{}", e);
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+            e.to_string(),
+            None,
+        )
+    } else if let Some(e) = r.find::<PayloadTooLarge>() {
+        event!(Level::WARN, request_id, "{}", e);
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            e.to_string(),
+            None,
+        )
+    } else if let Some(e) = r.find::<InvalidQuery>() {
+        event!(Level::WARN, request_id, "{}", e);
+        (
+            StatusCode::BAD_REQUEST,
+            "invalid_query",
+            e.to_string(),
+            None,
+        )
+    } else if let Some(e) = r.find::<MissingHeader>() {
+        event!(Level::WARN, request_id, "{}", e);
+        (
+            StatusCode::BAD_REQUEST,
+            "missing_header",
+            e.to_string(),
+            Some(json!({ "header": e.name() })),
+        )
+    } else if r.is_not_found() {
+        event!(Level::WARN, request_id, "Requested route was not found");
+        (
             StatusCode::NOT_FOUND,
-        ))
+            "route_not_found",
+            "Route not found".to_string(),
+            None,
+        )
+    } else {
+        event!(Level::ERROR, request_id, "Unhandled rejection: {:?}", r);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unhandled_rejection",
+            "Internal Server Error".to_string(),
+            None,
+        )
+    };
+
+    let body = ErrorBody {
+        code,
+        message,
+        details,
+        request_id: request_id.to_string(),
+    };
+    let mut res = warp::reply::with_status(warp::reply::json(&body), status).into_response();
+
+    if let Some(Error::TooManyRequests(retry_after)) = r.find() {
+        res.headers_mut().insert(RETRY_AFTER, (*retry_after).into());
     }
+
+    res
 }
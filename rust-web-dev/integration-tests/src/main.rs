@@ -1,9 +1,15 @@
+use std::future::Future;
 use std::io::{self, Write};
 use std::process::Command;
+use std::sync::Arc;
 
 use futures_util::future::FutureExt;
 
-use rust_web_dev::{config, handle_errors, oneshot, setup_store};
+use rust_web_dev::mailer::MemoryMailer;
+use rust_web_dev::rate_limit::MemoryRateLimiter;
+use rust_web_dev::{
+    config, handle_errors, oneshot, setup_admin, setup_content_filter, setup_store,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -28,7 +34,55 @@ struct QuestionAnswer {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct Token(String);
+struct Answer {
+    id: i32,
+    content: String,
+    question_id: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Page<T> {
+    items: Vec<T>,
+    next_cursor: Option<String>,
+    total: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct QuestionWithAnswers {
+    id: i32,
+    title: String,
+    content: String,
+    tags: Option<Vec<String>>,
+    answers: Vec<Answer>,
+    answers_total: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Score {
+    score: i32,
+    vote: i16,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ScoredQuestion {
+    id: i32,
+    score: i32,
+    accepted_answer_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Profile {
+    id: i32,
+    email: String,
+    role: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Token {
+    access_token: String,
+    refresh_token: String,
+    expires_in: i64,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), handle_errors::Error> {
@@ -65,9 +119,13 @@ async fn main() -> Result<(), handle_errors::Error> {
 
     // set up a new store instance with a db connection pool
     let store = setup_store(&config).await?;
+    let content_filter = setup_content_filter(&config)?;
 
     // start the server and listen for a sender signal to shut it down
-    let handler = oneshot(store).await;
+    let mailer = Arc::new(MemoryMailer::new());
+    let rate_limiter = Arc::new(MemoryRateLimiter::new());
+    let admin_store = store.clone();
+    let handler = oneshot(store, content_filter, mailer.clone(), rate_limiter).await;
 
     // create a test user to use throughout the tests
     let u = User {
@@ -77,6 +135,18 @@ async fn main() -> Result<(), handle_errors::Error> {
 
     let token;
 
+    print!("Running check_health...");
+    match std::panic::AssertUnwindSafe(check_health())
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
     print!("Running register_new_user...");
     let result = std::panic::AssertUnwindSafe(register_new_user(&u))
         .catch_unwind()
@@ -89,8 +159,23 @@ async fn main() -> Result<(), handle_errors::Error> {
         }
     }
 
+    print!("Running verify_email...");
+    match std::panic::AssertUnwindSafe(verify_email(&mailer, &u))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
     print!("Running login...");
-    match std::panic::AssertUnwindSafe(login(u)).catch_unwind().await {
+    match std::panic::AssertUnwindSafe(login(u.clone()))
+        .catch_unwind()
+        .await
+    {
         Ok(t) => {
             token = t;
             println!("✓");
@@ -102,7 +187,105 @@ async fn main() -> Result<(), handle_errors::Error> {
     }
 
     print!("Running post_question...");
-    match std::panic::AssertUnwindSafe(post_question(token))
+    match std::panic::AssertUnwindSafe(post_question(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running post_answer...");
+    match std::panic::AssertUnwindSafe(post_answer(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running get_answers...");
+    match std::panic::AssertUnwindSafe(get_answers()).catch_unwind().await {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running vote_and_accept...");
+    match std::panic::AssertUnwindSafe(vote_and_accept(&mailer, token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    // How the first admin gets made, once registered
+    let mut admin_config = config::Config::new().expect("Config can't be set");
+    admin_config.admin_email = Some("admin@email.com".to_string());
+    print!("Running moderate...");
+    match std::panic::AssertUnwindSafe(moderate(&mailer, || {
+        setup_admin(&admin_config, &admin_store)
+    }))
+    .catch_unwind()
+    .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running refresh_and_logout...");
+    match std::panic::AssertUnwindSafe(refresh_and_logout(token))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running manage_account...");
+    match std::panic::AssertUnwindSafe(manage_account(&mailer))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running lock_account...");
+    match std::panic::AssertUnwindSafe(lock_account())
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running reset_password...");
+    match std::panic::AssertUnwindSafe(reset_password(&mailer, u))
         .catch_unwind()
         .await
     {
@@ -118,24 +301,114 @@ async fn main() -> Result<(), handle_errors::Error> {
     Ok(())
 }
 
+async fn check_health() {
+    let client = reqwest::Client::new();
+    let res = client
+        .get("http://localhost:3030/health")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let res = client
+        .get("http://localhost:3030/ready")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let readiness = res.json::<Value>().await.unwrap();
+    assert_eq!(readiness["status"], "ready");
+    assert_eq!(readiness["database"], "ok");
+    assert_eq!(readiness["migrations"]["pending"], serde_json::json!([]));
+
+    // The API only answers under its version
+    let res = client
+        .get("http://localhost:3030/questions")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+}
+
 async fn register_new_user(user: &User) {
     let client = reqwest::Client::new();
     let res = client
-        .post("http://localhost:3030/registration")
+        .post("http://localhost:3030/v1/registration")
         .json(&user)
         .send()
         .await
-        .unwrap()
-        .json::<Value>()
-        .await;
+        .unwrap();
+
+    assert_eq!(res.status(), 201);
+    assert_eq!(res.headers()["location"], "/v1/accounts/me");
+    let profile = res.json::<Profile>().await.unwrap();
+    // Stored normalised, as logins look it up
+    assert_eq!(profile.email, user.email.trim().to_lowercase());
+    assert_eq!(profile.role, "user");
+}
+
+/// Token in the last email sent to the address
+fn emailed_token(mailer: &MemoryMailer, to: &str) -> String {
+    let email = mailer
+        .sent()
+        .into_iter()
+        .rev()
+        .find(|email| email.to == to)
+        .expect("No email sent");
+
+    email
+        .body
+        .lines()
+        .find(|line| line.len() == 64 && line.chars().all(|c| c.is_ascii_hexdigit()))
+        .expect("No token in the email")
+        .to_string()
+}
+
+/// Token of the next email sent to the address after the first `sent`
+/// ones, for emails going out after the answer
+async fn next_emailed_token(mailer: &MemoryMailer, to: &str, sent: usize) -> String {
+    for _ in 0..50 {
+        if mailer.sent()[sent..].iter().any(|email| email.to == to) {
+            return emailed_token(mailer, to);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    panic!("No email sent");
+}
+
+async fn verify_email(mailer: &MemoryMailer, user: &User) {
+    let client = reqwest::Client::new();
+    let res = client
+        .post("http://localhost:3030/v1/login")
+        .json(&user)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 403);
+
+    let token = emailed_token(mailer, &user.email.trim().to_lowercase());
+    let res = client
+        .post("http://localhost:3030/v1/verify-email")
+        .json(&serde_json::json!({ "token": token }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
 
-    assert_eq!(res.unwrap(), "Account added".to_string());
+    // Tokens are good for one use
+    let res = client
+        .post("http://localhost:3030/v1/verify-email")
+        .json(&serde_json::json!({ "token": token }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 400);
 }
 
 async fn login(user: User) -> Token {
     let client = reqwest::Client::new();
     let res = client
-        .post("http://localhost:3030/login")
+        .post("http://localhost:3030/v1/login")
         .json(&user)
         .send()
         .await
@@ -154,16 +427,511 @@ async fn post_question(token: Token) {
 
     let client = reqwest::Client::new();
     let res = client
-        .post("http://localhost:3030/questions")
-        .header("Authorization", token.0)
+        .post("http://localhost:3030/v1/questions")
+        .header("Authorization", token.access_token)
         .json(&q)
         .send()
         .await
+        .unwrap();
+
+    assert_eq!(res.status(), 201);
+    assert_eq!(res.headers()["location"], "/v1/questions/1");
+    let question = res.json::<QuestionAnswer>().await.unwrap();
+    assert_eq!(question.id, 1);
+    assert_eq!(question.title, q.title);
+}
+
+async fn post_answer(token: Token) {
+    let client = reqwest::Client::new();
+    let res = client
+        .post("http://localhost:3030/v1/comments")
+        .header("Authorization", token.access_token.clone())
+        .json(&serde_json::json!({
+            "content": "Write an integration test",
+            "question_id": 1,
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), 201);
+    let location = res.headers()["location"].to_str().unwrap().to_string();
+    let answer = res.json::<Answer>().await.unwrap();
+    assert_eq!(location, format!("/v1/answers/{}", answer.id));
+    assert_eq!(answer.question_id, 1);
+
+    let res = client
+        .get(format!("http://localhost:3030{}", location))
+        .send()
+        .await
+        .unwrap()
+        .json::<Answer>()
+        .await
+        .unwrap();
+    assert_eq!(res.content, answer.content);
+
+    let res = client
+        .put(format!("http://localhost:3030{}", location))
+        .header("Authorization", token.access_token)
+        .json(&serde_json::json!({ "content": "Write two integration tests" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let updated = res.json::<Answer>().await.unwrap();
+    assert_eq!(updated.id, answer.id);
+    assert_eq!(updated.question_id, 1);
+    assert_eq!(updated.content, "Write two integration tests");
+}
+
+async fn get_answers() {
+    let client = reqwest::Client::new();
+    let page = client
+        .get("http://localhost:3030/v1/questions/1/answers")
+        .send()
+        .await
+        .unwrap()
+        .json::<Page<Answer>>()
+        .await
+        .unwrap();
+
+    assert_eq!(page.total, 1);
+    assert_eq!(page.next_cursor, None);
+    let answers = page.items;
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0].content, "Write two integration tests");
+    assert_eq!(answers[0].question_id, 1);
+
+    let question = client
+        .get("http://localhost:3030/v1/questions/1")
+        .send()
+        .await
+        .unwrap()
+        .json::<QuestionWithAnswers>()
+        .await
+        .unwrap();
+
+    assert_eq!(question.id, 1);
+    assert_eq!(question.answers.len(), 1);
+    assert_eq!(question.answers_total, 1);
+    assert_eq!(question.answers[0].id, answers[0].id);
+}
+
+async fn vote_and_accept(mailer: &MemoryMailer, token: Token) {
+    let u = User {
+        email: "voter@email.com".to_string(),
+        password: "password".to_string(),
+    };
+    register_new_user(&u).await;
+    verify_email(mailer, &u).await;
+    let voter = login(u).await;
+
+    let client = reqwest::Client::new();
+    let vote = |path: &str, token: &Token| {
+        client
+            .post(format!("http://localhost:3030/v1/{}", path))
+            .header("Authorization", token.access_token.clone())
+            .send()
+    };
+
+    let score = vote("questions/1/upvote", &voter)
+        .await
+        .unwrap()
+        .json::<Score>()
+        .await
+        .unwrap();
+    assert_eq!((score.score, score.vote), (1, 1));
+
+    // One vote per account, the last one counts
+    vote("questions/1/upvote", &voter).await.unwrap();
+    let score = vote("questions/1/downvote", &voter)
+        .await
+        .unwrap()
+        .json::<Score>()
+        .await
+        .unwrap();
+    assert_eq!((score.score, score.vote), (-1, -1));
+
+    let res = client
+        .delete("http://localhost:3030/v1/questions/1/vote")
+        .header("Authorization", voter.access_token.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Score>()
+        .await
+        .unwrap();
+    assert_eq!((res.score, res.vote), (0, 0));
+
+    let res = vote("questions/99/upvote", &voter).await.unwrap();
+    assert_eq!(res.status(), 404);
+
+    let score = vote("answers/1/upvote", &voter)
+        .await
+        .unwrap()
+        .json::<Score>()
+        .await
+        .unwrap();
+    assert_eq!(score.score, 1);
+
+    let question = client
+        .post("http://localhost:3030/v1/questions")
+        .header("Authorization", voter.access_token.clone())
+        .json(&Question {
+            title: "Second Question".to_string(),
+            content: "How do I vote?".to_string(),
+        })
+        .send()
+        .await
         .unwrap()
         .json::<QuestionAnswer>()
         .await
         .unwrap();
+    vote(&format!("questions/{}/upvote", question.id), &token)
+        .await
+        .unwrap();
+
+    let page = client
+        .get("http://localhost:3030/v1/questions?sort=-score&limit=1")
+        .send()
+        .await
+        .unwrap()
+        .json::<Page<ScoredQuestion>>()
+        .await
+        .unwrap();
+    assert_eq!(page.items[0].id, question.id);
+    assert_eq!(page.items[0].score, 1);
+
+    let cursor = page.next_cursor.unwrap();
+    // A cursor only continues the listing it comes from
+    let res = client
+        .get(format!(
+            "http://localhost:3030/v1/questions?sort=score&cursor={}",
+            cursor
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 400);
+
+    let page = client
+        .get(format!(
+            "http://localhost:3030/v1/questions?sort=-score&cursor={}",
+            cursor
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json::<Page<ScoredQuestion>>()
+        .await
+        .unwrap();
+    assert_eq!(page.items[0].id, 1);
+
+    // Only the author of the question accepts an answer
+    let res = vote("answers/1/accept", &voter).await.unwrap();
+    assert_eq!(res.status(), 403);
+
+    let question = vote("answers/1/accept", &token)
+        .await
+        .unwrap()
+        .json::<ScoredQuestion>()
+        .await
+        .unwrap();
+    assert_eq!(question.accepted_answer_id, Some(1));
+}
+
+async fn moderate<F, Fut>(mailer: &MemoryMailer, make_admin: F)
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<(), handle_errors::Error>>,
+{
+    let admin = User {
+        email: "admin@email.com".to_string(),
+        password: "password".to_string(),
+    };
+    register_new_user(&admin).await;
+    verify_email(mailer, &admin).await;
+    make_admin().await.unwrap();
+    let admin = login(admin).await;
+
+    let u = User {
+        email: "moderator@email.com".to_string(),
+        password: "password".to_string(),
+    };
+    register_new_user(&u).await;
+    verify_email(mailer, &u).await;
+    let user = login(u.clone()).await;
+
+    let client = reqwest::Client::new();
+    let edit_answer = |token: &Token| {
+        client
+            .put("http://localhost:3030/v1/answers/1")
+            .header("Authorization", token.access_token.clone())
+            .json(&serde_json::json!({ "content": "Moderated" }))
+            .send()
+    };
+    let list_accounts = |token: &Token| {
+        client
+            .get("http://localhost:3030/v1/accounts")
+            .header("Authorization", token.access_token.clone())
+            .send()
+    };
+
+    // Users only edit their own answers and do not manage accounts
+    assert_eq!(edit_answer(&user).await.unwrap().status(), 403);
+    assert_eq!(list_accounts(&user).await.unwrap().status(), 403);
 
-    assert_eq!(res.id, 1);
-    assert_eq!(res.title, q.title);
+    let accounts = list_accounts(&admin)
+        .await
+        .unwrap()
+        .json::<Vec<Profile>>()
+        .await
+        .unwrap();
+    let admin_profile = accounts
+        .iter()
+        .find(|a| a.email == "admin@email.com")
+        .unwrap();
+    assert_eq!(admin_profile.role, "admin");
+    let moderator = accounts
+        .iter()
+        .find(|a| a.email == "moderator@email.com")
+        .unwrap();
+
+    let set_role = |token: &Token, role: &str| {
+        client
+            .put(format!(
+                "http://localhost:3030/v1/accounts/{}/role",
+                moderator.id
+            ))
+            .header("Authorization", token.access_token.clone())
+            .json(&serde_json::json!({ "role": role }))
+            .send()
+    };
+    assert_eq!(set_role(&user, "admin").await.unwrap().status(), 403);
+    let profile = set_role(&admin, "moderator")
+        .await
+        .unwrap()
+        .json::<Profile>()
+        .await
+        .unwrap();
+    assert_eq!(profile.role, "moderator");
+
+    // The account is logged out, and gets the new role with the next login
+    assert_eq!(edit_answer(&user).await.unwrap().status(), 401);
+    let moderator = login(u).await;
+    let res = edit_answer(&moderator).await.unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.json::<Answer>().await.unwrap().content, "Moderated");
+
+    // Moderating is not managing accounts
+    assert_eq!(list_accounts(&moderator).await.unwrap().status(), 403);
+    assert_eq!(set_role(&moderator, "admin").await.unwrap().status(), 403);
+
+    // A demoted moderator loses the powers straight away
+    assert_eq!(set_role(&admin, "user").await.unwrap().status(), 200);
+    assert_eq!(edit_answer(&moderator).await.unwrap().status(), 401);
+}
+
+async fn refresh_and_logout(token: Token) {
+    let client = reqwest::Client::new();
+    let refresh = |refresh_token: String| {
+        client
+            .post("http://localhost:3030/v1/token/refresh")
+            .json(&serde_json::json!({ "refresh_token": refresh_token }))
+            .send()
+    };
+
+    let res = refresh(token.refresh_token.clone()).await.unwrap();
+    assert_eq!(res.status(), 200);
+    let refreshed = res.json::<Token>().await.unwrap();
+    assert_ne!(refreshed.refresh_token, token.refresh_token);
+
+    let res = client
+        .post("http://localhost:3030/v1/logout")
+        .header("Authorization", refreshed.access_token.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    // Every token of the session is revoked
+    let res = client
+        .post("http://localhost:3030/v1/questions")
+        .header("Authorization", token.access_token)
+        .json(&Question {
+            title: "After logout".to_string(),
+            content: "Should not be added".to_string(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 401);
+
+    let res = refresh(refreshed.refresh_token).await.unwrap();
+    assert_eq!(res.status(), 401);
+}
+
+async fn manage_account(mailer: &MemoryMailer) {
+    let u = User {
+        email: " Manage@Email.com".to_string(),
+        password: "password".to_string(),
+    };
+    register_new_user(&u).await;
+    verify_email(mailer, &u).await;
+    let token = login(u.clone()).await;
+
+    let client = reqwest::Client::new();
+    let profile = client
+        .get("http://localhost:3030/v1/accounts/me")
+        .header("Authorization", token.access_token.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Profile>()
+        .await
+        .unwrap();
+    assert_eq!(profile.email, "manage@email.com");
+    assert_eq!(profile.role, "user");
+
+    let res = client
+        .put("http://localhost:3030/v1/accounts/me/password")
+        .header("Authorization", token.access_token.clone())
+        .json(&serde_json::json!({
+            "old_password": u.password,
+            "new_password": "new password",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let u = User {
+        email: "manage@email.com".to_string(),
+        password: "new password".to_string(),
+    };
+    let token = login(u.clone()).await;
+
+    let res = client
+        .delete("http://localhost:3030/v1/accounts/me")
+        .header("Authorization", token.access_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let res = client
+        .post("http://localhost:3030/v1/login")
+        .json(&u)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 401);
+}
+
+async fn reset_password(mailer: &MemoryMailer, user: User) {
+    let client = reqwest::Client::new();
+    let mut tokens = Vec::new();
+    for _ in 0..2 {
+        let sent = mailer.sent().len();
+        let res = client
+            .post("http://localhost:3030/v1/password-reset")
+            .json(&serde_json::json!({ "email": user.email }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        tokens.push(next_emailed_token(mailer, &user.email, sent).await);
+    }
+
+    // Only the latest token works
+    for (token, status) in tokens.iter().zip([400, 200]) {
+        let res = client
+            .post("http://localhost:3030/v1/password-reset/confirm")
+            .json(&serde_json::json!({
+                "token": token,
+                "password": "reset password",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), status);
+    }
+
+    let res = client
+        .post("http://localhost:3030/v1/login")
+        .json(&user)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 401);
+
+    // An address gets few reset emails, however it is written
+    for status in [200, 429] {
+        let res = client
+            .post("http://localhost:3030/v1/password-reset")
+            .json(&serde_json::json!({ "email": user.email.to_uppercase() }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), status);
+    }
+
+    login(User {
+        email: user.email,
+        password: "reset password".to_string(),
+    })
+    .await;
+}
+
+async fn lock_account() {
+    let u = User {
+        email: "locked@email.com".to_string(),
+        password: "password".to_string(),
+    };
+    register_new_user(&u).await;
+
+    let client = reqwest::Client::new();
+    let wrong = User {
+        email: u.email.clone(),
+        password: "guess".to_string(),
+    };
+    for _ in 0..5 {
+        let res = client
+            .post("http://localhost:3030/v1/login")
+            .json(&wrong)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 401);
+    }
+
+    // Even the right password is refused while the account is locked
+    let res = client
+        .post("http://localhost:3030/v1/login")
+        .json(&u)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 429);
+    let retry_after = res.headers()["retry-after"]
+        .to_str()
+        .unwrap()
+        .parse::<u64>()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 15 * 60);
+
+    // Emails without an account get locked the same way
+    let unknown = User {
+        email: "nobody@email.com".to_string(),
+        password: "guess".to_string(),
+    };
+    for status in [401, 401, 401, 401, 401, 429] {
+        let res = client
+            .post("http://localhost:3030/v1/login")
+            .json(&unknown)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), status);
+    }
 }
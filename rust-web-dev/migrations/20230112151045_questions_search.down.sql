@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS questions_created_on_idx;
+DROP INDEX IF EXISTS questions_tags_idx;
+DROP INDEX IF EXISTS questions_search_idx;
+ALTER TABLE questions DROP COLUMN IF EXISTS search;
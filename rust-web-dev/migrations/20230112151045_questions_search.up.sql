@@ -0,0 +1,6 @@
+-- Add up migration script here
+ALTER TABLE questions ADD COLUMN search tsvector
+    GENERATED ALWAYS AS (to_tsvector('english', title || ' ' || content)) STORED;
+CREATE INDEX IF NOT EXISTS questions_search_idx ON questions USING GIN (search);
+CREATE INDEX IF NOT EXISTS questions_tags_idx ON questions USING GIN (tags);
+CREATE INDEX IF NOT EXISTS questions_created_on_idx ON questions (created_on);
@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS accounts_email_lower_idx;
+DELETE FROM answers WHERE account_id IS NULL;
+DELETE FROM questions WHERE account_id IS NULL;
+ALTER TABLE answers ALTER COLUMN account_id SET NOT NULL;
+ALTER TABLE questions ALTER COLUMN account_id SET NOT NULL;
@@ -0,0 +1,21 @@
+-- Add up migration script here
+ALTER TABLE questions ALTER COLUMN account_id DROP NOT NULL;
+ALTER TABLE answers ALTER COLUMN account_id DROP NOT NULL;
+-- Emails differing only by case would fail the index below with a bare
+-- unique violation. Which account to keep is for a person to decide: list
+-- them and stop.
+DO $$
+DECLARE
+    duplicates TEXT;
+BEGIN
+    SELECT string_agg(emails, '; ') INTO duplicates FROM (
+        SELECT string_agg(id || ' ' || email, ', ' ORDER BY id) AS emails
+        FROM accounts GROUP BY lower(email) HAVING COUNT(*) > 1
+    ) AS d;
+    IF duplicates IS NOT NULL THEN
+        RAISE EXCEPTION 'Accounts with emails differing only by case: %', duplicates
+            USING HINT = 'Merge or rename these accounts, then run the migrations again';
+    END IF;
+END
+$$;
+CREATE UNIQUE INDEX IF NOT EXISTS accounts_email_lower_idx ON accounts (lower(email));
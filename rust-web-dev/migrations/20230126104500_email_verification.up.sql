@@ -0,0 +1,13 @@
+-- Add up migration script here
+ALTER TABLE accounts ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT false;
+-- Accounts registered before verification existed stay usable
+UPDATE accounts SET email_verified = true;
+CREATE TABLE IF NOT EXISTS account_tokens (
+    id serial PRIMARY KEY,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    account_id integer NOT NULL,
+    purpose VARCHAR(32) NOT NULL,
+    expires_on TIMESTAMP NOT NULL,
+    used_on TIMESTAMP,
+    created_on TIMESTAMP NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,16 @@
+-- Add up migration script here
+-- Failed logins are counted per email, whether or not it has an account, so
+-- the lockout does not tell which ones do
+CREATE TABLE IF NOT EXISTS login_failures (
+    email TEXT PRIMARY KEY,
+    failed_logins INTEGER NOT NULL DEFAULT 0,
+    locked_until TIMESTAMP,
+    updated_on TIMESTAMP NOT NULL
+);
+CREATE TABLE IF NOT EXISTS rate_limits (
+    key TEXT PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    allowed BOOLEAN NOT NULL,
+    updated_on TIMESTAMP NOT NULL,
+    full_on TIMESTAMP NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE questions DROP COLUMN IF EXISTS accepted_answer_id;
+DROP INDEX IF EXISTS questions_score_idx;
+ALTER TABLE answers DROP COLUMN IF EXISTS score;
+ALTER TABLE questions DROP COLUMN IF EXISTS score;
+DROP TABLE IF EXISTS votes;
@@ -0,0 +1,22 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS votes (
+    id serial PRIMARY KEY,
+    account_id integer NOT NULL,
+    question_id integer REFERENCES questions ON DELETE CASCADE,
+    answer_id integer REFERENCES answers ON DELETE CASCADE,
+    value SMALLINT NOT NULL CHECK (value IN (-1, 1)),
+    created_on TIMESTAMP NOT NULL DEFAULT NOW(),
+    -- A vote is either on a question or on an answer
+    CHECK ((question_id IS NULL) <> (answer_id IS NULL))
+);
+CREATE UNIQUE INDEX IF NOT EXISTS votes_question_idx ON votes (question_id, account_id)
+    WHERE question_id IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS votes_answer_idx ON votes (answer_id, account_id)
+    WHERE answer_id IS NOT NULL;
+CREATE INDEX IF NOT EXISTS votes_account_id_idx ON votes (account_id);
+-- Sum of the votes, kept up to date along with them
+ALTER TABLE questions ADD COLUMN score INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE answers ADD COLUMN score INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS questions_score_idx ON questions (score, created_on, id);
+ALTER TABLE questions ADD COLUMN accepted_answer_id integer
+    REFERENCES answers ON DELETE SET NULL;
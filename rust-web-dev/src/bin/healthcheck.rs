@@ -0,0 +1,99 @@
+use clap::Parser;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// How long the check waits for the server, below the healthcheck timeout
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Health check for the container, whose image has no shell or HTTP client
+/// to run one: exits with success when `/health` answers 200 on the port
+/// the server listens to
+#[derive(Parser, Debug)]
+#[clap(about, long_about = None)]
+struct Args {
+    /// Port the server listens to. Like the server, PORT overrides it.
+    #[clap(short, long, default_value = "8080")]
+    port: u16,
+}
+
+fn main() -> ExitCode {
+    dotenv::dotenv().ok();
+    let args = Args::parse();
+    let port = std::env::var("PORT").unwrap_or_else(|_| args.port.to_string());
+
+    match check(&port) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Unhealthy: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn check(port: &str) -> Result<(), String> {
+    let addr: SocketAddr = format!("127.0.0.1:{}", port)
+        .parse()
+        .map_err(|e| format!("Invalid port {}: {}", port, e))?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+        .map_err(|e| e.to_string())?;
+
+    // HTTP/1.0 has the server close the connection after the answer
+    stream
+        .write_all(b"GET /health HTTP/1.0\r\nHost: localhost\r\n\r\n")
+        .map_err(|e| e.to_string())?;
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| e.to_string())?;
+
+    match status_code(&response) {
+        Some(200) => Ok(()),
+        Some(status) => Err(format!("/health answered {}", status)),
+        None => Err("/health did not answer HTTP".to_string()),
+    }
+}
+
+/// Status code of the status line starting the response
+fn status_code(response: &str) -> Option<u16> {
+    let status_line = response.lines().next()?;
+    let mut parts = status_line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod healthcheck_tests {
+    use super::{status_code, Args};
+    use clap::Parser;
+
+    #[test]
+    fn reads_the_status_line() {
+        assert_eq!(
+            status_code("HTTP/1.0 200 OK\r\ncontent-type: application/json\r\n\r\n{}"),
+            Some(200)
+        );
+        assert_eq!(
+            status_code("HTTP/1.1 503 Service Unavailable\r\n\r\n"),
+            Some(503)
+        );
+        assert_eq!(status_code(""), None);
+        assert_eq!(status_code("SSH-2.0-OpenSSH\r\n"), None);
+    }
+
+    #[test]
+    fn port_from_the_arguments() {
+        assert_eq!(Args::parse_from(["healthcheck"]).port, 8080);
+        assert_eq!(
+            Args::parse_from(["healthcheck", "--port", "3030"]).port,
+            3030
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use rust_web_dev::config;
-use rust_web_dev::{run, setup_store};
+use rust_web_dev::{
+    run, setup_admin, setup_content_filter, setup_mailer, setup_rate_limiter, setup_store,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), handle_errors::Error> {
@@ -7,10 +9,14 @@ async fn main() -> Result<(), handle_errors::Error> {
 
     let config = config::Config::new().expect("Config can't be set");
     let store = setup_store(&config).await?;
+    setup_admin(&config, &store).await?;
+    let content_filter = setup_content_filter(&config)?;
+    let mailer = setup_mailer(&config)?;
+    let rate_limiter = setup_rate_limiter(&config, &store)?;
 
     tracing::info!("Q&A service build ID {}", env!("RUST_WEB_DEV_VERSION"));
 
-    run(config, store).await;
+    run(config, store, content_filter, mailer, rate_limiter).await;
 
     Ok(())
 }
@@ -1,6 +1,7 @@
 use clap::Parser;
 use dotenv;
 use std::env;
+use std::net::IpAddr;
 
 /// Q&A web service API
 #[derive(Parser, Debug, PartialEq)]
@@ -27,6 +28,40 @@ pub struct Config {
     /// Database name
     #[clap(long, default_value = "rustwebdev")]
     pub db_name: String,
+    /// How user content is censored (api-layer or word-list)
+    #[clap(long, default_value = "api-layer")]
+    pub content_filter: String,
+    /// URL of the APILayer API, used by the api-layer content filter
+    #[clap(long, default_value = "https://api.apilayer.com")]
+    pub api_layer_url: String,
+    /// File listing the words the word-list content filter censors
+    #[clap(long, default_value = "bad_words.txt")]
+    pub word_list: String,
+    /// How emails are sent (smtp or file)
+    #[clap(long, default_value = "file")]
+    pub mailer: String,
+    /// Sender of the emails
+    #[clap(long, default_value = "Q&A <noreply@localhost>")]
+    pub mail_from: String,
+    /// Directory the file mailer writes the emails to
+    #[clap(long, default_value = "mails")]
+    pub mail_dir: String,
+    /// Host of the SMTP relay, used by the smtp mailer
+    #[clap(long, default_value = "localhost")]
+    pub smtp_host: String,
+    /// PORT of the SMTP relay
+    #[clap(long, default_value = "587")]
+    pub smtp_port: u16,
+    /// Where the rate limits are counted (memory or postgres)
+    #[clap(long, default_value = "memory")]
+    pub rate_limit_store: String,
+    /// Account made admin on startup, once its email is verified
+    #[clap(long)]
+    pub admin_email: Option<String>,
+    /// Comma separated addresses of the proxies in front of the service,
+    /// whose X-Forwarded-For header tells the client address
+    #[clap(long, value_delimiter = ',')]
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 impl Config {
@@ -34,10 +69,6 @@ impl Config {
         dotenv::dotenv().ok();
         let config = Config::parse();
 
-        if let Err(_) = env::var("BAD_WORDS_API_KEY") {
-            panic!("BadWords API key not set");
-        }
-
         if let Err(_) = env::var("PASETO_KEY") {
             panic!("PASETO_KEY not set");
         }
@@ -53,6 +84,32 @@ impl Config {
         let db_host = env::var("POSTGRES_HOST").unwrap_or(config.db_host.to_owned());
         let db_port = env::var("POSTGRES_PORT").unwrap_or(config.db_port.to_string());
         let db_name = env::var("POSTGRES_DB").unwrap_or(config.db_name.to_owned());
+        let content_filter = env::var("CONTENT_FILTER").unwrap_or(config.content_filter.to_owned());
+        let api_layer_url = env::var("API_LAYER_URL").unwrap_or(config.api_layer_url.to_owned());
+        let word_list = env::var("BAD_WORDS_FILE").unwrap_or(config.word_list.to_owned());
+        let mailer = env::var("MAILER").unwrap_or(config.mailer.to_owned());
+        let mail_from = env::var("MAIL_FROM").unwrap_or(config.mail_from.to_owned());
+        let mail_dir = env::var("MAIL_DIR").unwrap_or(config.mail_dir.to_owned());
+        let smtp_host = env::var("SMTP_HOST").unwrap_or(config.smtp_host.to_owned());
+        let smtp_port = env::var("SMTP_PORT").unwrap_or(config.smtp_port.to_string());
+        let rate_limit_store =
+            env::var("RATE_LIMIT_STORE").unwrap_or(config.rate_limit_store.to_owned());
+        let admin_email = env::var("ADMIN_EMAIL").ok().or(config.admin_email);
+        let trusted_proxies = match env::var("TRUSTED_PROXIES") {
+            Ok(proxies) => proxies
+                .split(',')
+                .filter(|proxy| !proxy.trim().is_empty())
+                .map(|proxy| {
+                    proxy.trim().parse::<IpAddr>().map_err(|_| {
+                        handle_errors::Error::ConfigurationError(format!(
+                            "Invalid trusted proxy address {}",
+                            proxy
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            Err(_) => config.trusted_proxies,
+        };
 
         Ok(Config {
             log_level: config.log_level,
@@ -64,6 +121,19 @@ impl Config {
                 .parse::<u16>()
                 .map_err(|e| handle_errors::Error::ParseError(e))?,
             db_name,
+            content_filter,
+            api_layer_url,
+            word_list,
+            mailer,
+            mail_from,
+            mail_dir,
+            smtp_host,
+            smtp_port: smtp_port
+                .parse::<u16>()
+                .map_err(handle_errors::Error::ParseError)?,
+            rate_limit_store,
+            admin_email,
+            trusted_proxies,
         })
     }
 }
@@ -99,6 +169,17 @@ mod config_tests {
             db_host: "localhost".to_string(),
             db_port: 5432,
             db_name: "rustwebdev".to_string(),
+            content_filter: "api-layer".to_string(),
+            api_layer_url: "https://api.apilayer.com".to_string(),
+            word_list: "bad_words.txt".to_string(),
+            mailer: "file".to_string(),
+            mail_from: "Q&A <noreply@localhost>".to_string(),
+            mail_dir: "mails".to_string(),
+            smtp_host: "localhost".to_string(),
+            smtp_port: 587,
+            rate_limit_store: "memory".to_string(),
+            admin_email: None,
+            trusted_proxies: Vec::new(),
         };
 
         let config = Config::new().unwrap();
@@ -1,23 +1,54 @@
 #![warn(clippy::all)]
+// The route filters nest deeper than the default limit
+#![recursion_limit = "256"]
 
 pub use handle_errors;
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{oneshot, oneshot::Sender};
 
 use tracing_subscriber::fmt::format::FmtSpan;
-use warp::{http::Method, Filter, Reply};
+use utoipa::OpenApi;
+use warp::{
+    http::{HeaderMap, HeaderValue, Method},
+    reply::Response,
+    Filter, Rejection, Reply,
+};
 
 pub mod config;
-mod profanity;
+pub mod mailer;
+pub mod openapi;
+pub mod profanity;
+pub mod rate_limit;
 mod routes;
 mod store;
 pub mod types;
 
+const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Longest request id taken from the client, longer ones get replaced
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+/// How often the rate limit buckets and failed logins which no longer count
+/// get dropped
+const PRUNE_INTERVAL_SECONDS: u64 = 60;
+
 pub struct OneshotHandler {
     pub sender: Sender<i32>,
 }
 
-async fn build_routes(store: store::Store) -> impl Filter<Extract = impl Reply> + Clone {
+async fn build_routes(
+    store: store::Store,
+    content_filter: profanity::SharedContentFilter,
+    mailer: mailer::SharedMailer,
+    rate_limiter: rate_limit::SharedRateLimiter,
+    trusted_proxies: Vec<IpAddr>,
+) -> impl Filter<Extract = impl Reply> + Clone {
+    let trusted_proxies: Arc<[IpAddr]> = trusted_proxies.into();
+    let auth = routes::authentication::auth(store.clone());
     let store_filter = warp::any().map(move || store.clone());
+    let content_filter = warp::any().map(move || content_filter.clone());
+    let mailer = warp::any().map(move || mailer.clone());
 
     let cors = warp::cors()
         .allow_any_origin()
@@ -25,7 +56,7 @@ async fn build_routes(store: store::Store) -> impl Filter<Extract = impl Reply>
         .allow_methods(&[Method::PUT, Method::DELETE, Method::GET, Method::POST]);
 
     let get_questions = warp::get()
-        .and(warp::path("questions"))
+        .and(segment("questions"))
         .and(warp::path::end())
         .and(warp::query())
         .and(store_filter.clone())
@@ -39,63 +70,383 @@ async fn build_routes(store: store::Store) -> impl Filter<Extract = impl Reply>
             )
         }));
 
+    let get_question = warp::get()
+        .and(segment("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(routes::question::get_question);
+
     let update_question = warp::put()
-        .and(warp::path("questions"))
+        .and(segment("questions"))
         .and(warp::path::param::<i32>())
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(auth.clone())
         .and(store_filter.clone())
+        .and(content_filter.clone())
         .and(warp::body::json())
         .and_then(routes::question::update_question);
 
     let delete_question = warp::delete()
-        .and(warp::path("questions"))
+        .and(segment("questions"))
         .and(warp::path::param::<i32>())
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(auth.clone())
         .and(store_filter.clone())
         .and_then(routes::question::delete_question);
 
     let add_question = warp::post()
-        .and(warp::path("questions"))
+        .and(segment("questions"))
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(auth.clone())
         .and(store_filter.clone())
+        .and(content_filter.clone())
         .and(warp::body::json())
         .and_then(routes::question::add_question);
 
     let add_answer = warp::post()
-        .and(warp::path("comments"))
+        .and(segment("comments"))
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(auth.clone())
         .and(store_filter.clone())
-        .and(warp::body::form())
+        .and(content_filter.clone())
+        .and(warp::body::json())
         .and_then(routes::answer::add_answer);
 
+    let get_answer = warp::get()
+        .and(segment("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(routes::answer::get_answer);
+
+    let get_answers = warp::get()
+        .and(segment("questions"))
+        .and(warp::path::param::<i32>())
+        .and(segment("answers"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(store_filter.clone())
+        .and_then(routes::answer::get_answers);
+
+    let update_answer = warp::put()
+        .and(segment("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and(content_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::answer::update_answer);
+
+    let delete_answer = warp::delete()
+        .and(segment("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::answer::delete_answer);
+
+    let vote_question = warp::post()
+        .and(segment("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::param::<types::vote::Vote>())
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::vote::vote_question);
+
+    let unvote_question = warp::delete()
+        .and(segment("questions"))
+        .and(warp::path::param::<i32>())
+        .and(segment("vote"))
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::vote::unvote_question);
+
+    let vote_answer = warp::post()
+        .and(segment("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::param::<types::vote::Vote>())
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::vote::vote_answer);
+
+    let unvote_answer = warp::delete()
+        .and(segment("answers"))
+        .and(warp::path::param::<i32>())
+        .and(segment("vote"))
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::vote::unvote_answer);
+
+    let accept_answer = warp::post()
+        .and(segment("answers"))
+        .and(warp::path::param::<i32>())
+        .and(segment("accept"))
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::answer::accept_answer);
+
     let registration = warp::post()
-        .and(warp::path("registration"))
+        .and(segment("registration"))
         .and(warp::path::end())
         .and(store_filter.clone())
+        .and(mailer.clone())
         .and(warp::body::json())
         .and_then(routes::authentication::register);
 
     let login = warp::post()
-        .and(warp::path("login"))
+        .and(segment("login"))
         .and(warp::path::end())
+        .and(rate_limit::by_ip(
+            rate_limiter.clone(),
+            trusted_proxies.clone(),
+            "login",
+            routes::authentication::LOGIN_IP_QUOTA,
+        ))
         .and(store_filter.clone())
-        .and(warp::body::json())
+        .and(rate_limit::by_body(
+            rate_limiter.clone(),
+            "login",
+            routes::authentication::LOGIN_ACCOUNT_QUOTA,
+            |account: &types::account::Account| account.email.trim().to_lowercase(),
+        ))
         .and_then(routes::authentication::login);
 
-    get_questions
+    let verify_email = warp::post()
+        .and(segment("verify-email"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::verification::verify_email);
+
+    let resend_verification = warp::post()
+        .and(segment("verify-email"))
+        .and(segment("resend"))
+        .and(warp::path::end())
+        .and(rate_limit::by_ip(
+            rate_limiter.clone(),
+            trusted_proxies.clone(),
+            "resend_verification",
+            routes::verification::EMAIL_IP_QUOTA,
+        ))
+        .and(store_filter.clone())
+        .and(mailer.clone())
+        .and(rate_limit::by_body(
+            rate_limiter.clone(),
+            "resend_verification",
+            routes::verification::EMAIL_ADDRESS_QUOTA,
+            |request: &types::account::EmailRequest| request.email.trim().to_lowercase(),
+        ))
+        .and_then(routes::verification::resend_verification);
+
+    let request_password_reset = warp::post()
+        .and(segment("password-reset"))
+        .and(warp::path::end())
+        .and(rate_limit::by_ip(
+            rate_limiter.clone(),
+            trusted_proxies,
+            "password_reset",
+            routes::verification::EMAIL_IP_QUOTA,
+        ))
+        .and(store_filter.clone())
+        .and(mailer.clone())
+        .and(rate_limit::by_body(
+            rate_limiter,
+            "password_reset",
+            routes::verification::EMAIL_ADDRESS_QUOTA,
+            |request: &types::account::EmailRequest| request.email.trim().to_lowercase(),
+        ))
+        .and_then(routes::verification::request_password_reset);
+
+    let confirm_password_reset = warp::post()
+        .and(segment("password-reset"))
+        .and(segment("confirm"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::verification::confirm_password_reset);
+
+    let get_me = warp::get()
+        .and(segment("accounts"))
+        .and(segment("me"))
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::account::get_me);
+
+    let change_password = warp::put()
+        .and(segment("accounts"))
+        .and(segment("me"))
+        .and(segment("password"))
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::account::change_password);
+
+    let delete_me = warp::delete()
+        .and(segment("accounts"))
+        .and(segment("me"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::account::delete_me);
+
+    let get_accounts = warp::get()
+        .and(segment("accounts"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::account::get_accounts);
+
+    let update_role = warp::put()
+        .and(segment("accounts"))
+        .and(warp::path::param::<i32>())
+        .and(segment("role"))
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::account::update_role);
+
+    let refresh = warp::post()
+        .and(segment("token"))
+        .and(segment("refresh"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::authentication::refresh);
+
+    let logout = warp::post()
+        .and(segment("logout"))
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::authentication::logout);
+
+    let openapi_json = warp::get()
+        .and(segment("openapi.json"))
+        .and(warp::path::end())
+        .map(|| warp::reply::json(&openapi::ApiDoc::openapi()));
+
+    let swagger_ui = warp::get()
+        .and(segment("docs"))
+        .and(warp::path::end())
+        .map(|| warp::reply::html(openapi::swagger_ui()));
+
+    let swagger_ui_assets = warp::get()
+        .and(segment("docs"))
+        .and(warp::fs::dir(openapi::SWAGGER_UI_DIR));
+
+    let health = warp::get()
+        .and(segment("health"))
+        .and(warp::path::end())
+        .and_then(routes::health::health);
+
+    let ready = warp::get()
+        .and(segment("ready"))
+        .and(warp::path::end())
+        .and(store_filter.clone())
+        .and_then(routes::health::ready);
+
+    // Breaking changes to the API get a new version prefix, health checks
+    // are about the server and stay outside of it
+    let api = get_questions
+        .or(get_question)
         .or(update_question)
         .or(add_question)
         .or(add_answer)
+        .or(get_answer)
+        .or(get_answers)
+        .or(update_answer)
+        .or(delete_answer)
+        .or(vote_question)
+        .or(unvote_question)
+        .or(vote_answer)
+        .or(unvote_answer)
+        .or(accept_answer)
         .or(registration)
         .or(delete_question)
         .or(login)
+        .or(refresh)
+        .or(logout)
+        .or(verify_email)
+        .or(resend_verification)
+        .or(request_password_reset)
+        .or(confirm_password_reset)
+        .or(get_me)
+        .or(change_password)
+        .or(delete_me)
+        .or(get_accounts)
+        .or(update_role)
+        .or(openapi_json)
+        .or(swagger_ui)
+        .or(swagger_ui_assets);
+
+    let routes = segment("v1")
+        .and(api)
+        .or(health)
+        .or(ready)
         .with(cors)
-        .with(warp::trace::request())
-        .recover(handle_errors::return_error)
+        .with(warp::trace::request());
+
+    // Errors get answered with the id of the request, which every response
+    // carries in its X-Request-Id header
+    request_id()
+        .and(
+            routes
+                .map(|reply| Ok(Reply::into_response(reply)))
+                .or_else(|r| async { Ok::<_, Infallible>((Err(r),)) }),
+        )
+        .map(|id: String, res: Result<Response, Rejection>| {
+            let mut res = res.unwrap_or_else(|r| handle_errors::return_error(r, &id));
+            if let Ok(value) = HeaderValue::from_str(&id) {
+                res.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+            res
+        })
+}
+
+/// Filter matching a literal path segment. Test builds record every segment
+/// `build_routes` matches, to check they all show up in the API document.
+fn segment(name: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    #[cfg(test)]
+    SEGMENTS.lock().unwrap().insert(name);
+    warp::path(name)
+}
+
+#[cfg(test)]
+static SEGMENTS: std::sync::Mutex<std::collections::BTreeSet<&str>> =
+    std::sync::Mutex::new(std::collections::BTreeSet::new());
+
+/// Id of the request, taken from the X-Request-Id header when the client or a
+/// proxy sent a sensible one
+fn request_id() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: HeaderMap| {
+        match headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(id)
+                if !id.is_empty()
+                    && id.len() <= MAX_REQUEST_ID_LENGTH
+                    && id
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+            {
+                id.to_string()
+            }
+            _ => uuid::Uuid::new_v4().to_string(),
+        }
+    })
 }
 
 pub async fn setup_store(config: &config::Config) -> Result<store::Store, handle_errors::Error> {
@@ -127,13 +478,80 @@ pub async fn setup_store(config: &config::Config) -> Result<store::Store, handle
     Ok(store)
 }
 
-pub async fn run(config: config::Config, store: store::Store) {
-    let routes = build_routes(store).await;
+/// Make the configured account an admin, the first one has no other way to
+/// get there. It has to be registered with a verified email first.
+pub async fn setup_admin(
+    config: &config::Config,
+    store: &store::Store,
+) -> Result<(), handle_errors::Error> {
+    if let Some(email) = &config.admin_email {
+        match store.make_admin(email).await? {
+            Some(account) => tracing::info!("Account {} is an admin", account.id.0),
+            None => tracing::warn!(
+                "No account with the verified email {} to make admin, register it and restart",
+                email
+            ),
+        }
+    }
+    Ok(())
+}
+
+pub fn setup_content_filter(
+    config: &config::Config,
+) -> Result<profanity::SharedContentFilter, handle_errors::Error> {
+    profanity::from_config(config)
+}
+
+pub fn setup_mailer(config: &config::Config) -> Result<mailer::SharedMailer, handle_errors::Error> {
+    mailer::from_config(config)
+}
+
+pub fn setup_rate_limiter(
+    config: &config::Config,
+    store: &store::Store,
+) -> Result<rate_limit::SharedRateLimiter, handle_errors::Error> {
+    rate_limit::from_config(config, &store.connection)
+}
+
+/// Drop the rate limit buckets and failed logins which no longer count, for
+/// as long as the service runs. Failures get logged where they happen.
+async fn prune(store: store::Store, rate_limiter: rate_limit::SharedRateLimiter) {
+    let forget_after = chrono::Duration::minutes(routes::authentication::LOCKOUT_MINUTES);
+    let mut interval = tokio::time::interval(Duration::from_secs(PRUNE_INTERVAL_SECONDS));
+    loop {
+        interval.tick().await;
+        let _ = rate_limiter.prune().await;
+        let _ = store.prune_login_failures(forget_after).await;
+    }
+}
+
+pub async fn run(
+    config: config::Config,
+    store: store::Store,
+    content_filter: profanity::SharedContentFilter,
+    mailer: mailer::SharedMailer,
+    rate_limiter: rate_limit::SharedRateLimiter,
+) {
+    tokio::spawn(prune(store.clone(), rate_limiter.clone()));
+
+    let routes = build_routes(
+        store,
+        content_filter,
+        mailer,
+        rate_limiter,
+        config.trusted_proxies,
+    )
+    .await;
     warp::serve(routes).run(([0, 0, 0, 0], config.port)).await;
 }
 
-pub async fn oneshot(store: store::Store) -> OneshotHandler {
-    let routes = build_routes(store).await;
+pub async fn oneshot(
+    store: store::Store,
+    content_filter: profanity::SharedContentFilter,
+    mailer: mailer::SharedMailer,
+    rate_limiter: rate_limit::SharedRateLimiter,
+) -> OneshotHandler {
+    let routes = build_routes(store, content_filter, mailer, rate_limiter, Vec::new()).await;
     let (tx, rx) = oneshot::channel::<i32>();
 
     let socket: std::net::SocketAddr = "127.0.0.1:3030"
@@ -149,3 +567,135 @@ pub async fn oneshot(store: store::Store) -> OneshotHandler {
 
     OneshotHandler { sender: tx }
 }
+
+#[cfg(test)]
+mod lib_tests {
+    use super::request_id;
+    use handle_errors::{return_error, Error};
+    use warp::{hyper::body::to_bytes, Rejection};
+
+    async fn body(res: warp::reply::Response) -> serde_json::Value {
+        serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn request_id_from_header() {
+        let id = warp::test::request()
+            .header("x-request-id", "b5f0c5a4-proxy")
+            .filter(&request_id())
+            .await
+            .unwrap();
+        assert_eq!(id, "b5f0c5a4-proxy");
+
+        // Ids which could mess up the logs get replaced
+        let id = warp::test::request()
+            .header("x-request-id", "a b\"c")
+            .filter(&request_id())
+            .await
+            .unwrap();
+        assert!(uuid::Uuid::parse_str(&id).is_ok());
+    }
+
+    #[tokio::test]
+    async fn json_error_body() {
+        let res = return_error(
+            warp::reject::custom(Error::InvalidParameter("sort=up".to_string())),
+            "b5f0c5a4",
+        );
+
+        assert_eq!(res.status(), 400);
+        assert_eq!(
+            body(res).await,
+            serde_json::json!({
+                "code": "invalid_parameter",
+                "message": "Invalid parameter: sort=up",
+                "details": { "parameter": "sort=up" },
+                "request_id": "b5f0c5a4",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_after() {
+        let res = return_error(warp::reject::custom(Error::TooManyRequests(12)), "1");
+
+        assert_eq!(res.status(), 429);
+        assert_eq!(res.headers()["retry-after"], "12");
+    }
+
+    #[tokio::test]
+    async fn server_errors_hide_their_cause() {
+        let res = return_error(
+            warp::reject::custom(Error::ConfigurationError("PASETO_KEY".to_string())),
+            "1",
+        );
+        assert_eq!(res.status(), 500);
+        assert_eq!(body(res).await["message"], "Internal Server Error");
+
+        let res = return_error(
+            warp::reject::custom(Error::DatabaseQueryError(sqlx::Error::PoolTimedOut)),
+            "1",
+        );
+        assert_eq!(res.status(), 500);
+        assert_eq!(body(res).await["code"], "database_error");
+    }
+
+    #[tokio::test]
+    async fn unknown_route() {
+        let res = return_error(warp::reject::not_found(), "1");
+
+        assert_eq!(res.status(), 404);
+        assert_eq!(body(res).await["code"], "route_not_found");
+    }
+
+    /// Status and code answered for the rejection
+    async fn status_and_code(r: Rejection) -> (u16, String) {
+        let res = return_error(r, "1");
+        let status = res.status().as_u16();
+        let code = body(res).await["code"].as_str().unwrap().to_string();
+        (status, code)
+    }
+
+    #[tokio::test]
+    async fn warp_rejections() {
+        let r = warp::test::request()
+            .method("DELETE")
+            .filter(&warp::get())
+            .await
+            .unwrap_err();
+        assert_eq!(status_and_code(r).await, (405, "method_not_allowed".into()));
+
+        let r = warp::test::request()
+            .method("POST")
+            .header("content-type", "text/plain")
+            .body("{}")
+            .filter(&warp::body::json::<serde_json::Value>())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            status_and_code(r).await,
+            (415, "unsupported_media_type".into())
+        );
+
+        let r = warp::test::request()
+            .method("POST")
+            .body("too long")
+            .filter(&warp::body::content_length_limit(4))
+            .await
+            .unwrap_err();
+        assert_eq!(status_and_code(r).await, (413, "payload_too_large".into()));
+
+        let r = warp::test::request()
+            .path("/?keep_content=maybe")
+            .filter(&warp::query::<std::collections::HashMap<String, bool>>())
+            .await
+            .unwrap_err();
+        assert_eq!(status_and_code(r).await, (400, "invalid_query".into()));
+
+        let r = warp::test::request()
+            .filter(&warp::header::<String>("authorization"))
+            .await
+            .unwrap_err();
+        assert_eq!(status_and_code(r).await, (400, "missing_header".into()));
+    }
+}
@@ -0,0 +1,222 @@
+use async_trait::async_trait;
+use lettre::message::{header::ContentType, Mailbox, Message};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::config::Config;
+
+/// An email to one recipient, in plain text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Sends the emails of the account flows, like verification and password
+/// resets
+#[async_trait]
+pub trait Mailer: std::fmt::Debug + Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), handle_errors::Error>;
+}
+
+pub type SharedMailer = Arc<dyn Mailer>;
+
+/// Build the mailer selected in the config
+pub fn from_config(config: &Config) -> Result<SharedMailer, handle_errors::Error> {
+    let from = config.mail_from.parse::<Mailbox>().map_err(|e| {
+        handle_errors::Error::ConfigurationError(format!(
+            "Invalid sender {}: {}",
+            config.mail_from, e
+        ))
+    })?;
+
+    match config.mailer.as_str() {
+        "smtp" => {
+            let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+                (Ok(username), Ok(password)) => Some(Credentials::new(username, password)),
+                _ => None,
+            };
+            Ok(Arc::new(SmtpMailer::new(
+                &config.smtp_host,
+                config.smtp_port,
+                credentials,
+                from,
+            )?))
+        }
+        "file" => Ok(Arc::new(FileMailer::new(&config.mail_dir, from))),
+        other => Err(handle_errors::Error::ConfigurationError(format!(
+            "Unknown mailer {}, expected smtp or file",
+            other
+        ))),
+    }
+}
+
+fn build_message(from: &Mailbox, email: Email) -> Result<Message, handle_errors::Error> {
+    let to = email
+        .to
+        .parse::<Mailbox>()
+        .map_err(|e| handle_errors::Error::MailerError(e.to_string()))?;
+
+    Message::builder()
+        .from(from.clone())
+        .to(to)
+        .subject(email.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(email.body)
+        .map_err(|e| handle_errors::Error::MailerError(e.to_string()))
+}
+
+/// Mailer handing the emails to an SMTP relay
+#[derive(Debug, Clone)]
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    /// Connects with STARTTLS when given credentials. Without them the relay
+    /// is expected to be a local one, like a development mail catcher, and
+    /// the connection is not encrypted.
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<Credentials>,
+        from: Mailbox,
+    ) -> Result<Self, handle_errors::Error> {
+        let transport = match credentials {
+            Some(credentials) => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .map_err(|e| handle_errors::Error::ConfigurationError(e.to_string()))?
+                .port(port)
+                .credentials(credentials)
+                .build(),
+            None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+                .port(port)
+                .build(),
+        };
+
+        Ok(SmtpMailer { transport, from })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<(), handle_errors::Error> {
+        let message = build_message(&self.from, email)?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| handle_errors::Error::MailerError(e.to_string()))?;
+        Ok(())
+    }
+}
+
+/// Mailer writing each email to an `.eml` file of a directory, for local
+/// development
+#[derive(Debug, Clone)]
+pub struct FileMailer {
+    dir: PathBuf,
+    from: Mailbox,
+}
+
+impl FileMailer {
+    pub fn new(dir: &str, from: Mailbox) -> Self {
+        FileMailer {
+            dir: PathBuf::from(dir),
+            from,
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), handle_errors::Error> {
+        let message = build_message(&self.from, email)?;
+        let path = self.dir.join(format!("{}.eml", uuid::Uuid::new_v4()));
+
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| handle_errors::Error::MailerError(e.to_string()))?;
+        tokio::fs::write(&path, message.formatted())
+            .await
+            .map_err(|e| handle_errors::Error::MailerError(e.to_string()))?;
+
+        tracing::event!(tracing::Level::INFO, "Email written to {}", path.display());
+        Ok(())
+    }
+}
+
+/// Mailer keeping the emails in memory, for tests to read them back
+#[derive(Debug, Default)]
+pub struct MemoryMailer {
+    sent: Mutex<Vec<Email>>,
+}
+
+impl MemoryMailer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every email sent so far, oldest first
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, email: Email) -> Result<(), handle_errors::Error> {
+        self.sent.lock().unwrap().push(email);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod mailer_tests {
+    use super::{build_message, Email, FileMailer, Mailer, MemoryMailer};
+
+    fn email() -> Email {
+        Email {
+            to: "jane@example.com".to_string(),
+            subject: "Hello".to_string(),
+            body: "How are you?".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn memory() {
+        let mailer = MemoryMailer::new();
+        mailer.send(email()).await.unwrap();
+
+        assert_eq!(mailer.sent(), vec![email()]);
+    }
+
+    #[tokio::test]
+    async fn file() {
+        let dir = std::env::temp_dir().join(format!("mails-{}", uuid::Uuid::new_v4()));
+        let mailer = FileMailer::new(
+            dir.to_str().unwrap(),
+            "Q&A <noreply@example.com>".parse().unwrap(),
+        );
+        mailer.send(email()).await.unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let content = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert!(content.contains("To: jane@example.com"));
+        assert!(content.contains("How are you?"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_recipient() {
+        let mut email = email();
+        email.to = "not an address".to_string();
+
+        assert!(build_message(&"noreply@example.com".parse().unwrap(), email).is_err());
+    }
+}
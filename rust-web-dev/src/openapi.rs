@@ -0,0 +1,343 @@
+use std::path::Path;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::routes::{account, answer, authentication, health, question, verification, vote};
+use crate::types::{
+    account::{
+        Account, AccountId, EmailRequest, PasswordChange, PasswordReset, Profile, RefreshRequest,
+        Role, RoleUpdate, TokenPair, VerificationRequest,
+    },
+    answer::{Answer, AnswerId, NewAnswer, UpdateAnswer},
+    health::{Health, MigrationStatus, Readiness},
+    pagination::{AnswerPage, QuestionPage},
+    question::{NewQuestion, Question, QuestionId, QuestionWithAnswers},
+    vote::Score,
+};
+
+/// OpenAPI document of the service. The schemas are derived from the types,
+/// the operations are written by hand as `#[utoipa::path]` attributes on
+/// the route handlers, and the tests below check them against the routes
+/// both ways so neither can change without the other.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Q&A web service API",
+        description = "Questions and answers, with accounts to post them"
+    ),
+    paths(
+        question::get_questions,
+        question::get_question,
+        question::add_question,
+        question::update_question,
+        question::delete_question,
+        answer::get_answers,
+        answer::get_answer,
+        answer::add_answer,
+        answer::update_answer,
+        answer::delete_answer,
+        answer::accept_answer,
+        vote::vote_question,
+        vote::unvote_question,
+        vote::vote_answer,
+        vote::unvote_answer,
+        authentication::register,
+        authentication::login,
+        authentication::refresh,
+        authentication::logout,
+        verification::verify_email,
+        verification::resend_verification,
+        verification::request_password_reset,
+        verification::confirm_password_reset,
+        account::get_me,
+        account::change_password,
+        account::delete_me,
+        account::get_accounts,
+        account::update_role,
+        health::health,
+        health::ready,
+    ),
+    components(schemas(
+        Question,
+        QuestionId,
+        NewQuestion,
+        QuestionWithAnswers,
+        QuestionPage,
+        Answer,
+        AnswerId,
+        NewAnswer,
+        UpdateAnswer,
+        AnswerPage,
+        Score,
+        Account,
+        AccountId,
+        Role,
+        RoleUpdate,
+        Profile,
+        TokenPair,
+        RefreshRequest,
+        EmailRequest,
+        VerificationRequest,
+        PasswordReset,
+        PasswordChange,
+        Health,
+        Readiness,
+        MigrationStatus,
+        handle_errors::ErrorBody,
+    )),
+    modifiers(&AccessToken, &Unlicensed),
+    tags(
+        (name = "questions"),
+        (name = "answers"),
+        (name = "votes", description = "One vote per account on each question and answer"),
+        (name = "authentication", description = "Registration, login and sessions"),
+        (name = "accounts"),
+        (name = "health", description = "Checks for orchestrators and load balancers, outside of the versioned API"),
+    )
+)]
+pub struct ApiDoc;
+
+/// The access token of a login, sent as is in the `Authorization` header
+struct AccessToken;
+
+impl Modify for AccessToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "access_token",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("Authorization"))),
+            );
+        }
+    }
+}
+
+/// The crate has no license for the document to mention
+struct Unlicensed;
+
+impl Modify for Unlicensed {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi.info.license = None;
+    }
+}
+
+/// Directory the Swagger UI assets are served from, relative to the working
+/// directory. The Dockerfile fetches them from the swagger-ui-dist package.
+pub const SWAGGER_UI_DIR: &str = "swagger-ui";
+
+/// Where the page loads the assets from when SWAGGER_UI_DIR does not have
+/// them, as under `cargo run`. Pinned to the version the Dockerfile fetches.
+const SWAGGER_UI_CDN: &str = "https://unpkg.com/swagger-ui-dist@5.17.14";
+
+/// Page rendering the document with Swagger UI
+pub fn swagger_ui() -> String {
+    swagger_ui_page(Path::new(SWAGGER_UI_DIR))
+}
+
+fn swagger_ui_page(dir: &Path) -> String {
+    let assets = if dir.join("swagger-ui-bundle.js").is_file() {
+        "/v1/docs"
+    } else {
+        SWAGGER_UI_CDN
+    };
+    SWAGGER_UI.replace("{assets}", assets)
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Q&amp;A web service API</title>
+  <link rel="stylesheet" href="{assets}/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="{assets}/swagger-ui-bundle.js"></script>
+  <script>
+    window.onload = () => {
+      window.ui = SwaggerUIBundle({ url: "/v1/openapi.json", dom_id: "#swagger-ui" });
+    };
+  </script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod openapi_tests {
+    use super::{swagger_ui_page, ApiDoc, SWAGGER_UI_CDN};
+    use std::sync::Arc;
+    use std::{env, fs};
+    use utoipa::OpenApi;
+    use warp::{Filter, Reply};
+
+    use crate::mailer::MemoryMailer;
+    use crate::profanity::WordListFilter;
+    use crate::rate_limit::MemoryRateLimiter;
+    use crate::store::Store;
+
+    /// Committed copy of the document, for clients and reviewers to see the
+    /// API change
+    const SPEC_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+    #[test]
+    fn spec_is_up_to_date() {
+        let spec = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+        if env::var("UPDATE_OPENAPI").is_ok() {
+            fs::write(SPEC_FILE, &spec).unwrap();
+        }
+
+        let committed = fs::read_to_string(SPEC_FILE).unwrap_or_default();
+        assert!(
+            committed == spec,
+            "openapi.json is out of date, run `UPDATE_OPENAPI=1 cargo test openapi` and commit it"
+        );
+    }
+
+    /// Schemas the operations refer to are part of the document
+    #[test]
+    fn every_reference_resolves() {
+        fn refs(value: &serde_json::Value, found: &mut Vec<String>) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    if let Some(serde_json::Value::String(r)) = map.get("$ref") {
+                        found.push(r.clone());
+                    }
+                    map.values().for_each(|v| refs(v, found));
+                }
+                serde_json::Value::Array(items) => items.iter().for_each(|v| refs(v, found)),
+                _ => {}
+            }
+        }
+
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let mut found = Vec::new();
+        refs(&spec, &mut found);
+
+        assert!(!found.is_empty());
+        for r in found {
+            let name = r.trim_start_matches("#/components/schemas/");
+            assert!(
+                spec["components"]["schemas"].get(name).is_some(),
+                "{} is not in the components",
+                r
+            );
+        }
+    }
+
+    /// Routes of the service, without a database to reach
+    async fn routes() -> impl Filter<Extract = impl Reply> + Clone + 'static {
+        let store = Store::unreachable();
+        crate::build_routes(
+            store,
+            Arc::new(WordListFilter::new(Vec::<String>::new())),
+            Arc::new(MemoryMailer::new()),
+            Arc::new(MemoryRateLimiter::new()),
+            Vec::new(),
+        )
+        .await
+    }
+
+    /// Every documented operation has a route. Without credentials or a
+    /// database the routes fail, but with their own errors and not with
+    /// `route_not_found` or `method_not_allowed`.
+    #[tokio::test]
+    async fn every_operation_is_routed() {
+        let routes = routes().await;
+
+        for (path, item) in ApiDoc::openapi().paths.paths {
+            for method in item.operations.keys() {
+                let method = serde_json::to_value(method).unwrap();
+                let method = method.as_str().unwrap().to_uppercase();
+                let uri = path.replace("{id}", "1").replace("{vote}", "upvote");
+
+                let res = warp::test::request()
+                    .method(&method)
+                    .path(&uri)
+                    .header("content-type", "application/json")
+                    .body("{}")
+                    .reply(&routes)
+                    .await;
+                let body: serde_json::Value =
+                    serde_json::from_slice(res.body()).unwrap_or_default();
+
+                assert!(
+                    body["code"] != "route_not_found" && body["code"] != "method_not_allowed",
+                    "{} {} has no route",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+
+    /// Every route is documented: each path segment `build_routes` matches
+    /// shows up in the document, and the documented paths refuse the methods
+    /// which are not documented.
+    #[tokio::test]
+    async fn every_route_is_documented() {
+        // Routes serving the document itself
+        const UNDOCUMENTED: [&str; 2] = ["openapi.json", "docs"];
+        let paths = ApiDoc::openapi().paths.paths;
+
+        let routes = routes().await;
+        let segments = crate::SEGMENTS.lock().unwrap().clone();
+        for segment in segments {
+            assert!(
+                UNDOCUMENTED.contains(&segment)
+                    || paths
+                        .keys()
+                        .any(|path| path.split('/').any(|part| part == segment)),
+                "The {} route is not documented",
+                segment
+            );
+        }
+
+        for (path, item) in paths {
+            let documented: Vec<String> = item
+                .operations
+                .keys()
+                .map(|method| {
+                    let method = serde_json::to_value(method).unwrap();
+                    method.as_str().unwrap().to_uppercase()
+                })
+                .collect();
+            let uri = path.replace("{id}", "1").replace("{vote}", "upvote");
+
+            for method in ["GET", "POST", "PUT", "PATCH", "DELETE"] {
+                if documented.iter().any(|m| m == method) {
+                    continue;
+                }
+                let res = warp::test::request()
+                    .method(method)
+                    .path(&uri)
+                    .header("content-type", "application/json")
+                    .body("{}")
+                    .reply(&routes)
+                    .await;
+                let body: serde_json::Value =
+                    serde_json::from_slice(res.body()).unwrap_or_default();
+
+                assert_eq!(
+                    body["code"], "method_not_allowed",
+                    "{} {} is routed but not documented",
+                    method, path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn swagger_ui_assets_fall_back_to_the_cdn() {
+        let dir = env::temp_dir().join(format!("swagger-ui-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let page = swagger_ui_page(&dir);
+        assert!(page.contains(&format!("{}/swagger-ui-bundle.js", SWAGGER_UI_CDN)));
+
+        fs::write(dir.join("swagger-ui-bundle.js"), "").unwrap();
+        let page = swagger_ui_page(&dir);
+        assert!(page.contains("\"/v1/docs/swagger-ui-bundle.js\""));
+        assert!(!page.contains(SWAGGER_UI_CDN));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
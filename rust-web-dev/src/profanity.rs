@@ -1,7 +1,41 @@
-use reqwest_middleware::ClientBuilder;
+use async_trait::async_trait;
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use serde::{Deserialize, Serialize};
-use std::env;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::{env, fs};
+
+use crate::config::Config;
+
+/// Censors user content before it gets stored
+#[async_trait]
+pub trait ContentFilter: std::fmt::Debug + Send + Sync {
+    /// Returns the content with its profane words masked
+    async fn censor(&self, content: String) -> Result<String, handle_errors::Error>;
+}
+
+pub type SharedContentFilter = Arc<dyn ContentFilter>;
+
+/// Build the content filter selected in the config
+pub fn from_config(config: &Config) -> Result<SharedContentFilter, handle_errors::Error> {
+    match config.content_filter.as_str() {
+        "api-layer" => {
+            let api_key = env::var("BAD_WORDS_API_KEY").map_err(|_| {
+                handle_errors::Error::ConfigurationError("BAD_WORDS_API_KEY not set".to_string())
+            })?;
+            Ok(Arc::new(APILayerFilter::new(
+                &config.api_layer_url,
+                api_key,
+            )))
+        }
+        "word-list" => Ok(Arc::new(WordListFilter::from_file(&config.word_list)?)),
+        other => Err(handle_errors::Error::ConfigurationError(format!(
+            "Unknown content filter {}, expected api-layer or word-list",
+            other
+        ))),
+    }
+}
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct APIResponse {
@@ -26,65 +60,186 @@ struct BadWordsResponse {
     censored_content: String,
 }
 
-pub async fn check_profanity(content: String) -> Result<String, handle_errors::Error> {
-    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
-    let client = ClientBuilder::new(reqwest::Client::new())
-        // Trace HTTP requests. See the tracing crate to make use of these traces.
-        // Retry failed requests.
-        .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-        .build();
-    let api_key = env::var("BAD_WORDS_API_KEY").unwrap();
-    let res = client
-        .post("https://api.apilayer.com/bad_words?censor_character=*")
-        .header("apikey", api_key)
-        .body(content)
-        .send()
-        .await
-        // Map error to a different type since we cannot implement a trait for
-        // a type we don't own, and need this one to implement warp::Reject
-        .map_err(|e| handle_errors::Error::MiddlewareReqwestAPIError(e))?;
-
-    if !res.status().is_success() {
-        if res.status().is_client_error() {
-            let err = transform_error(res).await;
-            return Err(handle_errors::Error::ClientError(err));
-        } else {
-            let err = transform_error(res).await;
-            return Err(handle_errors::Error::ServerError(err));
+/// Filter backed by the APILayer Bad Words API
+#[derive(Debug, Clone)]
+pub struct APILayerFilter {
+    /// Where the API lives, `https://api.apilayer.com` unless a mock stands in
+    base_url: String,
+    api_key: String,
+    client: ClientWithMiddleware,
+}
+
+impl APILayerFilter {
+    pub fn new(base_url: &str, api_key: String) -> Self {
+        let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
+        let client = ClientBuilder::new(reqwest::Client::new())
+            // Trace HTTP requests. See the tracing crate to make use of these traces.
+            // Retry failed requests.
+            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+            .build();
+
+        APILayerFilter {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            client,
         }
     }
+}
 
-    match res.json::<BadWordsResponse>().await {
-        Ok(res) => Ok(res.censored_content),
-        Err(e) => Err(handle_errors::Error::ReqwestAPIError(e)),
+#[async_trait]
+impl ContentFilter for APILayerFilter {
+    async fn censor(&self, content: String) -> Result<String, handle_errors::Error> {
+        let res = self
+            .client
+            .post(format!("{}/bad_words?censor_character=*", self.base_url))
+            .header("apikey", &self.api_key)
+            .body(content)
+            .send()
+            .await
+            // Map error to a different type since we cannot implement a trait for
+            // a type we don't own, and need this one to implement warp::Reject
+            .map_err(handle_errors::Error::MiddlewareReqwestAPIError)?;
+
+        if !res.status().is_success() {
+            if res.status().is_client_error() {
+                let err = transform_error(res).await;
+                return Err(handle_errors::Error::ClientError(err));
+            } else {
+                let err = transform_error(res).await;
+                return Err(handle_errors::Error::ServerError(err));
+            }
+        }
+
+        match res.json::<BadWordsResponse>().await {
+            Ok(res) => Ok(res.censored_content),
+            Err(e) => Err(handle_errors::Error::ReqwestAPIError(e)),
+        }
     }
 }
 
+/// Error the API answered with. Gateways in front of it answer errors with
+/// bodies of their own, which get the status text for a message.
 async fn transform_error(res: reqwest::Response) -> handle_errors::APILayerError {
+    let status = res.status();
+    let message = match res.json::<APIResponse>().await {
+        Ok(body) => body.message,
+        Err(_) => status
+            .canonical_reason()
+            .unwrap_or("Unknown error")
+            .to_string(),
+    };
+
     handle_errors::APILayerError {
-        status: res.status().as_u16(),
-        message: res.json::<APIResponse>().await.unwrap().message,
+        status: status.as_u16(),
+        message,
     }
 }
 
+/// Offline filter masking the words of a list, matched as whole words and
+/// seen through leetspeak: with `shit` listed, `sh1t` and `$hit` get masked
+/// but `shitake` does not.
+#[derive(Debug, Clone)]
+pub struct WordListFilter {
+    words: HashSet<String>,
+}
+
+impl WordListFilter {
+    pub fn new<I, S>(words: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        WordListFilter {
+            words: words
+                .into_iter()
+                .map(|word| normalise(word.as_ref().trim()))
+                .filter(|word| !word.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Read the list from a file with a word per line. Blank lines and lines
+    /// starting with `#` are skipped.
+    pub fn from_file(path: &str) -> Result<Self, handle_errors::Error> {
+        let list = fs::read_to_string(path).map_err(|e| {
+            handle_errors::Error::ConfigurationError(format!("Cannot read {}: {}", path, e))
+        })?;
+
+        Ok(Self::new(
+            list.lines()
+                .filter(|line| !line.trim_start().starts_with('#')),
+        ))
+    }
+
+    fn mask(&self, content: &str) -> String {
+        let mut censored = String::with_capacity(content.len());
+        let mut word = String::new();
+
+        for c in content.chars().chain(std::iter::once(' ')) {
+            if is_word_char(c) {
+                word.push(c);
+                continue;
+            }
+            if self.words.contains(&normalise(&word)) {
+                censored.push_str(&"*".repeat(word.chars().count()));
+            } else {
+                censored.push_str(&word);
+            }
+            word.clear();
+            censored.push(c);
+        }
+        // Drop the space which flushed the last word
+        censored.pop();
+        censored
+    }
+}
+
+#[async_trait]
+impl ContentFilter for WordListFilter {
+    async fn censor(&self, content: String) -> Result<String, handle_errors::Error> {
+        Ok(self.mask(&content))
+    }
+}
+
+/// Characters which can make up a word, leetspeak substitutes included
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '@' || c == '$'
+}
+
+/// Lowercase the word and undo leetspeak substitutions
+fn normalise(word: &str) -> String {
+    word.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            '0' => 'o',
+            '1' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' => 't',
+            c => c,
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod profanity_tests {
-    use super::{check_profanity, env};
+    use super::{APILayerFilter, ContentFilter, WordListFilter};
+    use handle_errors::Error;
 
     use mock_server::{MockServer, OneshotHandler};
 
     #[tokio::test]
     async fn run() {
         let handler = run_mock();
-        censor_profane_words().await;
-        no_profane_words().await;
+        let filter = APILayerFilter::new("http://127.0.0.1:3030", "YES".to_string());
+        censor_profane_words(&filter).await;
+        no_profane_words(&filter).await;
+        error_without_json_body().await;
         let _ = handler.sender.send(1);
     }
 
     fn run_mock() -> OneshotHandler {
-        env::set_var("API_LAYER_URL", "http://127.0.0.1:3030");
-        env::set_var("BAD_WORDS_API_KEY", "YES");
-
         let socket = "127.0.0.1:3030"
             .to_string()
             .parse()
@@ -94,15 +249,43 @@ mod profanity_tests {
         mock.oneshot()
     }
 
-    async fn censor_profane_words() {
+    async fn censor_profane_words(filter: &APILayerFilter) {
         let content = "This is a shitty sentence".to_string();
-        let censored_content = check_profanity(content).await;
+        let censored_content = filter.censor(content).await;
         assert_eq!(censored_content.unwrap(), "this is a ****** sentence");
     }
 
-    async fn no_profane_words() {
+    async fn no_profane_words(filter: &APILayerFilter) {
         let content = "this is a sentence".to_string();
-        let censored_content = check_profanity(content).await;
+        let censored_content = filter.censor(content).await;
         assert_eq!(censored_content.unwrap(), "");
     }
+
+    async fn error_without_json_body() {
+        // The mock answers unknown paths with an empty 404
+        let filter = APILayerFilter::new("http://127.0.0.1:3030/missing", "YES".to_string());
+        match filter.censor("this is a sentence".to_string()).await {
+            Err(Error::ClientError(e)) => {
+                assert_eq!(e.status, 404);
+                assert_eq!(e.message, "Not Found");
+            }
+            other => panic!("expected a client error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn word_list() {
+        let filter = WordListFilter::new(["shitty", "Damn"]);
+
+        let censored = filter.censor("This is a shitty sentence".to_string()).await;
+        assert_eq!(censored.unwrap(), "This is a ****** sentence");
+
+        let censored = filter.censor("DAMN, $h1tty d4mn!".to_string()).await;
+        assert_eq!(censored.unwrap(), "****, ****** ****!");
+
+        let censored = filter
+            .censor("Damnation is not shittyish".to_string())
+            .await;
+        assert_eq!(censored.unwrap(), "Damnation is not shittyish");
+    }
 }
@@ -0,0 +1,400 @@
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use sqlx::postgres::{PgPool, PgRow};
+use sqlx::Row;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use warp::Filter;
+
+use crate::config::Config;
+
+/// Number of buckets the in-memory store holds at most
+const MAX_MEMORY_BUCKETS: usize = 10_000;
+
+/// Requests a client can make: bursts of up to `capacity`, refilled at
+/// `per_minute`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub capacity: u32,
+    pub per_minute: u32,
+}
+
+impl Quota {
+    fn per_second(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+
+    /// Seconds until a bucket holding `tokens` has a whole one again
+    fn retry_after(&self, tokens: f64) -> u64 {
+        ((1.0 - tokens) / self.per_second()).ceil().max(1.0) as u64
+    }
+}
+
+/// Token buckets, one per key
+#[async_trait]
+pub trait RateLimiter: std::fmt::Debug + Send + Sync {
+    /// Take a token from the bucket of the key, failing with
+    /// `Error::TooManyRequests` when it is empty
+    async fn take(&self, key: &str, quota: Quota) -> Result<(), handle_errors::Error>;
+
+    /// Drop the buckets which are full again, they count the same as
+    /// missing ones
+    async fn prune(&self) -> Result<(), handle_errors::Error>;
+}
+
+pub type SharedRateLimiter = Arc<dyn RateLimiter>;
+
+/// Build the rate limiter selected in the config
+pub fn from_config(
+    config: &Config,
+    connection: &PgPool,
+) -> Result<SharedRateLimiter, handle_errors::Error> {
+    match config.rate_limit_store.as_str() {
+        "memory" => Ok(Arc::new(MemoryRateLimiter::new())),
+        "postgres" => Ok(Arc::new(PostgresRateLimiter::new(connection.clone()))),
+        other => Err(handle_errors::Error::ConfigurationError(format!(
+            "Unknown rate limit store {}, expected memory or postgres",
+            other
+        ))),
+    }
+}
+
+/// Limit the requests per client IP address. Behind one of the
+/// `trusted_proxies` the client is the address it forwards for.
+pub fn by_ip(
+    limiter: SharedRateLimiter,
+    trusted_proxies: Arc<[IpAddr]>,
+    scope: &'static str,
+    quota: Quota,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and_then(
+            move |addr: Option<SocketAddr>, forwarded_for: Option<String>| {
+                let limiter = limiter.clone();
+                let trusted_proxies = trusted_proxies.clone();
+                async move {
+                    let ip = client_ip(
+                        addr.map(|addr| addr.ip()),
+                        forwarded_for.as_deref(),
+                        &trusted_proxies,
+                    )
+                    .map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+                    limiter
+                        .take(&format!("{}:ip:{}", scope, ip), quota)
+                        .await
+                        .map_err(warp::reject::custom)
+                }
+            },
+        )
+        .untuple_one()
+}
+
+/// Address of the client: the remote one, unless it is a trusted proxy.
+/// Then X-Forwarded-For is read from the right, each trusted proxy vouching
+/// for the address before it, and the first untrusted one is the client.
+/// Addresses further left could be made up by the client.
+fn client_ip(
+    remote: Option<IpAddr>,
+    forwarded_for: Option<&str>,
+    trusted_proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    let mut ip = remote?;
+    for hop in forwarded_for.unwrap_or_default().rsplit(',') {
+        if !trusted_proxies.contains(&ip) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(hop) => ip = hop,
+            Err(_) => break,
+        }
+    }
+    Some(ip)
+}
+
+/// Limit the requests per key of the JSON body, like the account a login is
+/// for. The body gets passed on.
+pub fn by_body<T>(
+    limiter: SharedRateLimiter,
+    scope: &'static str,
+    quota: Quota,
+    key: fn(&T) -> String,
+) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    warp::body::json().and_then(move |body: T| {
+        let limiter = limiter.clone();
+        async move {
+            limiter
+                .take(&format!("{}:{}", scope, key(&body)), quota)
+                .await
+                .map_err(warp::reject::custom)?;
+            Ok::<_, warp::Rejection>(body)
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_on: Instant,
+}
+
+impl Bucket {
+    fn refilled(&self, quota: Quota, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated_on).as_secs_f64();
+        (self.tokens + elapsed * quota.per_second()).min(f64::from(quota.capacity))
+    }
+
+    fn is_full(&self, quota: Quota, now: Instant) -> bool {
+        self.refilled(quota, now) >= f64::from(quota.capacity)
+    }
+}
+
+/// Buckets kept in the process, each instance of the service counting on
+/// its own
+#[derive(Debug, Default)]
+pub struct MemoryRateLimiter {
+    buckets: Mutex<HashMap<String, (Bucket, Quota)>>,
+}
+
+impl MemoryRateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make room for new buckets once there are `MAX_MEMORY_BUCKETS`: the
+    /// full ones go first, then the least recently used ones until half the
+    /// room is free. Freeing that much keeps the sweeps rare.
+    fn make_room(buckets: &mut HashMap<String, (Bucket, Quota)>, now: Instant) {
+        buckets.retain(|_, (bucket, quota)| !bucket.is_full(*quota, now));
+
+        let keep = MAX_MEMORY_BUCKETS / 2;
+        if buckets.len() > keep {
+            let mut updated_on: Vec<Instant> = buckets
+                .values()
+                .map(|(bucket, _)| bucket.updated_on)
+                .collect();
+            let cutoff_index = updated_on.len() - keep;
+            let (_, cutoff, _) = updated_on.select_nth_unstable(cutoff_index);
+            let cutoff = *cutoff;
+            buckets.retain(|_, (bucket, _)| bucket.updated_on > cutoff);
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimiter for MemoryRateLimiter {
+    async fn take(&self, key: &str, quota: Quota) -> Result<(), handle_errors::Error> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_MEMORY_BUCKETS && !buckets.contains_key(key) {
+            Self::make_room(&mut buckets, now);
+        }
+
+        let (bucket, _) = buckets.entry(key.to_string()).or_insert((
+            Bucket {
+                tokens: f64::from(quota.capacity),
+                updated_on: now,
+            },
+            quota,
+        ));
+        let tokens = bucket.refilled(quota, now);
+        bucket.updated_on = now;
+
+        if tokens >= 1.0 {
+            bucket.tokens = tokens - 1.0;
+            Ok(())
+        } else {
+            bucket.tokens = tokens;
+            Err(handle_errors::Error::TooManyRequests(
+                quota.retry_after(tokens),
+            ))
+        }
+    }
+
+    async fn prune(&self) -> Result<(), handle_errors::Error> {
+        let now = Instant::now();
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, (bucket, quota)| !bucket.is_full(*quota, now));
+        Ok(())
+    }
+}
+
+/// Buckets kept in the database, shared by every instance of the service
+#[derive(Debug, Clone)]
+pub struct PostgresRateLimiter {
+    connection: PgPool,
+}
+
+impl PostgresRateLimiter {
+    pub fn new(connection: PgPool) -> Self {
+        PostgresRateLimiter { connection }
+    }
+}
+
+#[async_trait]
+impl RateLimiter for PostgresRateLimiter {
+    async fn take(&self, key: &str, quota: Quota) -> Result<(), handle_errors::Error> {
+        // Tokens in the bucket once refilled, $2 being the capacity and $3
+        // the tokens added per second
+        let refilled = "LEAST($2, b.tokens + EXTRACT(EPOCH FROM NOW() - b.updated_on) * $3)";
+        let tokens = format!(
+            "CASE WHEN {refilled} >= 1 THEN {refilled} - 1 ELSE {refilled} END",
+            refilled = refilled
+        );
+        // Buckets get pruned once full again
+        let query = format!(
+            "INSERT INTO rate_limits AS b (key, tokens, allowed, updated_on, full_on)
+            VALUES ($1, $2 - 1, true, NOW(), NOW() + make_interval(secs => 1 / $3))
+            ON CONFLICT (key) DO UPDATE SET
+                tokens = {tokens},
+                allowed = {refilled} >= 1,
+                updated_on = NOW(),
+                full_on = NOW() + make_interval(secs => ($2 - {tokens}) / $3)
+            RETURNING tokens, allowed",
+            tokens = tokens,
+            refilled = refilled
+        );
+
+        match sqlx::query(&query)
+            .bind(key)
+            .bind(f64::from(quota.capacity))
+            .bind(quota.per_second())
+            .map(|row: PgRow| (row.get::<f64, _>("tokens"), row.get::<bool, _>("allowed")))
+            .fetch_one(&self.connection)
+            .await
+        {
+            Ok((_, true)) => Ok(()),
+            Ok((tokens, false)) => Err(handle_errors::Error::TooManyRequests(
+                quota.retry_after(tokens),
+            )),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(handle_errors::Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    async fn prune(&self) -> Result<(), handle_errors::Error> {
+        match sqlx::query("DELETE FROM rate_limits WHERE full_on <= NOW()")
+            .execute(&self.connection)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(handle_errors::Error::DatabaseQueryError(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod rate_limit_tests {
+    use super::{client_ip, MemoryRateLimiter, Quota, RateLimiter, MAX_MEMORY_BUCKETS};
+    use std::net::IpAddr;
+
+    const QUOTA: Quota = Quota {
+        capacity: 3,
+        per_minute: 6,
+    };
+
+    #[tokio::test]
+    async fn burst_then_limited() {
+        let limiter = MemoryRateLimiter::new();
+
+        for _ in 0..3 {
+            assert!(limiter.take("login:ip:127.0.0.1", QUOTA).await.is_ok());
+        }
+
+        let err = limiter.take("login:ip:127.0.0.1", QUOTA).await.unwrap_err();
+        assert!(matches!(
+            err,
+            handle_errors::Error::TooManyRequests(retry_after) if retry_after <= 10
+        ));
+
+        // Buckets are per key
+        assert!(limiter.take("login:ip:10.0.0.1", QUOTA).await.is_ok());
+    }
+
+    #[test]
+    fn retry_after() {
+        assert_eq!(QUOTA.retry_after(0.0), 10);
+        assert_eq!(QUOTA.retry_after(0.5), 5);
+        assert_eq!(QUOTA.retry_after(0.99), 1);
+    }
+
+    #[tokio::test]
+    async fn bucket_count_is_bounded() {
+        let limiter = MemoryRateLimiter::new();
+
+        for i in 0..MAX_MEMORY_BUCKETS {
+            limiter.take(&format!("login:{}", i), QUOTA).await.unwrap();
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_MEMORY_BUCKETS);
+
+        // Half the buckets make room for the new one, the most recent
+        // ones staying
+        limiter.take("login:new", QUOTA).await.unwrap();
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(buckets.len() <= MAX_MEMORY_BUCKETS / 2 + 1);
+        assert!(buckets.contains_key("login:new"));
+        assert!(buckets.contains_key(&format!("login:{}", MAX_MEMORY_BUCKETS - 1)));
+        assert!(!buckets.contains_key("login:0"));
+    }
+
+    #[tokio::test]
+    async fn prune_drops_full_buckets() {
+        let limiter = MemoryRateLimiter::new();
+        let fast = Quota {
+            capacity: 1,
+            per_minute: 60_000,
+        };
+        limiter.take("fast", fast).await.unwrap();
+        limiter.take("slow", QUOTA).await.unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        limiter.prune().await.unwrap();
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(!buckets.contains_key("fast"));
+        assert!(buckets.contains_key("slow"));
+    }
+
+    #[test]
+    fn client_behind_trusted_proxies() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let proxies = [ip("10.0.0.1"), ip("10.0.0.2")];
+
+        // Not a proxy, so the header could be made up
+        assert_eq!(
+            client_ip(Some(ip("1.2.3.4")), Some("5.6.7.8"), &proxies),
+            Some(ip("1.2.3.4"))
+        );
+        assert_eq!(
+            client_ip(Some(ip("10.0.0.1")), None, &proxies),
+            Some(ip("10.0.0.1"))
+        );
+        // Addresses before the first untrusted one are the client's word
+        assert_eq!(
+            client_ip(
+                Some(ip("10.0.0.1")),
+                Some("9.9.9.9, 1.2.3.4, 10.0.0.2"),
+                &proxies
+            ),
+            Some(ip("1.2.3.4"))
+        );
+        assert_eq!(
+            client_ip(Some(ip("10.0.0.1")), Some("garbage"), &proxies),
+            Some(ip("10.0.0.1"))
+        );
+        assert_eq!(client_ip(None, Some("1.2.3.4"), &proxies), None);
+    }
+}
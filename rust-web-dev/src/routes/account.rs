@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use warp::http::StatusCode;
+
+use crate::routes::authentication::{hash_password, verify_password};
+use crate::routes::authorization::require_role;
+use crate::store::Store;
+use crate::types::account::{AccountId, PasswordChange, Profile, Role, RoleUpdate, Session};
+use crate::types::pagination::extract_pagination;
+
+/// List the accounts, for admins
+#[utoipa::path(
+    get,
+    path = "/v1/accounts",
+    params(
+        ("limit" = Option<u32>, Query, description = "Accounts per page, 20 by default and 100 at most"),
+        ("offset" = Option<u32>, Query, description = "Accounts to skip"),
+    ),
+    responses(
+        (status = 200, description = "Accounts", body = [Profile]),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "accounts"
+)]
+pub async fn get_accounts(
+    params: HashMap<String, String>,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_role(&session, Role::Admin)?;
+    let pagination = extract_pagination(params)?;
+
+    match store
+        .get_accounts(pagination.limit, pagination.offset)
+        .await
+    {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Change the role of an account, for admins. The account gets logged out
+/// and picks up the new role when it logs in again.
+#[utoipa::path(
+    put,
+    path = "/v1/accounts/{id}/role",
+    params(("id" = i32, Path, description = "Account id")),
+    request_body = RoleUpdate,
+    responses(
+        (status = 200, description = "Account with its new role", body = Profile),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+        (status = 404, description = "No such account", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "accounts"
+)]
+pub async fn update_role(
+    id: i32,
+    session: Session,
+    store: Store,
+    update: RoleUpdate,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_role(&session, Role::Admin)?;
+
+    match store.update_role(&AccountId(id), update.role).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Profile of the account logged in
+#[utoipa::path(
+    get,
+    path = "/v1/accounts/me",
+    responses(
+        (status = 200, description = "Profile of the account", body = Profile),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "accounts"
+)]
+pub async fn get_me(session: Session, store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.get_account_by_id(&session.account_id).await {
+        Ok(account) => Ok(warp::reply::json(&Profile {
+            id: session.account_id,
+            email: account.email,
+            role: account.role,
+            email_verified: account.email_verified,
+        })),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Change the password of the account logged in. Its other sessions get
+/// closed, they may belong to whoever knew the old password.
+#[utoipa::path(
+    put,
+    path = "/v1/accounts/me/password",
+    request_body = PasswordChange,
+    responses(
+        (status = 200, description = "Password changed, other sessions closed", body = String),
+        (status = 401, description = "Not logged in or wrong old password", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "accounts"
+)]
+pub async fn change_password(
+    session: Session,
+    store: Store,
+    change: PasswordChange,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account = store.get_account_by_id(&session.account_id).await?;
+
+    match verify_password(&account.password, change.old_password.as_bytes()) {
+        Ok(true) => (),
+        Ok(false) => return Err(warp::reject::custom(handle_errors::Error::WrongPassword)),
+        Err(e) => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::ArgonLibraryError(e),
+            ))
+        }
+    }
+
+    let hashed_password = hash_password(change.new_password.as_bytes());
+    store
+        .update_password(&session.account_id, hashed_password)
+        .await?;
+
+    match store
+        .revoke_other_sessions(&session.account_id, &session.session_id)
+        .await
+    {
+        Ok(_) => Ok(warp::reply::with_status("Password changed", StatusCode::OK)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Delete the account logged in. Its questions and answers stay online
+/// without an author, unless `?content=delete` asks to delete them as well.
+#[utoipa::path(
+    delete,
+    path = "/v1/accounts/me",
+    params(("content" = Option<String>, Query, description = "anonymise (default) keeps the questions and answers of the account, delete deletes them")),
+    responses(
+        (status = 200, description = "Account deleted", body = String),
+        (status = 400, description = "Invalid content parameter", body = ErrorBody),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "accounts"
+)]
+pub async fn delete_me(
+    params: HashMap<String, String>,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let keep_content = match params.get("content").map(String::as_str) {
+        None | Some("anonymise") => true,
+        Some("delete") => false,
+        Some(other) => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::InvalidParameter(format!("content={}", other)),
+            ))
+        }
+    };
+
+    match store
+        .delete_account(&session.account_id, keep_content)
+        .await
+    {
+        Ok(_) => Ok(warp::reply::with_status("Account deleted", StatusCode::OK)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
@@ -1,17 +1,87 @@
+use std::collections::HashMap;
 use warp::http::StatusCode;
 
-use crate::profanity::check_profanity;
+use crate::profanity::SharedContentFilter;
+use crate::routes::authorization::{can_accept_answer, can_modify_answer};
+use crate::routes::created;
 use crate::store::Store;
 use crate::types::account::Session;
-use crate::types::answer::NewAnswer;
+use crate::types::answer::{NewAnswer, UpdateAnswer};
+use crate::types::filter::Sort;
+use crate::types::pagination::extract_pagination;
 
+#[utoipa::path(
+    get,
+    path = "/v1/questions/{id}/answers",
+    params(
+        ("id" = i32, Path, description = "Question id"),
+        ("limit" = Option<u32>, Query, description = "Answers per page, 20 by default and 100 at most"),
+        ("offset" = Option<u32>, Query, description = "Answers to skip"),
+        ("cursor" = Option<String>, Query, description = "next_cursor of the previous page, in place of an offset"),
+    ),
+    responses(
+        (status = 200, description = "Page of answers to the question", body = AnswerPage),
+        (status = 400, description = "Invalid pagination", body = ErrorBody),
+        (status = 404, description = "No such question", body = ErrorBody),
+    ),
+    tag = "answers"
+)]
+pub async fn get_answers(
+    question_id: i32,
+    params: HashMap<String, String>,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let pagination = extract_pagination(params)?;
+    // Answers are listed in the order they were given
+    pagination.check_sort(Sort::Id)?;
+
+    // Tell an unknown question apart from one nobody answered yet
+    store.get_question(question_id).await?;
+
+    match store.get_answers(question_id, &pagination).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/answers/{id}",
+    params(("id" = i32, Path, description = "Answer id")),
+    responses(
+        (status = 200, description = "The answer", body = Answer),
+        (status = 404, description = "No such answer", body = ErrorBody),
+    ),
+    tag = "answers"
+)]
+pub async fn get_answer(id: i32, store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.get_answer(id).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/comments",
+    request_body = NewAnswer,
+    responses(
+        (status = 201, description = "Answer added", body = Answer,
+            headers(("location" = String, description = "Path of the answer"))),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 422, description = "Invalid answer or unknown question", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "answers"
+)]
 pub async fn add_answer(
     session: Session,
     store: Store,
+    content_filter: SharedContentFilter,
     new_answer: NewAnswer,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let account_id = session.account_id;
-    let content = match check_profanity(new_answer.content).await {
+    let content = match content_filter.censor(new_answer.content).await {
         Ok(res) => res,
         Err(e) => return Err(warp::reject::custom(e)),
     };
@@ -22,7 +92,99 @@ pub async fn add_answer(
     };
 
     match store.add_answer(answer, account_id).await {
-        Ok(_) => Ok(warp::reply::with_status("Answer added", StatusCode::OK)),
+        Ok(answer) => Ok(created(format!("/v1/answers/{}", answer.id.0), &answer)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/v1/answers/{id}",
+    params(("id" = i32, Path, description = "Answer id")),
+    request_body = UpdateAnswer,
+    responses(
+        (status = 200, description = "Answer updated", body = Answer),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "Neither the author nor a moderator", body = ErrorBody),
+        (status = 422, description = "Invalid answer", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "answers"
+)]
+pub async fn update_answer(
+    id: i32,
+    session: Session,
+    store: Store,
+    content_filter: SharedContentFilter,
+    answer: UpdateAnswer,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    can_modify_answer(&session, id, &store).await?;
+
+    let content = match content_filter.censor(answer.content).await {
+        Ok(res) => res,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    let answer = UpdateAnswer { content };
+
+    match store.update_answer(answer, id).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/answers/{id}",
+    params(("id" = i32, Path, description = "Answer id")),
+    responses(
+        (status = 200, description = "Answer deleted", body = String),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "Neither the author nor a moderator", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "answers"
+)]
+pub async fn delete_answer(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    can_modify_answer(&session, id, &store).await?;
+
+    match store.delete_answer(id).await {
+        Ok(_) => Ok(warp::reply::with_status(
+            format!("Answer {} deleted", id),
+            StatusCode::OK,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Mark the answer as the accepted one of its question, in place of any
+/// other
+#[utoipa::path(
+    post,
+    path = "/v1/answers/{id}/accept",
+    params(("id" = i32, Path, description = "Answer id")),
+    responses(
+        (status = 200, description = "The question, with the answer accepted", body = Question),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "Not the author of the question", body = ErrorBody),
+        (status = 404, description = "No such answer", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "answers"
+)]
+pub async fn accept_answer(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let question_id = can_accept_answer(&session, id, &store).await?;
+
+    match store.accept_answer(&question_id, id).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
         Err(e) => Err(warp::reject::custom(e)),
     }
 }
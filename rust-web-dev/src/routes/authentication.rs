@@ -1,49 +1,214 @@
 use argon2::{self, Config};
 use chrono::prelude::*;
 use rand::Rng;
+use sha2::{Digest, Sha256};
 use std::{env, future};
 use warp::{http::StatusCode, Filter};
 
+use crate::mailer::SharedMailer;
+use crate::rate_limit::Quota;
+use crate::routes::created;
+use crate::routes::verification::send_verification_email;
 use crate::store::Store;
-use crate::types::account::{Account, AccountId, Session};
+use crate::types::account::{
+    normalise_email, Account, AccountId, Profile, RefreshRequest, Role, Session, TokenPair,
+};
 
-fn hash_password(password: &[u8]) -> String {
+/// How long an access token is valid, clients refresh it past that
+const ACCESS_TOKEN_LIFETIME_MINUTES: i64 = 15;
+/// How long a refresh token can be exchanged for new tokens
+const REFRESH_TOKEN_LIFETIME_DAYS: i64 = 30;
+/// Wrong passwords in a row which lock the account
+const MAX_FAILED_LOGINS: i32 = 5;
+/// How long a locked email cannot log in, and how long failed logins are
+/// remembered
+pub const LOCKOUT_MINUTES: i64 = 15;
+
+/// Login attempts per client IP address
+pub const LOGIN_IP_QUOTA: Quota = Quota {
+    capacity: 30,
+    per_minute: 30,
+};
+/// Login attempts per account
+pub const LOGIN_ACCOUNT_QUOTA: Quota = Quota {
+    capacity: 10,
+    per_minute: 5,
+};
+
+pub fn hash_password(password: &[u8]) -> String {
     let salt = rand::thread_rng().gen::<[u8; 32]>();
     let config = Config::default();
     argon2::hash_encoded(password, &salt, &config).unwrap()
 }
 
-pub async fn register(store: Store, account: Account) -> Result<impl warp::Reply, warp::Rejection> {
+#[utoipa::path(
+    post,
+    path = "/v1/registration",
+    request_body = Account,
+    responses(
+        (status = 201, description = "Account added, a verification email is on its way", body = Profile,
+            headers(("location" = String, description = "Path of the profile, once logged in"))),
+        (status = 409, description = "Email already registered", body = ErrorBody),
+        (status = 422, description = "Invalid email", body = ErrorBody),
+    ),
+    tag = "authentication"
+)]
+pub async fn register(
+    store: Store,
+    mailer: SharedMailer,
+    account: Account,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let email = normalise_email(&account.email)?;
     let hashed_password = hash_password(account.password.as_bytes());
 
     let account = Account {
         id: account.id,
-        email: account.email,
+        email,
         password: hashed_password,
+        role: Role::User,
+        email_verified: false,
     };
 
-    match store.add_account(account).await {
-        Ok(_) => Ok(warp::reply::with_status("Account added", StatusCode::OK)),
-        Err(e) => Err(warp::reject::custom(e)),
+    let account = store.add_account(account).await?;
+
+    // The account is there even when the email cannot go out, it can be
+    // sent again from /verify-email/resend
+    if let Err(e) = send_verification_email(&store, &mailer, &account).await {
+        tracing::event!(tracing::Level::ERROR, "{}", e);
     }
+
+    // Accounts can only read their own profile, under /accounts/me
+    Ok(created(
+        "/v1/accounts/me".to_string(),
+        &Profile {
+            id: account.id.expect("id not found"),
+            email: account.email,
+            role: account.role,
+            email_verified: account.email_verified,
+        },
+    ))
 }
 
+#[utoipa::path(
+    post,
+    path = "/v1/login",
+    request_body = Account,
+    responses(
+        (status = 200, description = "Tokens of the new session", body = TokenPair),
+        (status = 401, description = "Wrong email or password", body = ErrorBody),
+        (status = 403, description = "Email not verified yet", body = ErrorBody),
+        (status = 429, description = "Too many attempts or account locked", body = ErrorBody),
+    ),
+    tag = "authentication"
+)]
 pub async fn login(store: Store, login: Account) -> Result<impl warp::Reply, warp::Rejection> {
-    match store.get_account(login.email).await {
-        Ok(account) => match verify_password(&account.password, login.password.as_bytes()) {
-            Ok(verified) => {
-                if verified {
-                    Ok(warp::reply::json(&issue_token(
-                        account.id.expect("id not found"),
-                    )))
-                } else {
-                    Err(warp::reject::custom(handle_errors::Error::WrongPassword))
-                }
-            }
-            Err(e) => Err(warp::reject::custom(
-                handle_errors::Error::ArgonLibraryError(e),
-            )),
-        },
+    let email = login.email.trim().to_lowercase();
+
+    // Locked emails are refused even with the right password, or the
+    // lockout would not slow down guessing it. Emails without an account
+    // get locked all the same, not to tell which ones have one.
+    if let Some(remaining) = store.lockout_remaining(&email).await? {
+        return Err(warp::reject::custom(handle_errors::Error::TooManyRequests(
+            remaining,
+        )));
+    }
+
+    let account = match store.get_account(email.clone()).await {
+        Ok(account) => Some(account),
+        Err(handle_errors::Error::NotFound) => None,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    let password_matches = match &account {
+        Some(account) => verify_password(&account.password, login.password.as_bytes())
+            .map_err(|e| warp::reject::custom(handle_errors::Error::ArgonLibraryError(e)))?,
+        None => false,
+    };
+    let account = match account {
+        Some(account) if password_matches => account,
+        // Same answer for an unknown email as for a wrong password
+        _ => {
+            store
+                .add_failed_login(
+                    &email,
+                    MAX_FAILED_LOGINS,
+                    chrono::Duration::minutes(LOCKOUT_MINUTES),
+                )
+                .await?;
+            return Err(warp::reject::custom(handle_errors::Error::WrongPassword));
+        }
+    };
+    let account_id = account.id.expect("id not found");
+    store.reset_failed_logins(&email).await?;
+
+    if !account.email_verified {
+        return Err(warp::reject::custom(handle_errors::Error::EmailNotVerified));
+    }
+
+    let session_id = uuid::Uuid::new_v4().to_string();
+    let tokens = issue_tokens(&store, account_id, account.role, session_id).await?;
+    Ok(warp::reply::json(&tokens))
+}
+
+/// Exchange a refresh token for a new access token and refresh token
+#[utoipa::path(
+    post,
+    path = "/v1/token/refresh",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "New tokens of the session", body = TokenPair),
+        (status = 401, description = "Invalid, used or expired refresh token", body = ErrorBody),
+    ),
+    tag = "authentication"
+)]
+pub async fn refresh(
+    store: Store,
+    request: RefreshRequest,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let token = match store
+        .use_refresh_token(&hash_token(&request.refresh_token))
+        .await?
+    {
+        Some(token) => token,
+        None => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::InvalidRefreshToken,
+            ))
+        }
+    };
+
+    if token.used {
+        // Refresh tokens are good for one exchange, seeing one again means
+        // it leaked: close the session for whoever holds it
+        store.revoke_session(&token.session_id).await?;
+        return Err(warp::reject::custom(
+            handle_errors::Error::InvalidRefreshToken,
+        ));
+    }
+    if token.revoked || token.expired {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InvalidRefreshToken,
+        ));
+    }
+
+    let tokens = issue_tokens(&store, token.account_id, token.role, token.session_id).await?;
+    Ok(warp::reply::json(&tokens))
+}
+
+/// Close the session, its access and refresh tokens stop working
+#[utoipa::path(
+    post,
+    path = "/v1/logout",
+    responses(
+        (status = 200, description = "Session closed", body = String),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "authentication"
+)]
+pub async fn logout(session: Session, store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.revoke_session(&session.session_id).await {
+        Ok(_) => Ok(warp::reply::with_status("Logged out", StatusCode::OK)),
         Err(e) => Err(warp::reject::custom(e)),
     }
 }
@@ -61,31 +226,85 @@ pub fn verify_token(token: String) -> Result<Session, handle_errors::Error> {
     serde_json::from_value::<Session>(token).map_err(|_| handle_errors::Error::CannotDecryptToken)
 }
 
-fn verify_password(hash: &str, password: &[u8]) -> Result<bool, argon2::Error> {
+pub fn verify_password(hash: &str, password: &[u8]) -> Result<bool, argon2::Error> {
     argon2::verify_encoded(hash, password)
 }
 
-fn issue_token(account_id: AccountId) -> String {
+fn issue_token(account_id: AccountId, role: Role, session_id: &str) -> String {
     let current_date_time = Utc::now();
-    let dt = current_date_time + chrono::Duration::days(1);
+    let dt = current_date_time + chrono::Duration::minutes(ACCESS_TOKEN_LIFETIME_MINUTES);
     let key = env::var("PASETO_KEY").unwrap();
     paseto::tokens::PasetoBuilder::new()
         .set_encryption_key(&Vec::from(key.as_bytes()))
         .set_expiration(&dt)
         .set_not_before(&Utc::now())
         .set_claim("account_id", serde_json::json!(account_id))
+        .set_claim("session_id", serde_json::json!(session_id))
+        .set_claim("role", serde_json::json!(role))
         .build()
         .expect("Failed to construct paseto token w/ builder!")
 }
 
+/// Issue an access token along with a new refresh token for the session
+async fn issue_tokens(
+    store: &Store,
+    account_id: AccountId,
+    role: Role,
+    session_id: String,
+) -> Result<TokenPair, handle_errors::Error> {
+    let refresh_token = random_token();
+    store
+        .add_refresh_token(
+            &hash_token(&refresh_token),
+            &session_id,
+            &account_id,
+            chrono::Duration::days(REFRESH_TOKEN_LIFETIME_DAYS),
+        )
+        .await?;
+
+    Ok(TokenPair {
+        access_token: issue_token(account_id, role, &session_id),
+        refresh_token,
+        expires_in: ACCESS_TOKEN_LIFETIME_MINUTES * 60,
+    })
+}
+
+/// Random token handed out to a client, like refresh or reset tokens
+pub fn random_token() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+/// Tokens handed out to clients are only stored hashed
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
 /// Auth middleware function
 //  Middleware functions return an impl Filter signature with <Extract = (T,), Error =
 //  warp::Rejection> and expect a ` std::future::Ready` as return value
-pub fn auth() -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
-    warp::header::<String>("Authorization").and_then(|token: String| {
-        let token = match verify_token(token) {
-            Ok(t) => t,
-            Err(_) => return future::ready(Err(warp::reject::reject())),
+pub fn auth(store: Store) -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
+    session()
+        .and(warp::any().map(move || store.clone()))
+        .and_then(|session: Session, store: Store| async move {
+            match store.is_session_revoked(&session.session_id).await {
+                Ok(false) => Ok(session),
+                Ok(true) => Err(warp::reject::custom(handle_errors::Error::TokenRevoked)),
+                Err(e) => Err(warp::reject::custom(e)),
+            }
+        })
+}
+
+/// Session of the access token in the request, without checking whether it
+/// has been revoked since
+fn session() -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("Authorization").and_then(|token: Option<String>| {
+        let token = match token.map(verify_token) {
+            Some(Ok(t)) => t,
+            _ => {
+                return future::ready(Err(warp::reject::custom(
+                    handle_errors::Error::Unauthorized,
+                )))
+            }
         };
 
         future::ready(Ok(token))
@@ -94,19 +313,33 @@ pub fn auth() -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Cl
 
 #[cfg(test)]
 mod authentication_tests {
-    use super::{auth, env, issue_token, AccountId};
+    use super::{env, hash_token, issue_token, session, AccountId, Role};
 
     #[tokio::test]
     async fn post_questions_auth() {
         env::set_var("PASETO_KEY", "RANDOM WORDS WINTER MACINTOSH PC");
-        let token = issue_token(AccountId(3));
+        let token = issue_token(
+            AccountId(3),
+            Role::Moderator,
+            "4c5a9f6e-2b3d-4f1a-9c8e-7d6b5a4f3e2d",
+        );
 
-        let filter = auth();
+        let filter = session();
 
         let res = warp::test::request()
             .header("Authorization", token)
             .filter(&filter);
 
-        assert_eq!(res.await.unwrap().account_id, AccountId(3));
+        let session = res.await.unwrap();
+        assert_eq!(session.account_id, AccountId(3));
+        assert_eq!(session.role, Role::Moderator);
+    }
+
+    #[test]
+    fn refresh_tokens_are_hashed() {
+        let hash = hash_token("token");
+        assert_eq!(hash.len(), 64);
+        assert_ne!(hash, "token");
+        assert_eq!(hash, hash_token("token"));
     }
 }
@@ -0,0 +1,125 @@
+use handle_errors::Error;
+
+use crate::store::Store;
+use crate::types::account::{Role, Session};
+use crate::types::question::QuestionId;
+
+/// Only the author of a question and moderators can edit or delete it
+pub async fn can_modify_question(
+    session: &Session,
+    question_id: i32,
+    store: &Store,
+) -> Result<(), Error> {
+    if session.role >= Role::Moderator
+        || store
+            .is_question_owner(question_id, &session.account_id)
+            .await?
+    {
+        Ok(())
+    } else {
+        Err(Error::Forbidden)
+    }
+}
+
+/// Only the author of an answer and moderators can edit or delete it
+pub async fn can_modify_answer(
+    session: &Session,
+    answer_id: i32,
+    store: &Store,
+) -> Result<(), Error> {
+    if session.role >= Role::Moderator
+        || store
+            .is_answer_owner(answer_id, &session.account_id)
+            .await?
+    {
+        Ok(())
+    } else {
+        Err(Error::Forbidden)
+    }
+}
+
+/// Only the author of a question can accept one of its answers, moderators
+/// included. Returns the question the answer was given to.
+pub async fn can_accept_answer(
+    session: &Session,
+    answer_id: i32,
+    store: &Store,
+) -> Result<QuestionId, Error> {
+    let question_id = store.get_answer_question_id(answer_id).await?;
+
+    if store
+        .is_question_owner(question_id.0, &session.account_id)
+        .await?
+    {
+        Ok(question_id)
+    } else {
+        Err(Error::Forbidden)
+    }
+}
+
+/// The session's account needs at least the role
+pub fn require_role(session: &Session, role: Role) -> Result<(), Error> {
+    if session.role >= role {
+        Ok(())
+    } else {
+        Err(Error::Forbidden)
+    }
+}
+
+#[cfg(test)]
+mod authorization_tests {
+    use super::{can_modify_answer, can_modify_question, require_role, Error, Role, Session};
+    use chrono::Utc;
+
+    use crate::store::Store;
+    use crate::types::account::AccountId;
+
+    fn session(role: Role) -> Session {
+        Session {
+            exp: Utc::now(),
+            account_id: AccountId(1),
+            session_id: "session".to_string(),
+            role,
+            nbf: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn roles_at_least_the_required_one() {
+        assert!(require_role(&session(Role::Admin), Role::Admin).is_ok());
+        assert!(require_role(&session(Role::Admin), Role::Moderator).is_ok());
+        assert!(require_role(&session(Role::Moderator), Role::Moderator).is_ok());
+        assert!(require_role(&session(Role::User), Role::User).is_ok());
+
+        assert!(matches!(
+            require_role(&session(Role::Moderator), Role::Admin),
+            Err(Error::Forbidden)
+        ));
+        assert!(matches!(
+            require_role(&session(Role::User), Role::Moderator),
+            Err(Error::Forbidden)
+        ));
+    }
+
+    #[tokio::test]
+    async fn moderators_modify_without_owning() {
+        let store = Store::unreachable();
+        for role in [Role::Moderator, Role::Admin] {
+            assert!(can_modify_question(&session(role), 7, &store).await.is_ok());
+            assert!(can_modify_answer(&session(role), 7, &store).await.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn users_need_the_owner_check() {
+        let store = Store::unreachable();
+        assert!(matches!(
+            can_modify_question(&session(Role::User), 7, &store).await,
+            Err(Error::DatabaseQueryError(_))
+        ));
+        assert!(matches!(
+            can_modify_answer(&session(Role::User), 7, &store).await,
+            Err(Error::DatabaseQueryError(_))
+        ));
+    }
+}
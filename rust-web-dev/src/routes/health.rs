@@ -0,0 +1,125 @@
+use std::time::Duration;
+use warp::http::StatusCode;
+
+use crate::store::Store;
+use crate::types::health::{Health, MigrationStatus, Readiness};
+
+/// Build id produced by `build.rs`
+const VERSION: &str = env!("RUST_WEB_DEV_VERSION");
+/// How long `/ready` waits for the database, probes give up soon as well
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Liveness of the server, which does not depend on the database
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses((status = 200, description = "The server runs", body = Health)),
+    tag = "health"
+)]
+pub async fn health() -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&Health {
+        status: "ok".to_string(),
+        version: VERSION.to_string(),
+    }))
+}
+
+/// Readiness of the server: the database answers and has every migration
+/// the server ships applied
+#[utoipa::path(
+    get,
+    path = "/ready",
+    responses(
+        (status = 200, description = "Ready to serve requests", body = Readiness),
+        (status = 503, description = "Database unavailable or migrations pending", body = Readiness),
+    ),
+    tag = "health"
+)]
+pub async fn ready(store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    let applied = tokio::time::timeout(READY_TIMEOUT, async {
+        store.ping().await?;
+        store.applied_migrations().await
+    })
+    .await;
+
+    let migrations = match applied {
+        Ok(Ok(applied)) => Some(migration_status(&applied)),
+        _ => None,
+    };
+    let ready = matches!(&migrations, Some(m) if m.pending.is_empty());
+
+    let readiness = Readiness {
+        status: if ready { "ready" } else { "not_ready" }.to_string(),
+        version: VERSION.to_string(),
+        database: if migrations.is_some() {
+            "ok"
+        } else {
+            "unavailable"
+        }
+        .to_string(),
+        migrations,
+    };
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&readiness),
+        status,
+    ))
+}
+
+/// Compare the migrations applied to the database with the ones embedded in
+/// the server
+fn migration_status(applied: &[i64]) -> MigrationStatus {
+    let pending = sqlx::migrate!()
+        .migrations
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| m.version)
+        .filter(|version| !applied.contains(version))
+        .collect();
+
+    MigrationStatus {
+        current: applied.last().copied(),
+        pending,
+    }
+}
+
+#[cfg(test)]
+mod health_tests {
+    use super::{migration_status, ready};
+    use warp::{hyper::body::to_bytes, Reply};
+
+    use crate::store::Store;
+
+    #[test]
+    fn pending_migrations() {
+        let status = migration_status(&[]);
+        assert_eq!(status.current, None);
+        assert!(!status.pending.is_empty());
+
+        let all = status.pending.clone();
+        let status = migration_status(&all);
+        assert_eq!(status.current, all.last().copied());
+        assert!(status.pending.is_empty());
+
+        let status = migration_status(&all[..1]);
+        assert_eq!(status.pending, all[1..]);
+    }
+
+    #[tokio::test]
+    async fn not_ready_without_database() {
+        let store = Store::unreachable();
+
+        let res = ready(store).await.unwrap().into_response();
+        assert_eq!(res.status(), 503);
+
+        let body: serde_json::Value =
+            serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["status"], "not_ready");
+        assert_eq!(body["database"], "unavailable");
+        assert_eq!(body["version"], env!("RUST_WEB_DEV_VERSION"));
+    }
+}
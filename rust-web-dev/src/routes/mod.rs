@@ -1,3 +1,21 @@
+pub mod account;
 pub mod answer;
 pub mod authentication;
+pub mod authorization;
+pub mod health;
 pub mod question;
+pub mod verification;
+pub mod vote;
+
+use serde::Serialize;
+use warp::http::StatusCode;
+
+/// Reply to a request creating a resource: the resource as it got stored,
+/// with where to find it in the `Location` header
+pub fn created<T: Serialize>(location: String, resource: &T) -> impl warp::Reply {
+    warp::reply::with_header(
+        warp::reply::with_status(warp::reply::json(resource), StatusCode::CREATED),
+        "location",
+        location,
+    )
+}
@@ -1,51 +1,102 @@
-use std::collections::HashMap;
 use tracing::{event, instrument, Level};
 use warp::http::StatusCode;
 
-use crate::profanity::check_profanity;
+use crate::profanity::SharedContentFilter;
+use crate::routes::authorization::can_modify_question;
+use crate::routes::created;
 use crate::store::Store;
 use crate::types::account::Session;
 
 use crate::types::{
+    filter::extract_question_filter,
     pagination::{extract_pagination, Pagination},
-    question::{NewQuestion, Question},
+    question::{NewQuestion, Question, QuestionWithAnswers},
 };
 
 #[instrument]
+#[utoipa::path(
+    get,
+    path = "/v1/questions",
+    params(
+        ("q" = Option<String>, Query, description = "Full-text search over title and content"),
+        ("tag" = Option<Vec<String>>, Query, description = "Tag the questions must have, can be repeated"),
+        ("sort" = Option<String>, Query, description = "id, created_on, -created_on, score or -score"),
+        ("limit" = Option<u32>, Query, description = "Questions per page, 20 by default and 100 at most"),
+        ("offset" = Option<u32>, Query, description = "Questions to skip"),
+        ("cursor" = Option<String>, Query, description = "next_cursor of the previous page, in place of an offset"),
+    ),
+    responses(
+        (status = 200, description = "Page of questions", body = QuestionPage),
+        (status = 400, description = "Invalid filter or pagination", body = ErrorBody),
+    ),
+    tag = "questions"
+)]
 pub async fn get_questions(
-    params: HashMap<String, String>,
+    params: Vec<(String, String)>,
     store: Store,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     event!(target: "practical_rust_book", Level::INFO, "querying questions");
-    // Use default trait:
-    let mut pagination = Pagination::default();
+    let filter = extract_question_filter(&params)?;
+    let pagination = extract_pagination(params.into_iter().collect())?;
 
-    if !params.is_empty() {
-        event!(Level::INFO, pagination = true);
-        pagination = extract_pagination(params)?;
-    }
-    match store
-        .get_questions(pagination.limit, pagination.offset)
-        .await
-    {
+    pagination.check_sort(filter.sort)?;
+
+    match store.get_questions(&filter, &pagination).await {
         Ok(res) => Ok(warp::reply::json(&res)),
         Err(e) => Err(warp::reject::custom(e)),
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/questions/{id}",
+    params(("id" = i32, Path, description = "Question id")),
+    responses(
+        (status = 200, description = "The question with the first page of its answers", body = QuestionWithAnswers),
+        (status = 404, description = "No such question", body = ErrorBody),
+    ),
+    tag = "questions"
+)]
+pub async fn get_question(id: i32, store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    let question = store.get_question(id).await?;
+    // The next pages come from /questions/{id}/answers
+    let answers = store.get_answers(id, &Pagination::default()).await?;
+
+    Ok(warp::reply::json(&QuestionWithAnswers {
+        question,
+        answers: answers.items,
+        answers_next_cursor: answers.next_cursor,
+        answers_total: answers.total,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/questions",
+    request_body = NewQuestion,
+    responses(
+        (status = 201, description = "Question added", body = Question,
+            headers(("location" = String, description = "Path of the question"))),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 422, description = "Invalid question", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "questions"
+)]
 pub async fn add_question(
     session: Session,
     store: Store,
+    content_filter: SharedContentFilter,
     new_question: NewQuestion,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let account_id = session.account_id;
 
-    let title = match check_profanity(new_question.title).await {
+    let title = match content_filter.censor(new_question.title).await {
         Ok(res) => res,
         Err(e) => return Err(warp::reject::custom(e)),
     };
 
-    let content = match check_profanity(new_question.content).await {
+    let content = match content_filter.censor(new_question.content).await {
         Ok(res) => res,
         Err(e) => return Err(warp::reject::custom(e)),
     };
@@ -57,63 +108,89 @@ pub async fn add_question(
     };
 
     match store.add_question(question, account_id).await {
-        Ok(_) => Ok(warp::reply::with_status("Question added", StatusCode::OK)),
+        Ok(question) => Ok(created(
+            format!("/v1/questions/{}", question.id.0),
+            &question,
+        )),
         Err(e) => Err(warp::reject::custom(e)),
     }
 }
 
+#[utoipa::path(
+    put,
+    path = "/v1/questions/{id}",
+    params(("id" = i32, Path, description = "Question id")),
+    request_body = Question,
+    responses(
+        (status = 200, description = "Question updated", body = Question),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "Neither the author nor a moderator", body = ErrorBody),
+        (status = 422, description = "Invalid question", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "questions"
+)]
 pub async fn update_question(
     id: i32,
     session: Session,
     store: Store,
+    content_filter: SharedContentFilter,
     question: Question,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let account_id = session.account_id;
-    if store.is_question_owner(id, &account_id).await? {
-        let title = check_profanity(question.title);
-        let content = check_profanity(question.content);
-        // Both requests at once:
-        let (title, content) = tokio::join!(title, content);
-
-        if title.is_err() {
-            return Err(warp::reject::custom(title.unwrap_err()));
-        }
-
-        if content.is_err() {
-            return Err(warp::reject::custom(content.unwrap_err()));
-        }
-
-        let question = Question {
-            id: question.id,
-            title: title.unwrap(),
-            content: content.unwrap(),
-            tags: question.tags,
-        };
-
-        match store.update_question(question, id, account_id).await {
-            Ok(res) => Ok(warp::reply::json(&res)),
-            Err(e) => Err(warp::reject::custom(e)),
-        }
-    } else {
-        Err(warp::reject::custom(handle_errors::Error::Unauthorized))
+    can_modify_question(&session, id, &store).await?;
+
+    let title = content_filter.censor(question.title);
+    let content = content_filter.censor(question.content);
+    // Both requests at once:
+    let (title, content) = tokio::join!(title, content);
+
+    if title.is_err() {
+        return Err(warp::reject::custom(title.unwrap_err()));
+    }
+
+    if content.is_err() {
+        return Err(warp::reject::custom(content.unwrap_err()));
+    }
+
+    let question = Question {
+        id: question.id,
+        title: title.unwrap(),
+        content: content.unwrap(),
+        tags: question.tags,
+        score: question.score,
+        accepted_answer_id: question.accepted_answer_id,
+    };
+
+    match store.update_question(question, id).await {
+        Ok(res) => Ok(warp::reply::json(&res)),
+        Err(e) => Err(warp::reject::custom(e)),
     }
 }
 
+#[utoipa::path(
+    delete,
+    path = "/v1/questions/{id}",
+    params(("id" = i32, Path, description = "Question id")),
+    responses(
+        (status = 200, description = "Question deleted", body = String),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 403, description = "Neither the author nor a moderator", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "questions"
+)]
 pub async fn delete_question(
     id: i32,
     session: Session,
     store: Store,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let account_id = session.account_id;
-    if store.is_question_owner(id, &account_id).await? {
-        match store.delete_question(id, account_id).await {
-            Ok(_) => Ok(warp::reply::with_status(
-                format!("Question {} deleted", id),
-                StatusCode::OK,
-            )),
-            Err(e) => Err(warp::reject::custom(e)),
-        }
-    } else {
-        Err(warp::reject::custom(handle_errors::Error::Unauthorized))
+    can_modify_question(&session, id, &store).await?;
+
+    match store.delete_question(id).await {
+        Ok(_) => Ok(warp::reply::with_status(
+            format!("Question {} deleted", id),
+            StatusCode::OK,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
     }
 }
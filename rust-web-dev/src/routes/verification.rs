@@ -0,0 +1,227 @@
+use warp::http::StatusCode;
+
+use crate::mailer::{Email, SharedMailer};
+use crate::rate_limit::Quota;
+use crate::routes::authentication::{hash_password, hash_token, random_token};
+use crate::store::Store;
+use crate::types::account::{
+    Account, EmailRequest, PasswordReset, TokenPurpose, VerificationRequest,
+};
+
+/// How long the token sent at registration can verify the email address
+const VERIFICATION_TOKEN_LIFETIME_HOURS: i64 = 24;
+/// How long a password reset token can be used
+const RESET_TOKEN_LIFETIME_MINUTES: i64 = 60;
+
+/// Verification or password reset emails asked for per client IP address
+pub const EMAIL_IP_QUOTA: Quota = Quota {
+    capacity: 10,
+    per_minute: 10,
+};
+/// Verification or password reset emails asked for per email address
+pub const EMAIL_ADDRESS_QUOTA: Quota = Quota {
+    capacity: 3,
+    per_minute: 1,
+};
+
+/// Email a new verification token to the account
+pub async fn send_verification_email(
+    store: &Store,
+    mailer: &SharedMailer,
+    account: &Account,
+) -> Result<(), handle_errors::Error> {
+    let account_id = account.id.clone().expect("id not found");
+    let token = random_token();
+    store
+        .add_account_token(
+            &hash_token(&token),
+            &account_id,
+            TokenPurpose::VerifyEmail,
+            chrono::Duration::hours(VERIFICATION_TOKEN_LIFETIME_HOURS),
+        )
+        .await?;
+
+    mailer
+        .send(Email {
+            to: account.email.clone(),
+            subject: "Verify your email address".to_string(),
+            body: format!(
+                "Welcome!\n\n\
+                Confirm your email address by sending this token to POST /verify-email:\n\n\
+                {}\n\n\
+                It expires in {} hours.\n",
+                token, VERIFICATION_TOKEN_LIFETIME_HOURS
+            ),
+        })
+        .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/verify-email",
+    request_body = VerificationRequest,
+    responses(
+        (status = 200, description = "Email address verified", body = String),
+        (status = 400, description = "Invalid or expired token", body = ErrorBody),
+    ),
+    tag = "authentication"
+)]
+pub async fn verify_email(
+    store: Store,
+    request: VerificationRequest,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = match store
+        .use_account_token(&hash_token(&request.token), TokenPurpose::VerifyEmail)
+        .await?
+    {
+        Some(account_id) => account_id,
+        None => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::InvalidAccountToken,
+            ))
+        }
+    };
+
+    match store.verify_email(&account_id).await {
+        Ok(_) => Ok(warp::reply::with_status(
+            "Email address verified",
+            StatusCode::OK,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Send a new verification email. The answer is the same whether or not the
+/// account exists or needs one, not to tell which emails have an account.
+#[utoipa::path(
+    post,
+    path = "/v1/verify-email/resend",
+    request_body = EmailRequest,
+    responses(
+        (status = 200, description = "Verification email sent if the account needs one", body = String),
+        (status = 429, description = "Too many emails asked for", body = ErrorBody),
+    ),
+    tag = "authentication"
+)]
+pub async fn resend_verification(
+    store: Store,
+    mailer: SharedMailer,
+    request: EmailRequest,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // Looking the account up and sending the email after answering keeps
+    // the time the answer takes the same for every address
+    tokio::spawn(async move {
+        match store.get_account(request.email.trim().to_string()).await {
+            Ok(account) if !account.email_verified => {
+                if let Err(e) = send_verification_email(&store, &mailer, &account).await {
+                    tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                }
+            }
+            Ok(_) | Err(handle_errors::Error::NotFound) => (),
+            Err(e) => tracing::event!(tracing::Level::ERROR, "{:?}", e),
+        }
+    });
+
+    Ok(warp::reply::with_status(
+        "Verification email sent if the account needs one",
+        StatusCode::OK,
+    ))
+}
+
+/// Email a password reset token. Like the verification, the answer does not
+/// tell whether the account exists.
+#[utoipa::path(
+    post,
+    path = "/v1/password-reset",
+    request_body = EmailRequest,
+    responses(
+        (status = 200, description = "Reset email sent if the account exists", body = String),
+        (status = 429, description = "Too many emails asked for", body = ErrorBody),
+    ),
+    tag = "authentication"
+)]
+pub async fn request_password_reset(
+    store: Store,
+    mailer: SharedMailer,
+    request: EmailRequest,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // As for the verification, the email goes out after the answer
+    tokio::spawn(async move {
+        if let Err(e) = send_reset_email(&store, &mailer, request.email.trim().to_string()).await {
+            tracing::event!(tracing::Level::ERROR, "{:?}", e);
+        }
+    });
+
+    Ok(warp::reply::with_status(
+        "Reset email sent if the account exists",
+        StatusCode::OK,
+    ))
+}
+
+/// Email a new reset token to the account of the address, if there is one.
+/// Earlier tokens stop working.
+async fn send_reset_email(
+    store: &Store,
+    mailer: &SharedMailer,
+    email: String,
+) -> Result<(), handle_errors::Error> {
+    let account = match store.get_account(email).await {
+        Ok(account) => account,
+        Err(handle_errors::Error::NotFound) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let account_id = account.id.expect("id not found");
+    let token = random_token();
+    store
+        .add_account_token(
+            &hash_token(&token),
+            &account_id,
+            TokenPurpose::ResetPassword,
+            chrono::Duration::minutes(RESET_TOKEN_LIFETIME_MINUTES),
+        )
+        .await?;
+
+    mailer
+        .send(Email {
+            to: account.email,
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Choose a new password by sending it along with this token to \
+                POST /password-reset/confirm:\n\n\
+                {}\n\n\
+                It expires in {} minutes. If you did not ask for it, ignore this email.\n",
+                token, RESET_TOKEN_LIFETIME_MINUTES
+            ),
+        })
+        .await
+}
+
+/// Set the new password. Every session of the account gets closed, and the
+/// email address counts as verified since the token reached it.
+#[utoipa::path(
+    post,
+    path = "/v1/password-reset/confirm",
+    request_body = PasswordReset,
+    responses(
+        (status = 200, description = "Password changed, every session closed", body = String),
+        (status = 400, description = "Invalid or expired token", body = ErrorBody),
+    ),
+    tag = "authentication"
+)]
+pub async fn confirm_password_reset(
+    store: Store,
+    reset: PasswordReset,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let hashed_password = hash_password(reset.password.as_bytes());
+    match store
+        .reset_password(&hash_token(&reset.token), hashed_password)
+        .await
+    {
+        Ok(Some(_)) => Ok(warp::reply::with_status("Password changed", StatusCode::OK)),
+        Ok(None) => Err(warp::reject::custom(
+            handle_errors::Error::InvalidAccountToken,
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
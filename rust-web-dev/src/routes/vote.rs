@@ -0,0 +1,117 @@
+use crate::store::Store;
+use crate::types::account::Session;
+use crate::types::{
+    answer::AnswerId,
+    question::QuestionId,
+    vote::{Vote, VoteTarget},
+};
+
+/// Upvote or downvote the question, replacing an earlier vote of the account
+#[utoipa::path(
+    post,
+    path = "/v1/questions/{id}/{vote}",
+    params(
+        ("id" = i32, Path, description = "Question id"),
+        ("vote" = String, Path, description = "upvote or downvote"),
+    ),
+    responses(
+        (status = 200, description = "New score of the question", body = Score),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 404, description = "No such question", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "votes"
+)]
+pub async fn vote_question(
+    id: i32,
+    vote: Vote,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let target = VoteTarget::Question(QuestionId(id));
+
+    match store.vote(&session.account_id, &target, Some(vote)).await {
+        Ok(score) => Ok(warp::reply::json(&score)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/questions/{id}/vote",
+    params(("id" = i32, Path, description = "Question id")),
+    responses(
+        (status = 200, description = "New score of the question", body = Score),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 404, description = "No such question", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "votes"
+)]
+pub async fn unvote_question(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let target = VoteTarget::Question(QuestionId(id));
+
+    match store.vote(&session.account_id, &target, None).await {
+        Ok(score) => Ok(warp::reply::json(&score)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Upvote or downvote the answer, replacing an earlier vote of the account
+#[utoipa::path(
+    post,
+    path = "/v1/answers/{id}/{vote}",
+    params(
+        ("id" = i32, Path, description = "Answer id"),
+        ("vote" = String, Path, description = "upvote or downvote"),
+    ),
+    responses(
+        (status = 200, description = "New score of the answer", body = Score),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 404, description = "No such answer", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "votes"
+)]
+pub async fn vote_answer(
+    id: i32,
+    vote: Vote,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let target = VoteTarget::Answer(AnswerId(id));
+
+    match store.vote(&session.account_id, &target, Some(vote)).await {
+        Ok(score) => Ok(warp::reply::json(&score)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/answers/{id}/vote",
+    params(("id" = i32, Path, description = "Answer id")),
+    responses(
+        (status = 200, description = "New score of the answer", body = Score),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+        (status = 404, description = "No such answer", body = ErrorBody),
+    ),
+    security(("access_token" = [])),
+    tag = "votes"
+)]
+pub async fn unvote_answer(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let target = VoteTarget::Answer(AnswerId(id));
+
+    match store.vote(&session.account_id, &target, None).await {
+        Ok(score) => Ok(warp::reply::json(&score)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
@@ -4,11 +4,29 @@ use sqlx::Row;
 use handle_errors::Error;
 
 use crate::types::{
-    account::{Account, AccountId},
-    answer::{Answer, AnswerId, NewAnswer},
+    account::{Account, AccountId, Profile, RefreshToken, Role, TokenPurpose},
+    answer::{Answer, AnswerId, NewAnswer, UpdateAnswer},
+    filter::{QuestionFilter, Sort},
+    pagination::{Cursor, Page, Pagination},
     question::{NewQuestion, Question, QuestionId},
+    vote::{Score, Vote, VoteTarget},
 };
 
+/// `WHERE` clause of a question listing, binding the search query to `$1`
+/// and the tags to `$2`
+const QUESTION_FILTER: &str = "($1::text IS NULL OR search @@ websearch_to_tsquery('english', $1))
+        AND (cardinality($2::text[]) = 0 OR tags @> $2)";
+
+/// Role of the account in the row. A role this code does not know is an
+/// error, not a silent demotion to `user`.
+fn account_role(row: &PgRow) -> Result<Role, sqlx::Error> {
+    let role: String = row.try_get("role")?;
+    role.parse().map_err(|_| sqlx::Error::ColumnDecode {
+        index: "role".to_string(),
+        source: format!("unknown role {}", role).into(),
+    })
+}
+
 #[derive(Debug, Clone)]
 pub struct Store {
     pub connection: PgPool,
@@ -27,30 +45,148 @@ impl Store {
         })
     }
 
+    /// Store whose database can never be reached, for tests that must not
+    /// (or cannot) touch a real one
+    #[cfg(test)]
+    pub fn unreachable() -> Self {
+        Store {
+            connection: PgPoolOptions::new()
+                .connect_timeout(std::time::Duration::from_millis(100))
+                .connect_lazy("postgres://localhost:1/none")
+                .unwrap(),
+        }
+    }
+
+    /// Whether the database answers at all
+    pub async fn ping(&self) -> Result<bool, Error> {
+        match sqlx::query("SELECT 1").execute(&self.connection).await {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Versions of the migrations applied to the database, in order
+    pub async fn applied_migrations(&self) -> Result<Vec<i64>, Error> {
+        match sqlx::query("SELECT version FROM _sqlx_migrations WHERE success ORDER BY version")
+            .map(|row: PgRow| row.get("version"))
+            .fetch_all(&self.connection)
+            .await
+        {
+            Ok(versions) => Ok(versions),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
     pub async fn get_questions(
         &self,
-        limit: Option<u32>,
-        offset: u32,
-    ) -> Result<Vec<Question>, Error> {
-        match sqlx::query("SELECT * FROM questions LIMIT $1 OFFSET $2")
-            .bind(limit)
-            .bind(offset)
+        filter: &QuestionFilter,
+        pagination: &Pagination,
+    ) -> Result<Page<Question>, Error> {
+        let total = match sqlx::query(&format!(
+            "SELECT COUNT(*) AS total FROM questions WHERE {}",
+            QUESTION_FILTER
+        ))
+        .bind(&filter.query)
+        .bind(&filter.tags)
+        .map(|row: PgRow| row.get::<i64, _>("total"))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(total) => total,
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                return Err(Error::DatabaseQueryError(e));
+            }
+        };
+
+        // The sort only ever picks one of a few fixed clauses, everything
+        // coming from the client is bound
+        let after_cursor = match filter.sort {
+            Sort::Id => "id > $4",
+            Sort::CreatedOn => "(created_on, id) > ($3, $4)",
+            Sort::CreatedOnDesc => "(created_on, id) < ($3, $4)",
+            Sort::Score => "(score, created_on, id) > ($7, $3, $4)",
+            Sort::ScoreDesc => "(score, created_on, id) < ($7, $3, $4)",
+        };
+        let query = format!(
+            "SELECT * FROM questions
+        WHERE {} AND ($4::integer IS NULL OR {})
+        ORDER BY {}
+        LIMIT $5 OFFSET $6",
+            QUESTION_FILTER,
+            after_cursor,
+            filter.sort.order_by()
+        );
+        match sqlx::query(&query)
+            .bind(&filter.query)
+            .bind(&filter.tags)
+            .bind(pagination.cursor.as_ref().map(|c| c.created_on))
+            .bind(pagination.cursor.as_ref().map(|c| c.id))
+            .bind(pagination.limit.map(|limit| i64::from(limit) + 1))
+            .bind(i64::from(pagination.offset))
+            .bind(pagination.cursor.as_ref().and_then(|c| c.score))
+            .map(|row: PgRow| {
+                (
+                    Question {
+                        id: QuestionId(row.get("id")),
+                        title: row.get("title"),
+                        content: row.get("content"),
+                        tags: row.get("tags"),
+                        score: row.get("score"),
+                        accepted_answer_id: row
+                            .get::<Option<i32>, _>("accepted_answer_id")
+                            .map(AnswerId),
+                    },
+                    Cursor {
+                        sort: filter.sort,
+                        score: filter.sort.by_score().then(|| row.get("score")),
+                        created_on: row.get("created_on"),
+                        id: row.get("id"),
+                    },
+                )
+            })
+            .fetch_all(&self.connection)
+            .await
+        {
+            Ok(questions) => Ok(Page::new(questions, pagination.limit, total)),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn get_question(&self, question_id: i32) -> Result<Question, Error> {
+        match sqlx::query("SELECT * FROM questions WHERE id = $1")
+            .bind(question_id)
             .map(|row: PgRow| Question {
                 id: QuestionId(row.get("id")),
                 title: row.get("title"),
                 content: row.get("content"),
                 tags: row.get("tags"),
+                score: row.get("score"),
+                accepted_answer_id: row
+                    .get::<Option<i32>, _>("accepted_answer_id")
+                    .map(AnswerId),
             })
-            .fetch_all(&self.connection)
+            .fetch_optional(&self.connection)
             .await
         {
-            Ok(questions) => Ok(questions),
+            Ok(Some(question)) => Ok(question),
+            Ok(None) => Err(Error::NotFound),
             Err(e) => {
                 tracing::event!(tracing::Level::ERROR, "{:?}", e);
                 Err(Error::DatabaseQueryError(e))
             }
         }
     }
+
     pub async fn is_question_owner(
         &self,
         question_id: i32,
@@ -75,7 +211,7 @@ impl Store {
         new_question: NewQuestion,
         account_id: AccountId,
     ) -> Result<Question, Error> {
-        match sqlx::query("INSERT INTO questions (title, content, tags, account_id) VALUES ($1, $2, $3, $4) RETURNING id, title, content, tags")
+        match sqlx::query("INSERT INTO questions (title, content, tags, account_id) VALUES ($1, $2, $3, $4) RETURNING id, title, content, tags, score, accepted_answer_id")
             .bind(new_question.title)
             .bind(new_question.content)
             .bind(new_question.tags)
@@ -85,6 +221,10 @@ impl Store {
                 title: row.get("title"),
                 content: row.get("content"),
                 tags: row.get("tags"),
+                score: row.get("score"),
+                accepted_answer_id: row
+                    .get::<Option<i32>, _>("accepted_answer_id")
+                    .map(AnswerId),
             })
             .fetch_one(&self.connection).await {
                 Ok(question) => Ok(question),
@@ -98,23 +238,25 @@ impl Store {
         &self,
         question: Question,
         question_id: i32,
-        account_id: AccountId,
     ) -> Result<Question, Error> {
         match sqlx::query(
             "UPDATE questions SET title = $1, content = $2, tags = $3
-        WHERE id = $4 AND account_id = $5
-        RETURNING id, title, content, tags",
+        WHERE id = $4
+        RETURNING id, title, content, tags, score, accepted_answer_id",
         )
         .bind(question.title)
         .bind(question.content)
         .bind(question.tags)
         .bind(question_id)
-        .bind(account_id.0)
         .map(|row: PgRow| Question {
             id: QuestionId(row.get("id")),
             title: row.get("title"),
             content: row.get("content"),
             tags: row.get("tags"),
+            score: row.get("score"),
+            accepted_answer_id: row
+                .get::<Option<i32>, _>("accepted_answer_id")
+                .map(AnswerId),
         })
         .fetch_one(&self.connection)
         .await
@@ -127,14 +269,9 @@ impl Store {
         }
     }
 
-    pub async fn delete_question(
-        &self,
-        question_id: i32,
-        account_id: AccountId,
-    ) -> Result<bool, Error> {
-        match sqlx::query("DELETE FROM questions WHERE id = $1 AND account_id = $2 ")
+    pub async fn delete_question(&self, question_id: i32) -> Result<bool, Error> {
+        match sqlx::query("DELETE FROM questions WHERE id = $1")
             .bind(question_id)
-            .bind(account_id.0)
             .execute(&self.connection)
             .await
         {
@@ -152,7 +289,8 @@ impl Store {
         account_id: AccountId,
     ) -> Result<Answer, Error> {
         match sqlx::query(
-            "INSERT INTO answers (content, question_id, account_id) VALUES ($1, $2, $3)",
+            "INSERT INTO answers (content, question_id, account_id) VALUES ($1, $2, $3)
+        RETURNING id, content, question_id, score",
         )
         .bind(new_answer.content)
         .bind(new_answer.question_id.0)
@@ -161,6 +299,110 @@ impl Store {
             id: AnswerId(row.get("id")),
             content: row.get("content"),
             question_id: QuestionId(row.get("question_id")),
+            score: row.get("score"),
+        })
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(answer) => Ok(answer),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn get_answers(
+        &self,
+        question_id: i32,
+        pagination: &Pagination,
+    ) -> Result<Page<Answer>, Error> {
+        let total =
+            match sqlx::query("SELECT COUNT(*) AS total FROM answers WHERE question_id = $1")
+                .bind(question_id)
+                .map(|row: PgRow| row.get::<i64, _>("total"))
+                .fetch_one(&self.connection)
+                .await
+            {
+                Ok(total) => total,
+                Err(e) => {
+                    tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                    return Err(Error::DatabaseQueryError(e));
+                }
+            };
+
+        match sqlx::query(
+            "SELECT * FROM answers WHERE question_id = $1 AND ($2::integer IS NULL OR id > $2)
+        ORDER BY id
+        LIMIT $3 OFFSET $4",
+        )
+        .bind(question_id)
+        .bind(pagination.cursor.as_ref().map(|c| c.id))
+        .bind(pagination.limit.map(|limit| i64::from(limit) + 1))
+        .bind(i64::from(pagination.offset))
+        .map(|row: PgRow| {
+            (
+                Answer {
+                    id: AnswerId(row.get("id")),
+                    content: row.get("content"),
+                    question_id: QuestionId(row.get("question_id")),
+                    score: row.get("score"),
+                },
+                Cursor {
+                    sort: Sort::Id,
+                    score: None,
+                    created_on: row.get("created_on"),
+                    id: row.get("id"),
+                },
+            )
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(answers) => Ok(Page::new(answers, pagination.limit, total)),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn is_answer_owner(
+        &self,
+        answer_id: i32,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        match sqlx::query("SELECT * from answers where id = $1 and account_id = $2")
+            .bind(answer_id)
+            .bind(account_id.0)
+            .fetch_optional(&self.connection)
+            .await
+        {
+            Ok(answer) => Ok(answer.is_some()),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn update_answer(
+        &self,
+        answer: UpdateAnswer,
+        answer_id: i32,
+    ) -> Result<Answer, Error> {
+        match sqlx::query(
+            "UPDATE answers SET content = $1
+        WHERE id = $2
+        RETURNING id, content, question_id, score",
+        )
+        .bind(answer.content)
+        .bind(answer_id)
+        .map(|row: PgRow| Answer {
+            id: AnswerId(row.get("id")),
+            content: row.get("content"),
+            question_id: QuestionId(row.get("question_id")),
+            score: row.get("score"),
         })
         .fetch_one(&self.connection)
         .await
@@ -173,62 +415,746 @@ impl Store {
         }
     }
 
+    pub async fn delete_answer(&self, answer_id: i32) -> Result<bool, Error> {
+        match sqlx::query("DELETE FROM answers WHERE id = $1")
+            .bind(answer_id)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn get_answer(&self, answer_id: i32) -> Result<Answer, Error> {
+        match sqlx::query("SELECT id, content, question_id, score FROM answers WHERE id = $1")
+            .bind(answer_id)
+            .map(|row: PgRow| Answer {
+                id: AnswerId(row.get("id")),
+                content: row.get("content"),
+                question_id: QuestionId(row.get("question_id")),
+                score: row.get("score"),
+            })
+            .fetch_optional(&self.connection)
+            .await
+        {
+            Ok(Some(answer)) => Ok(answer),
+            Ok(None) => Err(Error::NotFound),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Question the answer was given to
+    pub async fn get_answer_question_id(&self, answer_id: i32) -> Result<QuestionId, Error> {
+        match sqlx::query("SELECT question_id FROM answers WHERE id = $1")
+            .bind(answer_id)
+            .map(|row: PgRow| QuestionId(row.get("question_id")))
+            .fetch_optional(&self.connection)
+            .await
+        {
+            Ok(Some(question_id)) => Ok(question_id),
+            Ok(None) => Err(Error::NotFound),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn accept_answer(
+        &self,
+        question_id: &QuestionId,
+        answer_id: i32,
+    ) -> Result<Question, Error> {
+        match sqlx::query(
+            "UPDATE questions SET accepted_answer_id = $2
+        WHERE id = $1
+        RETURNING id, title, content, tags, score, accepted_answer_id",
+        )
+        .bind(question_id.0)
+        .bind(answer_id)
+        .map(|row: PgRow| Question {
+            id: QuestionId(row.get("id")),
+            title: row.get("title"),
+            content: row.get("content"),
+            tags: row.get("tags"),
+            score: row.get("score"),
+            accepted_answer_id: row
+                .get::<Option<i32>, _>("accepted_answer_id")
+                .map(AnswerId),
+        })
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(question) => Ok(question),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Set the vote of the account on the target, or take it back with
+    /// `None`, and return the new score of the target
+    pub async fn vote(
+        &self,
+        account_id: &AccountId,
+        target: &VoteTarget,
+        vote: Option<Vote>,
+    ) -> Result<Score, Error> {
+        let result: Result<Option<i32>, sqlx::Error> = async {
+            let mut tx = self.connection.begin().await?;
+
+            // Locking the target makes concurrent votes on it wait for each
+            // other, so the score sums them all
+            let target_row = sqlx::query(&format!(
+                "SELECT id FROM {} WHERE id = $1 FOR UPDATE",
+                target.table()
+            ))
+            .bind(target.id())
+            .fetch_optional(&mut tx)
+            .await?;
+            if target_row.is_none() {
+                return Ok(None);
+            }
+
+            match vote {
+                Some(vote) => {
+                    sqlx::query(&format!(
+                        "INSERT INTO votes (account_id, {column}, value) VALUES ($1, $2, $3)
+                    ON CONFLICT ({column}, account_id) WHERE {column} IS NOT NULL
+                    DO UPDATE SET value = EXCLUDED.value, created_on = NOW()",
+                        column = target.column()
+                    ))
+                    .bind(account_id.0)
+                    .bind(target.id())
+                    .bind(vote.value())
+                    .execute(&mut tx)
+                    .await?;
+                }
+                None => {
+                    sqlx::query(&format!(
+                        "DELETE FROM votes WHERE account_id = $1 AND {} = $2",
+                        target.column()
+                    ))
+                    .bind(account_id.0)
+                    .bind(target.id())
+                    .execute(&mut tx)
+                    .await?;
+                }
+            }
+
+            let score = sqlx::query(&format!(
+                "UPDATE {table} SET score =
+                (SELECT COALESCE(SUM(value), 0) FROM votes WHERE {column} = $1)
+            WHERE id = $1
+            RETURNING score",
+                table = target.table(),
+                column = target.column()
+            ))
+            .bind(target.id())
+            .map(|row: PgRow| row.get::<i32, _>("score"))
+            .fetch_one(&mut tx)
+            .await?;
+
+            tx.commit().await?;
+            Ok(Some(score))
+        }
+        .await;
+
+        match result {
+            Ok(Some(score)) => Ok(Score {
+                score,
+                vote: vote.map_or(0, |vote| vote.value()),
+            }),
+            Ok(None) => Err(Error::NotFound),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
     pub async fn add_account(&self, account: Account) -> Result<Account, Error> {
-        match sqlx::query("INSERT INTO accounts (email, password) VALUES ($1, $2)")
-            .bind(account.email)
-            .bind(account.password)
-            .map(|row: PgRow| Account {
+        match sqlx::query(
+            "INSERT INTO accounts (email, password) VALUES ($1, $2)
+        RETURNING id, email, password, role, email_verified",
+        )
+        .bind(account.email)
+        .bind(account.password)
+        .try_map(|row: PgRow| {
+            Ok(Account {
                 id: Some(AccountId(row.get("id"))),
-                email: row.get("content"),
+                email: row.get("email"),
                 password: row.get("password"),
+                role: account_role(&row)?,
+                email_verified: row.get("email_verified"),
             })
-            .fetch_one(&self.connection)
-            .await
+        })
+        .fetch_one(&self.connection)
+        .await
         {
             Ok(account) => Ok(account),
             Err(e) => {
-                tracing::event!(
-                    tracing::Level::ERROR,
-                    code = e
-                        .as_database_error()
-                        .unwrap()
-                        .code()
-                        .unwrap()
-                        .parse::<i32>()
-                        .unwrap(),
-                    db_message = e.as_database_error().unwrap().message(),
-                    constraint = e.as_database_error().unwrap().constraint().unwrap()
-                );
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
                 Err(Error::DatabaseQueryError(e))
             }
         }
     }
 
     pub async fn get_account(&self, email: String) -> Result<Account, Error> {
-        match sqlx::query("SELECT * FROM accounts WHERE email = $1")
+        match sqlx::query("SELECT * FROM accounts WHERE lower(email) = lower($1)")
             .bind(email)
-            .map(|row: PgRow| Account {
-                id: Some(AccountId(row.get("id"))),
-                email: row.get("content"),
-                password: row.get("password"),
+            .try_map(|row: PgRow| {
+                Ok(Account {
+                    id: Some(AccountId(row.get("id"))),
+                    email: row.get("email"),
+                    password: row.get("password"),
+                    role: account_role(&row)?,
+                    email_verified: row.get("email_verified"),
+                })
+            })
+            .fetch_optional(&self.connection)
+            .await
+        {
+            Ok(Some(account)) => Ok(account),
+            Ok(None) => Err(Error::NotFound),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn get_account_by_id(&self, account_id: &AccountId) -> Result<Account, Error> {
+        match sqlx::query("SELECT * FROM accounts WHERE id = $1")
+            .bind(account_id.0)
+            .try_map(|row: PgRow| {
+                Ok(Account {
+                    id: Some(AccountId(row.get("id"))),
+                    email: row.get("email"),
+                    password: row.get("password"),
+                    role: account_role(&row)?,
+                    email_verified: row.get("email_verified"),
+                })
             })
-            .fetch_one(&self.connection)
+            .fetch_optional(&self.connection)
+            .await
+        {
+            Ok(Some(account)) => Ok(account),
+            Ok(None) => Err(Error::NotFound),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn update_password(
+        &self,
+        account_id: &AccountId,
+        password: String,
+    ) -> Result<bool, Error> {
+        match sqlx::query("UPDATE accounts SET password = $1 WHERE id = $2")
+            .bind(password)
+            .bind(account_id.0)
+            .execute(&self.connection)
             .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Delete the account and close its sessions. Its questions and answers
+    /// get deleted along with it, or kept without an author when
+    /// `keep_content` is set.
+    pub async fn delete_account(
+        &self,
+        account_id: &AccountId,
+        keep_content: bool,
+    ) -> Result<bool, Error> {
+        let statements = if keep_content {
+            [
+                "UPDATE answers SET account_id = NULL WHERE account_id = $1",
+                "UPDATE questions SET account_id = NULL WHERE account_id = $1",
+            ]
+        } else {
+            // Answers to the account's questions go with them
+            [
+                "DELETE FROM answers WHERE account_id = $1",
+                "DELETE FROM questions WHERE account_id = $1",
+            ]
+        };
+
+        let result: Result<(), sqlx::Error> = async {
+            let mut tx = self.connection.begin().await?;
+            for statement in statements.iter().chain(&[
+                // The votes of the account no longer count
+                "UPDATE questions SET score = questions.score - votes.value FROM votes
+                WHERE votes.question_id = questions.id AND votes.account_id = $1",
+                "UPDATE answers SET score = answers.score - votes.value FROM votes
+                WHERE votes.answer_id = answers.id AND votes.account_id = $1",
+                "DELETE FROM votes WHERE account_id = $1",
+                "UPDATE refresh_tokens SET revoked = true WHERE account_id = $1",
+                "DELETE FROM account_tokens WHERE account_id = $1",
+                "DELETE FROM accounts WHERE id = $1",
+            ]) {
+                sqlx::query(statement)
+                    .bind(account_id.0)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            tx.commit().await
+        }
+        .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn add_refresh_token(
+        &self,
+        token_hash: &str,
+        session_id: &str,
+        account_id: &AccountId,
+        lifetime: chrono::Duration,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "INSERT INTO refresh_tokens (token_hash, session_id, account_id, expires_on)
+        VALUES ($1, $2::uuid, $3, NOW() + make_interval(secs => $4))",
+        )
+        .bind(token_hash)
+        .bind(session_id)
+        .bind(account_id.0)
+        .bind(lifetime.num_seconds() as f64)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Mark the refresh token as used, returning it as it was before
+    pub async fn use_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, Error> {
+        match sqlx::query(
+            "UPDATE refresh_tokens AS t SET used = true
+        FROM (SELECT id, used FROM refresh_tokens WHERE token_hash = $1 FOR UPDATE) AS old
+        WHERE t.id = old.id
+        RETURNING t.session_id::text AS session_id, t.account_id, old.used, t.revoked,
+            t.expires_on < NOW() AS expired,
+            COALESCE((SELECT role FROM accounts WHERE accounts.id = t.account_id), 'user') AS role",
+        )
+        .bind(token_hash)
+        .try_map(|row: PgRow| {
+            Ok(RefreshToken {
+                session_id: row.get("session_id"),
+                account_id: AccountId(row.get("account_id")),
+                role: account_role(&row)?,
+                used: row.get("used"),
+                revoked: row.get("revoked"),
+                expired: row.get("expired"),
+            })
+        })
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(token) => Ok(token),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn revoke_session(&self, session_id: &str) -> Result<bool, Error> {
+        match sqlx::query("UPDATE refresh_tokens SET revoked = true WHERE session_id = $1::uuid")
+            .bind(session_id)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Close every session of the account but the given one
+    pub async fn revoke_other_sessions(
+        &self,
+        account_id: &AccountId,
+        session_id: &str,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "UPDATE refresh_tokens SET revoked = true
+        WHERE account_id = $1 AND session_id <> $2::uuid",
+        )
+        .bind(account_id.0)
+        .bind(session_id)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn is_session_revoked(&self, session_id: &str) -> Result<bool, Error> {
+        match sqlx::query(
+            "SELECT EXISTS (SELECT 1 FROM refresh_tokens WHERE session_id = $1::uuid AND revoked)
+        AS revoked",
+        )
+        .bind(session_id)
+        .map(|row: PgRow| row.get("revoked"))
+        .fetch_one(&self.connection)
+        .await
+        {
+            Ok(revoked) => Ok(revoked),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn get_accounts(
+        &self,
+        limit: Option<u32>,
+        offset: u32,
+    ) -> Result<Vec<Profile>, Error> {
+        match sqlx::query(
+            "SELECT id, email, role, email_verified FROM accounts ORDER BY id LIMIT $1 OFFSET $2",
+        )
+        .bind(limit.map(i64::from))
+        .bind(i64::from(offset))
+        .try_map(|row: PgRow| {
+            Ok(Profile {
+                id: AccountId(row.get("id")),
+                email: row.get("email"),
+                role: account_role(&row)?,
+                email_verified: row.get("email_verified"),
+            })
+        })
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(accounts) => Ok(accounts),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Change the role of the account and close its sessions, so a
+    /// demoted account loses its powers straight away rather than at the
+    /// next refresh
+    pub async fn update_role(&self, account_id: &AccountId, role: Role) -> Result<Profile, Error> {
+        let result: Result<Option<Profile>, sqlx::Error> = async {
+            let mut tx = self.connection.begin().await?;
+
+            let account = sqlx::query(
+                "UPDATE accounts SET role = $1 WHERE id = $2
+            RETURNING id, email, role, email_verified",
+            )
+            .bind(role.to_string())
+            .bind(account_id.0)
+            .try_map(|row: PgRow| {
+                Ok(Profile {
+                    id: AccountId(row.get("id")),
+                    email: row.get("email"),
+                    role: account_role(&row)?,
+                    email_verified: row.get("email_verified"),
+                })
+            })
+            .fetch_optional(&mut tx)
+            .await?;
+
+            if account.is_some() {
+                sqlx::query("UPDATE refresh_tokens SET revoked = true WHERE account_id = $1")
+                    .bind(account_id.0)
+                    .execute(&mut tx)
+                    .await?;
+            }
+
+            tx.commit().await?;
+            Ok(account)
+        }
+        .await;
+
+        match result {
+            Ok(Some(account)) => Ok(account),
+            Ok(None) => Err(Error::NotFound),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Make the account with the email an admin, once the email is
+    /// verified. `None` when there is no such account.
+    pub async fn make_admin(&self, email: &str) -> Result<Option<Profile>, Error> {
+        match sqlx::query(
+            "UPDATE accounts SET role = 'admin' WHERE lower(email) = lower($1) AND email_verified
+        RETURNING id, email, role, email_verified",
+        )
+        .bind(email)
+        .try_map(|row: PgRow| {
+            Ok(Profile {
+                id: AccountId(row.get("id")),
+                email: row.get("email"),
+                role: account_role(&row)?,
+                email_verified: row.get("email_verified"),
+            })
+        })
+        .fetch_optional(&self.connection)
+        .await
         {
             Ok(account) => Ok(account),
             Err(e) => {
-                tracing::event!(
-                    tracing::Level::ERROR,
-                    code = e
-                        .as_database_error()
-                        .unwrap()
-                        .code()
-                        .unwrap()
-                        .parse::<i32>()
-                        .unwrap(),
-                    db_message = e.as_database_error().unwrap().message(),
-                    constraint = e.as_database_error().unwrap().constraint().unwrap()
-                );
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Add a token for the purpose. The unused ones the account had for it
+    /// stop working, only the latest email counts.
+    pub async fn add_account_token(
+        &self,
+        token_hash: &str,
+        account_id: &AccountId,
+        purpose: TokenPurpose,
+        lifetime: chrono::Duration,
+    ) -> Result<bool, Error> {
+        let result: Result<(), sqlx::Error> = async {
+            let mut tx = self.connection.begin().await?;
+            sqlx::query(
+                "DELETE FROM account_tokens
+            WHERE account_id = $1 AND purpose = $2 AND used_on IS NULL",
+            )
+            .bind(account_id.0)
+            .bind(purpose.to_string())
+            .execute(&mut tx)
+            .await?;
+            sqlx::query(
+                "INSERT INTO account_tokens (token_hash, account_id, purpose, expires_on)
+            VALUES ($1, $2, $3, NOW() + make_interval(secs => $4))",
+            )
+            .bind(token_hash)
+            .bind(account_id.0)
+            .bind(purpose.to_string())
+            .bind(lifetime.num_seconds() as f64)
+            .execute(&mut tx)
+            .await?;
+            tx.commit().await
+        }
+        .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Mark the token as used, returning its account. `None` when there is
+    /// no such token for the purpose, or it expired or got used already.
+    pub async fn use_account_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+    ) -> Result<Option<AccountId>, Error> {
+        match sqlx::query(
+            "UPDATE account_tokens SET used_on = NOW()
+        WHERE token_hash = $1 AND purpose = $2 AND used_on IS NULL AND expires_on > NOW()
+        RETURNING account_id",
+        )
+        .bind(token_hash)
+        .bind(purpose.to_string())
+        .map(|row: PgRow| AccountId(row.get("account_id")))
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(account_id) => Ok(account_id),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Use the reset token to set the password of its account, verify its
+    /// email address and close its sessions, all or nothing. `None` when the
+    /// token is not valid.
+    pub async fn reset_password(
+        &self,
+        token_hash: &str,
+        password: String,
+    ) -> Result<Option<AccountId>, Error> {
+        let result: Result<Option<AccountId>, sqlx::Error> = async {
+            let mut tx = self.connection.begin().await?;
+            let account_id = sqlx::query(
+                "UPDATE account_tokens SET used_on = NOW()
+            WHERE token_hash = $1 AND purpose = $2 AND used_on IS NULL AND expires_on > NOW()
+            RETURNING account_id",
+            )
+            .bind(token_hash)
+            .bind(TokenPurpose::ResetPassword.to_string())
+            .map(|row: PgRow| AccountId(row.get("account_id")))
+            .fetch_optional(&mut tx)
+            .await?;
+            let account_id = match account_id {
+                Some(account_id) => account_id,
+                None => return Ok(None),
+            };
+
+            sqlx::query("UPDATE accounts SET password = $1, email_verified = true WHERE id = $2")
+                .bind(password)
+                .bind(account_id.0)
+                .execute(&mut tx)
+                .await?;
+            sqlx::query("UPDATE refresh_tokens SET revoked = true WHERE account_id = $1")
+                .bind(account_id.0)
+                .execute(&mut tx)
+                .await?;
+            tx.commit().await?;
+            Ok(Some(account_id))
+        }
+        .await;
+
+        match result {
+            Ok(account_id) => Ok(account_id),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn verify_email(&self, account_id: &AccountId) -> Result<bool, Error> {
+        match sqlx::query("UPDATE accounts SET email_verified = true WHERE id = $1")
+            .bind(account_id.0)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Seconds left before the email can log in again, `None` when it is
+    /// not locked
+    pub async fn lockout_remaining(&self, email: &str) -> Result<Option<u64>, Error> {
+        match sqlx::query(
+            "SELECT CEIL(EXTRACT(EPOCH FROM locked_until - NOW()))::bigint AS remaining
+        FROM login_failures WHERE email = $1 AND locked_until > NOW()",
+        )
+        .bind(email)
+        .map(|row: PgRow| row.get::<i64, _>("remaining") as u64)
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(remaining) => Ok(remaining),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Count a failed login for the email, locking it for `lockout` once it
+    /// reaches `max_failures` in a row
+    pub async fn add_failed_login(
+        &self,
+        email: &str,
+        max_failures: i32,
+        lockout: chrono::Duration,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "INSERT INTO login_failures AS f (email, failed_logins, updated_on)
+        VALUES ($1, 1, NOW())
+        ON CONFLICT (email) DO UPDATE SET
+            failed_logins = CASE WHEN f.failed_logins + 1 >= $2 THEN 0 ELSE f.failed_logins + 1 END,
+            locked_until = CASE WHEN f.failed_logins + 1 >= $2
+                THEN NOW() + make_interval(secs => $3) ELSE f.locked_until END,
+            updated_on = NOW()",
+        )
+        .bind(email)
+        .bind(max_failures)
+        .bind(lockout.num_seconds() as f64)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    pub async fn reset_failed_logins(&self, email: &str) -> Result<bool, Error> {
+        match sqlx::query("DELETE FROM login_failures WHERE email = $1")
+            .bind(email)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
+                Err(Error::DatabaseQueryError(e))
+            }
+        }
+    }
+
+    /// Forget the failed logins of the emails which are not locked and had
+    /// none for `forget_after`
+    pub async fn prune_login_failures(
+        &self,
+        forget_after: chrono::Duration,
+    ) -> Result<bool, Error> {
+        match sqlx::query(
+            "DELETE FROM login_failures
+        WHERE updated_on < NOW() - make_interval(secs => $1)
+        AND (locked_until IS NULL OR locked_until <= NOW())",
+        )
+        .bind(forget_after.num_seconds() as f64)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", e);
                 Err(Error::DatabaseQueryError(e))
             }
         }
@@ -1,19 +1,219 @@
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use utoipa::ToSchema;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Session {
     pub exp: DateTime<Utc>,
     pub account_id: AccountId,
+    /// Login the token stems from, shared by every token refreshed from it
+    pub session_id: String,
+    #[serde(default)]
+    pub role: Role,
     pub nbf: DateTime<Utc>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Tokens handed out at login and on refresh
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Seconds until the access token expires
+    pub expires_in: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// A stored refresh token, claimed for a refresh
+#[derive(Debug, Clone)]
+pub struct RefreshToken {
+    pub session_id: String,
+    pub account_id: AccountId,
+    pub role: Role,
+    /// Whether the token had already been exchanged before
+    pub used: bool,
+    pub revoked: bool,
+    pub expired: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Account {
     pub id: Option<AccountId>,
     pub email: String,
     pub password: String,
+    /// Only ever set by admins, never taken from a registration
+    #[serde(default, skip_deserializing)]
+    pub role: Role,
+    #[serde(default, skip_deserializing)]
+    pub email_verified: bool,
+}
+
+/// What an account may do besides managing its own content
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    /// Edits and deletes any question or answer
+    Moderator,
+    /// Moderates and manages accounts
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Role::User => write!(f, "user"),
+            Role::Moderator => write!(f, "moderator"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = handle_errors::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(handle_errors::Error::InvalidParameter(format!(
+                "role={}",
+                s
+            ))),
+        }
+    }
+}
+
+/// An account as shown to clients, without its password
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Profile {
+    pub id: AccountId,
+    pub email: String,
+    pub role: Role,
+    pub email_verified: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct RoleUpdate {
+    pub role: Role,
+}
+
+/// What a token emailed to an account proves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl fmt::Display for TokenPurpose {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenPurpose::VerifyEmail => write!(f, "verify_email"),
+            TokenPurpose::ResetPassword => write!(f, "reset_password"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct EmailRequest {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct VerificationRequest {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct PasswordReset {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct PasswordChange {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+/// Trim and lowercase the email address after checking it looks like one:
+/// a local part, an `@` and a domain made of dot separated labels.
+pub fn normalise_email(email: &str) -> Result<String, handle_errors::Error> {
+    let email = email.trim().to_lowercase();
+    let invalid = || handle_errors::Error::InvalidEmail(email.clone());
+
+    if email.len() > 255 {
+        return Err(invalid());
+    }
+    let (local, domain) = email.split_once('@').ok_or_else(invalid)?;
+    if local.is_empty()
+        || local
+            .chars()
+            .any(|c| c == '@' || c.is_whitespace() || c.is_control())
+    {
+        return Err(invalid());
+    }
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    let valid_label = |label: &&str| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+    };
+    if labels.len() < 2 || !labels.iter().all(valid_label) {
+        return Err(invalid());
+    }
+
+    Ok(email)
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash, ToSchema)]
 pub struct AccountId(pub i32);
+
+#[cfg(test)]
+mod account_tests {
+    use super::{normalise_email, Role};
+
+    #[test]
+    fn valid_email() {
+        assert_eq!(
+            normalise_email(" Jane.Doe+qa@Example.co.uk ").unwrap(),
+            "jane.doe+qa@example.co.uk"
+        );
+    }
+
+    #[test]
+    fn invalid_email() {
+        for email in [
+            "",
+            "jane",
+            "@example.com",
+            "jane@",
+            "jane@localhost",
+            "jane@@example.com",
+            "ja ne@example.com",
+            "jane@exa_mple.com",
+            "jane@-example.com",
+            "jane@example..com",
+        ] {
+            assert!(normalise_email(email).is_err(), "{} passed", email);
+        }
+    }
+
+    #[test]
+    fn roles() {
+        assert_eq!("moderator".parse::<Role>().unwrap(), Role::Moderator);
+        assert!("root".parse::<Role>().is_err());
+        assert!(Role::Admin > Role::Moderator && Role::Moderator > Role::User);
+    }
+}
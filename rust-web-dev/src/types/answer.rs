@@ -1,19 +1,30 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use crate::types::question::QuestionId;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Answer {
     pub id: AnswerId,
     pub content: String,
     pub question_id: QuestionId,
+    /// Upvotes minus downvotes, only ever changed by voting
+    #[serde(default)]
+    #[schema(read_only)]
+    pub score: i32,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash, ToSchema)]
 pub struct AnswerId(pub i32);
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
 pub struct NewAnswer {
     pub content: String,
     pub question_id: QuestionId,
 }
+
+/// What the author of an answer can change, the rest belongs to the store
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct UpdateAnswer {
+    pub content: String,
+}
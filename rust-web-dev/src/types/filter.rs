@@ -0,0 +1,172 @@
+use std::fmt;
+use std::str::FromStr;
+
+use handle_errors::Error;
+
+/// Order in which questions are listed
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Sort {
+    /// Order of insertion
+    #[default]
+    Id,
+    /// Oldest first, `sort=created_on`
+    CreatedOn,
+    /// Newest first, `sort=-created_on`
+    CreatedOnDesc,
+    /// Lowest score first, `sort=score`
+    Score,
+    /// Highest score first, `sort=-score`
+    ScoreDesc,
+}
+
+impl Sort {
+    /// `ORDER BY` clause of the sort, ties broken by id
+    pub fn order_by(&self) -> &'static str {
+        match self {
+            Sort::Id => "id",
+            Sort::CreatedOn => "created_on, id",
+            Sort::CreatedOnDesc => "created_on DESC, id DESC",
+            Sort::Score => "score, created_on, id",
+            Sort::ScoreDesc => "score DESC, created_on DESC, id DESC",
+        }
+    }
+
+    /// Whether the cursor of the listing needs the score
+    pub fn by_score(&self) -> bool {
+        matches!(self, Sort::Score | Sort::ScoreDesc)
+    }
+}
+
+/// The `sort` query parameter
+impl fmt::Display for Sort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Sort::Id => "id",
+            Sort::CreatedOn => "created_on",
+            Sort::CreatedOnDesc => "-created_on",
+            Sort::Score => "score",
+            Sort::ScoreDesc => "-score",
+        })
+    }
+}
+
+impl FromStr for Sort {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(Sort::Id),
+            "created_on" => Ok(Sort::CreatedOn),
+            "-created_on" => Ok(Sort::CreatedOnDesc),
+            "score" => Ok(Sort::Score),
+            "-score" => Ok(Sort::ScoreDesc),
+            _ => Err(Error::InvalidParameter(format!("sort={}", s))),
+        }
+    }
+}
+
+/// Which questions to list and in which order, extracted from query params
+#[derive(Default, Debug, PartialEq)]
+pub struct QuestionFilter {
+    /// Full-text search over title and content
+    pub query: Option<String>,
+    /// Tags a question must all have
+    pub tags: Vec<String>,
+    pub sort: Sort,
+}
+
+/// Extract the filter from the query parameters of the `/questions` route
+///
+/// # Example query
+///
+/// `/questions?q=async+runtime&tag=rust&tag=warp&sort=-score`
+///
+/// Parameters which are not part of the filter, like the pagination ones,
+/// are ignored.
+pub fn extract_question_filter(params: &[(String, String)]) -> Result<QuestionFilter, Error> {
+    let mut filter = QuestionFilter::default();
+
+    for (key, value) in params {
+        match key.as_str() {
+            "q" if !value.trim().is_empty() => filter.query = Some(value.trim().to_string()),
+            "tag" if !value.is_empty() => filter.tags.push(value.to_string()),
+            "sort" => filter.sort = value.parse()?,
+            _ => {}
+        }
+    }
+
+    Ok(filter)
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use super::{extract_question_filter, Error, QuestionFilter, Sort};
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn no_filter() {
+        let filter = extract_question_filter(&params(&[("limit", "1"), ("offset", "1")]));
+        assert_eq!(filter.unwrap(), QuestionFilter::default());
+    }
+
+    #[test]
+    fn query_and_tags() {
+        let filter = extract_question_filter(&params(&[
+            ("q", " async runtime "),
+            ("tag", "rust"),
+            ("tag", "warp"),
+            ("sort", "-created_on"),
+        ]));
+        let expected = QuestionFilter {
+            query: Some("async runtime".to_string()),
+            tags: vec!["rust".to_string(), "warp".to_string()],
+            sort: Sort::CreatedOnDesc,
+        };
+        assert_eq!(filter.unwrap(), expected);
+    }
+
+    #[test]
+    fn sort_by_score() {
+        let filter = extract_question_filter(&params(&[("sort", "-score")])).unwrap();
+        assert_eq!(filter.sort, Sort::ScoreDesc);
+        assert!(filter.sort.by_score());
+
+        let filter = extract_question_filter(&params(&[("sort", "score")])).unwrap();
+        assert_eq!(filter.sort, Sort::Score);
+    }
+
+    #[test]
+    fn sort_round_trip() {
+        for sort in [
+            Sort::Id,
+            Sort::CreatedOn,
+            Sort::CreatedOnDesc,
+            Sort::Score,
+            Sort::ScoreDesc,
+        ] {
+            assert_eq!(sort.to_string().parse::<Sort>().unwrap(), sort);
+        }
+    }
+
+    #[test]
+    fn empty_query() {
+        let filter = extract_question_filter(&params(&[("q", "  ")]));
+        assert_eq!(filter.unwrap().query, None);
+    }
+
+    #[test]
+    fn unknown_sort() {
+        let filter = format!(
+            "{}",
+            extract_question_filter(&params(&[("sort", "title")])).unwrap_err()
+        );
+        let expected = format!("{}", Error::InvalidParameter("sort=title".to_string()));
+        assert_eq!(filter, expected);
+    }
+}
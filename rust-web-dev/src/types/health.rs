@@ -0,0 +1,30 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Answer of `/health`, given as long as the server runs
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct Health {
+    pub status: String,
+    /// Build id of the server, like `0.1.0-1a2b3c4-x86_64-linux-gnu`
+    pub version: String,
+}
+
+/// Answer of `/ready`, telling whether the server can serve requests
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct Readiness {
+    /// `ready` or `not_ready`
+    pub status: String,
+    pub version: String,
+    /// `ok` or `unavailable`
+    pub database: String,
+    /// Unknown while the database is unavailable
+    pub migrations: Option<MigrationStatus>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct MigrationStatus {
+    /// Version of the last migration applied to the database
+    pub current: Option<i64>,
+    /// Versions of the migrations the server ships which the database lacks
+    pub pending: Vec<i64>,
+}
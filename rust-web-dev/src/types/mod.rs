@@ -1,4 +1,7 @@
 pub mod account;
 pub mod answer;
+pub mod filter;
+pub mod health;
 pub mod pagination;
 pub mod question;
+pub mod vote;
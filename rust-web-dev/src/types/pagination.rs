@@ -1,15 +1,141 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use utoipa::ToSchema;
 
 use handle_errors::Error;
 
+use crate::types::{answer::Answer, filter::Sort, question::Question};
+
+/// Number of items returned when the query does not set a limit
+pub const DEFAULT_LIMIT: u32 = 20;
+/// Largest limit a query can ask for, larger ones are capped
+pub const MAX_LIMIT: u32 = 100;
+
+const CURSOR_TIME_FORMAT: &str = "%Y%m%dT%H%M%S%.6f";
+
 /// Pagination struct which is getting extract from query params
-// Note `Default` trait usage here to provide default values
-#[derive(Default, Debug, PartialEq)]
+#[derive(Debug, PartialEq)]
 pub struct Pagination {
-    /// Optional max number of items to return
+    /// Max number of items to return, `None` returns all of them
     pub limit: Option<u32>,
     /// The index of the first item which has to be returned
     pub offset: u32,
+    /// Return the items after this one instead of skipping `offset` items
+    pub cursor: Option<Cursor>,
+}
+
+impl Pagination {
+    /// Cursors only make sense in the order of the listing they come from
+    pub fn check_sort(&self, sort: Sort) -> Result<(), Error> {
+        match &self.cursor {
+            Some(cursor) if cursor.sort != sort => Err(Error::InvalidParameter(format!(
+                "cursor={} does not match sort={}",
+                cursor, sort
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Note `Default` trait usage here to provide default values
+impl Default for Pagination {
+    fn default() -> Self {
+        Pagination {
+            limit: Some(DEFAULT_LIMIT),
+            offset: 0,
+            cursor: None,
+        }
+    }
+}
+
+/// Position of an item in a listing, the last item of a page. The next page
+/// starts right after it, whatever got inserted or deleted in between.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    /// Order of the listing the cursor comes from
+    pub sort: Sort,
+    /// Score of the item, when the listing is sorted by it
+    pub score: Option<i32>,
+    pub created_on: NaiveDateTime,
+    pub id: i32,
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.sort)?;
+        if let Some(score) = self.score {
+            write!(f, "{}_", score)?;
+        }
+        write!(
+            f,
+            "{}_{}",
+            self.created_on.format(CURSOR_TIME_FORMAT),
+            self.id
+        )
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidParameter(format!("cursor={}", s));
+        let (sort, rest) = s.split_once(':').ok_or_else(invalid)?;
+        let sort = sort.parse::<Sort>().map_err(|_| invalid())?;
+        let (rest, id) = rest.rsplit_once('_').ok_or_else(invalid)?;
+        let (score, created_on) = if sort.by_score() {
+            let (score, created_on) = rest.split_once('_').ok_or_else(invalid)?;
+            (
+                Some(score.parse::<i32>().map_err(|_| invalid())?),
+                created_on,
+            )
+        } else {
+            (None, rest)
+        };
+
+        Ok(Cursor {
+            sort,
+            score,
+            created_on: NaiveDateTime::parse_from_str(created_on, CURSOR_TIME_FORMAT)
+                .map_err(|_| invalid())?,
+            id: id.parse::<i32>().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// One page of a listing
+#[derive(Serialize, Debug, Clone, ToSchema)]
+#[aliases(QuestionPage = Page<Question>, AnswerPage = Page<Answer>)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Cursor of the next page, `None` on the last one
+    pub next_cursor: Option<String>,
+    /// Number of items in the whole listing
+    pub total: i64,
+}
+
+impl<T> Page<T> {
+    /// Build the page out of the rows fetched for it. Listings fetch one row
+    /// more than the limit to learn whether there is a next page.
+    pub fn new(mut rows: Vec<(T, Cursor)>, limit: Option<u32>, total: i64) -> Self {
+        let mut next_cursor = None;
+        if let Some(limit) = limit {
+            if rows.len() > limit as usize {
+                rows.truncate(limit as usize);
+                next_cursor = rows.last().map(|(_, cursor)| cursor.to_string());
+            }
+        }
+
+        Page {
+            items: rows.into_iter().map(|(item, _)| item).collect(),
+            next_cursor,
+            total,
+        }
+    }
 }
 
 /// Extract query parameters from the `/questions` route
@@ -21,9 +147,16 @@ pub struct Pagination {
 ///
 /// `/questions?offset=1&limit=10`
 ///
+/// Every parameter is optional: the limit defaults to `DEFAULT_LIMIT` and
+/// is capped at `MAX_LIMIT`, the offset defaults to 0. The `next_cursor` of
+/// a page can be given as `cursor` to get the next one, in place of an
+/// offset. Cursors carry the sort of their listing, see
+/// `Pagination::check_sort`.
+///
 /// # Example usage
 ///
 /// ```rust
+/// use rust_web_dev::types::pagination;
 /// use std::collections::HashMap;
 ///
 /// let mut query = HashMap::new();
@@ -34,33 +167,50 @@ pub struct Pagination {
 /// assert_eq!(p.offset, 10);
 /// ```
 pub fn extract_pagination(params: HashMap<String, String>) -> Result<Pagination, Error> {
-    // Needs to check for the existence of just one of the parameters
-    // and return an empty value in case nothing is given for "end" and 1 for "start"
-    if params.contains_key("offset") && params.contains_key("limit") {
-        return Ok(Pagination {
-            // Takes the "limit" parameter in the query and tries to convert it to a number
-            limit: Some(
-                params
-                    .get("limit")
-                    .unwrap()
-                    .parse::<u32>()
-                    .map_err(Error::ParseError)?,
-            ),
-            // Takes the "offset" parameter in the query and tries to convert it to a number
-            offset: params
-                .get("offset")
-                .unwrap()
-                .parse::<u32>()
-                .map_err(Error::ParseError)?,
-        });
-    }
-
-    Err(Error::MissingParameters)
+    let mut pagination = Pagination::default();
+
+    // Takes the "limit" parameter in the query and tries to convert it to a number
+    if let Some(limit) = params.get("limit") {
+        let limit = limit.parse::<u32>().map_err(Error::ParseError)?;
+        pagination.limit = Some(limit.clamp(1, MAX_LIMIT));
+    }
+
+    // Takes the "offset" parameter in the query and tries to convert it to a number
+    if let Some(offset) = params.get("offset") {
+        pagination.offset = offset.parse::<u32>().map_err(Error::ParseError)?;
+    }
+
+    if let Some(cursor) = params.get("cursor") {
+        if params.contains_key("offset") {
+            return Err(Error::InvalidParameter(
+                "offset and cursor are exclusive".to_string(),
+            ));
+        }
+        pagination.cursor = Some(cursor.parse::<Cursor>()?);
+    }
+
+    Ok(pagination)
 }
 
 #[cfg(test)]
 mod pagination_tests {
-    use super::{extract_pagination, Error, HashMap, Pagination};
+    use super::{
+        extract_pagination, Cursor, Error, HashMap, NaiveDateTime, Page, Pagination, Sort,
+        DEFAULT_LIMIT, MAX_LIMIT,
+    };
+
+    fn cursor(id: i32) -> Cursor {
+        Cursor {
+            sort: Sort::Id,
+            score: None,
+            created_on: NaiveDateTime::parse_from_str(
+                "2023-01-12 15:10:45.123456",
+                "%Y-%m-%d %H:%M:%S%.6f",
+            )
+            .unwrap(),
+            id,
+        }
+    }
 
     #[test]
     fn valid_pagination() {
@@ -71,6 +221,7 @@ mod pagination_tests {
         let expected = Pagination {
             limit: Some(1),
             offset: 1,
+            cursor: None,
         };
         assert_eq!(pagination_result.unwrap(), expected);
     }
@@ -80,10 +231,10 @@ mod pagination_tests {
         let mut params = HashMap::new();
         params.insert(String::from("limit"), String::from("1"));
 
-        let pagination_result = format!("{}", extract_pagination(params).unwrap_err());
-        let expected = format!("{}", Error::MissingParameters);
+        let pagination_result = extract_pagination(params).unwrap();
 
-        assert_eq!(pagination_result, expected);
+        assert_eq!(pagination_result.limit, Some(1));
+        assert_eq!(pagination_result.offset, 0);
     }
 
     #[test]
@@ -91,10 +242,20 @@ mod pagination_tests {
         let mut params = HashMap::new();
         params.insert(String::from("offset"), String::from("1"));
 
-        let pagination_result = format!("{}", extract_pagination(params).unwrap_err());
-        let expected = format!("{}", Error::MissingParameters);
+        let pagination_result = extract_pagination(params).unwrap();
 
-        assert_eq!(pagination_result, expected);
+        assert_eq!(pagination_result.limit, Some(DEFAULT_LIMIT));
+        assert_eq!(pagination_result.offset, 1);
+    }
+
+    #[test]
+    fn limit_above_maximum() {
+        let mut params = HashMap::new();
+        params.insert(String::from("limit"), String::from("100000"));
+
+        let pagination_result = extract_pagination(params).unwrap();
+
+        assert_eq!(pagination_result.limit, Some(MAX_LIMIT));
     }
 
     #[test]
@@ -120,4 +281,86 @@ mod pagination_tests {
 
         assert_eq!(pagination_result, expected);
     }
+
+    #[test]
+    fn valid_cursor() {
+        let mut params = HashMap::new();
+        params.insert(String::from("cursor"), cursor(7).to_string());
+
+        let pagination_result = extract_pagination(params).unwrap();
+
+        assert_eq!(pagination_result.cursor, Some(cursor(7)));
+    }
+
+    #[test]
+    fn cursor_with_score() {
+        let cursor = Cursor {
+            sort: Sort::ScoreDesc,
+            score: Some(-3),
+            ..cursor(7)
+        };
+        let mut params = HashMap::new();
+        params.insert(String::from("cursor"), cursor.to_string());
+
+        let pagination_result = extract_pagination(params).unwrap();
+
+        assert_eq!(pagination_result.cursor, Some(cursor));
+    }
+
+    #[test]
+    fn wrong_cursor() {
+        let mut params = HashMap::new();
+        params.insert(String::from("cursor"), String::from("NOT_A_CURSOR"));
+        let pagination_result = format!("{}", extract_pagination(params).unwrap_err());
+
+        let expected = format!(
+            "{}",
+            Error::InvalidParameter("cursor=NOT_A_CURSOR".to_string())
+        );
+
+        assert_eq!(pagination_result, expected);
+    }
+
+    #[test]
+    fn cursor_without_its_score() {
+        let cursor = Cursor {
+            sort: Sort::Score,
+            ..cursor(7)
+        };
+        assert!(cursor.to_string().parse::<Cursor>().is_err());
+        assert!("20230112T151045.123456_7".parse::<Cursor>().is_err());
+    }
+
+    #[test]
+    fn cursor_of_another_sort() {
+        let mut params = HashMap::new();
+        params.insert(String::from("cursor"), cursor(7).to_string());
+        let pagination = extract_pagination(params).unwrap();
+
+        assert!(pagination.check_sort(Sort::Id).is_ok());
+        assert!(pagination.check_sort(Sort::CreatedOnDesc).is_err());
+        assert!(Pagination::default().check_sort(Sort::Score).is_ok());
+    }
+
+    #[test]
+    fn cursor_and_offset() {
+        let mut params = HashMap::new();
+        params.insert(String::from("cursor"), cursor(7).to_string());
+        params.insert(String::from("offset"), String::from("1"));
+
+        assert!(extract_pagination(params).is_err());
+    }
+
+    #[test]
+    fn next_page() {
+        let rows = vec![(1, cursor(1)), (2, cursor(2)), (3, cursor(3))];
+
+        let page = Page::new(rows.clone(), Some(2), 3);
+        assert_eq!(page.items, vec![1, 2]);
+        assert_eq!(page.next_cursor, Some(cursor(2).to_string()));
+
+        let page = Page::new(rows, Some(3), 3);
+        assert_eq!(page.items, vec![1, 2, 3]);
+        assert_eq!(page.next_cursor, None);
+    }
 }
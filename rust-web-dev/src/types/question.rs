@@ -1,19 +1,42 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+use crate::types::answer::{Answer, AnswerId};
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
 pub struct Question {
     pub id: QuestionId,
     pub title: String,
     pub content: String,
     pub tags: Option<Vec<String>>,
+    /// Upvotes minus downvotes, only ever changed by voting
+    #[serde(default)]
+    #[schema(read_only)]
+    pub score: i32,
+    /// Answer the author of the question accepted
+    #[serde(default)]
+    #[schema(read_only)]
+    pub accepted_answer_id: Option<AnswerId>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash, ToSchema)]
 pub struct QuestionId(pub i32);
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
 pub struct NewQuestion {
     pub title: String,
     pub content: String,
     pub tags: Option<Vec<String>>,
 }
+
+/// A question together with the first page of the answers given to it
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct QuestionWithAnswers {
+    #[serde(flatten)]
+    pub question: Question,
+    pub answers: Vec<Answer>,
+    /// Cursor of the next page of answers, from `/questions/{id}/answers`
+    pub answers_next_cursor: Option<String>,
+    /// Number of answers to the question
+    pub answers_total: i64,
+}
@@ -0,0 +1,90 @@
+use serde::Serialize;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+use crate::types::{answer::AnswerId, question::QuestionId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Vote {
+    Up,
+    Down,
+}
+
+impl Vote {
+    /// What the vote adds to the score
+    pub fn value(&self) -> i16 {
+        match self {
+            Vote::Up => 1,
+            Vote::Down => -1,
+        }
+    }
+}
+
+/// Vote from the last segment of the vote routes, like
+/// `/questions/1/upvote`
+impl FromStr for Vote {
+    type Err = handle_errors::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "upvote" => Ok(Vote::Up),
+            "downvote" => Ok(Vote::Down),
+            _ => Err(handle_errors::Error::InvalidParameter(format!(
+                "vote={}",
+                s
+            ))),
+        }
+    }
+}
+
+/// What is voted on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoteTarget {
+    Question(QuestionId),
+    Answer(AnswerId),
+}
+
+impl VoteTarget {
+    /// Table holding the target, which has the `score` column
+    pub fn table(&self) -> &'static str {
+        match self {
+            VoteTarget::Question(_) => "questions",
+            VoteTarget::Answer(_) => "answers",
+        }
+    }
+
+    /// Column of the `votes` table referencing the target
+    pub fn column(&self) -> &'static str {
+        match self {
+            VoteTarget::Question(_) => "question_id",
+            VoteTarget::Answer(_) => "answer_id",
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        match self {
+            VoteTarget::Question(id) => id.0,
+            VoteTarget::Answer(id) => id.0,
+        }
+    }
+}
+
+/// Score of a question or answer after a vote
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Score {
+    pub score: i32,
+    /// Vote of the account, 1 for up, -1 for down and 0 for none
+    pub vote: i16,
+}
+
+#[cfg(test)]
+mod vote_tests {
+    use super::Vote;
+
+    #[test]
+    fn parse_vote() {
+        assert_eq!("upvote".parse::<Vote>().unwrap(), Vote::Up);
+        assert_eq!("downvote".parse::<Vote>().unwrap().value(), -1);
+        assert!("sidevote".parse::<Vote>().is_err());
+    }
+}